@@ -0,0 +1,362 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Error};
+use clap::ValueEnum;
+
+use crate::bootstrap::{ensure_self_venv, get_offline_index_args};
+use crate::cache::use_pip_cache;
+use crate::config::get_use_uv;
+use crate::lock::LockOptions;
+use crate::pyproject::get_source_options;
+use crate::utils::{CommandOutput, VENV_BIN};
+
+/// Selects the tool that resolves and installs packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// pip-tools from rye's internals.
+    Pip,
+    /// uv if it is installed.
+    Uv,
+}
+
+/// The tool that resolves and installs packages.
+///
+/// The backends produce the same lockfiles and virtualenvs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// pip-tools in the given self venv.
+    Pip(PathBuf),
+    /// The given uv executable.
+    Uv(PathBuf),
+}
+
+impl Backend {
+    /// Picks the backend to use.
+    ///
+    /// Unless a backend was requested explicitly, uv is used if it is enabled
+    /// with `behavior.use-uv` in the config and installed.
+    pub fn get(kind: Option<BackendKind>, output: CommandOutput) -> Result<Backend, Error> {
+        let use_uv = match kind {
+            Some(kind) => kind == BackendKind::Uv,
+            None => get_use_uv()?,
+        };
+        if use_uv {
+            match which::which("uv") {
+                Ok(uv) => return Ok(Backend::Uv(uv)),
+                Err(_) if kind.is_some() => bail!("the uv backend requires uv to be installed"),
+                Err(_) => {
+                    if output == CommandOutput::Verbose {
+                        eprintln!("uv is not installed, falling back to pip");
+                    }
+                }
+            }
+        }
+        Ok(Backend::Pip(ensure_self_venv(output)?))
+    }
+
+    /// Returns the name of the resolver for error messages.
+    pub fn resolver_name(&self) -> &'static str {
+        match self {
+            Backend::Pip(_) => "pip-compile",
+            Backend::Uv(_) => "uv pip compile",
+        }
+    }
+
+    /// Returns the name of the installer for error messages.
+    pub fn installer_name(&self) -> &'static str {
+        match self {
+            Backend::Pip(_) => "pip-sync",
+            Backend::Uv(_) => "uv pip sync",
+        }
+    }
+
+    /// Creates the command that resolves requirements into a lockfile.
+    ///
+    /// Existing pins in the output file are kept unless they are updated.
+    pub fn compile_command(
+        &self,
+        requirements: &[&Path],
+        out: &Path,
+        lock_options: &LockOptions,
+        no_deps: bool,
+        output: CommandOutput,
+    ) -> Result<Command, Error> {
+        let mut cmd = match self {
+            Backend::Pip(self_venv) => {
+                let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip-compile"));
+                cmd.arg("--resolver=backtracking")
+                    .arg("--no-annotate")
+                    .arg("--strip-extras")
+                    .arg("--allow-unsafe")
+                    .arg("--no-emit-index-url")
+                    .env("PYTHONWARNINGS", "ignore");
+                use_pip_cache(&mut cmd)?;
+                cmd
+            }
+            Backend::Uv(uv) => {
+                let mut cmd = Command::new(uv);
+                cmd.arg("pip").arg("compile").arg("--no-annotate");
+                cmd
+            }
+        };
+        // build backends might run python through the shims which must not
+        // start another sync of the project.
+        cmd.env("RYE_NO_AUTO_SYNC", "1");
+        cmd.arg("--no-header").arg("-o").arg(out).args(requirements);
+        if output == CommandOutput::Verbose {
+            cmd.arg("--verbose");
+        } else {
+            cmd.arg("-q");
+        }
+        for pkg in &lock_options.update {
+            cmd.arg("--upgrade-package").arg(pkg);
+        }
+        if lock_options.update_all {
+            cmd.arg("--upgrade");
+        }
+        if lock_options.pre {
+            cmd.arg(match self {
+                Backend::Pip(_) => "--pre",
+                Backend::Uv(_) => "--prerelease=allow",
+            });
+        }
+        if let Some(ref date) = lock_options.exclude_newer {
+            match self {
+                Backend::Pip(_) => bail!(
+                    "exclude-newer requires the uv backend as pip cannot filter \
+                     distributions by upload time (use `--backend uv` or `behavior.use-uv`)"
+                ),
+                Backend::Uv(_) => {
+                    cmd.arg("--exclude-newer").arg(date);
+                }
+            }
+        }
+        if lock_options.generate_hashes()? {
+            cmd.arg("--generate-hashes");
+        }
+        for option in get_source_options(&lock_options.sources) {
+            cmd.args(option.split(' '));
+        }
+        cmd.args(get_offline_index_args()?);
+        if no_deps {
+            cmd.arg(match self {
+                Backend::Pip(_) => "--pip-args=--no-deps",
+                Backend::Uv(_) => "--no-deps",
+            });
+        }
+        Ok(cmd)
+    }
+
+    /// Creates the command that makes a virtualenv match a lockfile.
+    ///
+    /// For pip, `pip_dir` has to be a folder with the pip module that pip-sync
+    /// picks up.
+    pub fn sync_command(
+        &self,
+        venv: &Path,
+        pip_dir: &Path,
+        lockfile: &Path,
+        require_hashes: bool,
+        output: CommandOutput,
+    ) -> Result<Command, Error> {
+        let python = venv.join(VENV_BIN).join("python");
+        let mut cmd = match self {
+            Backend::Pip(self_venv) => {
+                let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip-sync"));
+                cmd.env("PYTHONPATH", pip_dir)
+                    .arg("--python-executable")
+                    .arg(&python)
+                    // note that the double quotes are necessary to properly handle
+                    // spaces in paths
+                    .arg(format!(
+                        "--pip-args=\"--python={}{}\"",
+                        python.display(),
+                        if require_hashes {
+                            " --require-hashes"
+                        } else {
+                            ""
+                        }
+                    ));
+                match output {
+                    CommandOutput::Verbose => {
+                        cmd.arg("--verbose");
+                        if env::var("PIP_VERBOSE").is_err() {
+                            cmd.env("PIP_VERBOSE", "2");
+                        }
+                    }
+                    CommandOutput::Normal => {
+                        cmd.env("PYTHONWARNINGS", "ignore");
+                    }
+                    CommandOutput::Quiet => {
+                        cmd.arg("-q");
+                    }
+                }
+                use_pip_cache(&mut cmd)?;
+                cmd
+            }
+            Backend::Uv(uv) => {
+                let mut cmd = Command::new(uv);
+                cmd.arg("pip").arg("sync").arg("--python").arg(&python);
+                if require_hashes {
+                    cmd.arg("--require-hashes");
+                }
+                match output {
+                    CommandOutput::Verbose => cmd.arg("--verbose"),
+                    CommandOutput::Quiet => cmd.arg("-q"),
+                    CommandOutput::Normal => &mut cmd,
+                };
+                cmd
+            }
+        };
+        cmd.env("RYE_NO_AUTO_SYNC", "1");
+        cmd.args(get_offline_index_args()?).arg(lockfile);
+        Ok(cmd)
+    }
+
+    /// Creates the command that installs local projects without their
+    /// dependencies into a virtualenv.
+    ///
+    /// Projects are given as paths and are installed editable if requested.
+    pub fn install_projects_command(
+        &self,
+        venv: &Path,
+        projects: &[PathBuf],
+        editable: bool,
+        output: CommandOutput,
+    ) -> Result<Command, Error> {
+        let python = venv.join(VENV_BIN).join("python");
+        let mut cmd = match self {
+            // pip only honors --python before the subcommand
+            Backend::Pip(self_venv) => {
+                let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip"));
+                cmd.arg("--python").arg(&python).arg("install");
+                use_pip_cache(&mut cmd)?;
+                cmd
+            }
+            Backend::Uv(uv) => {
+                let mut cmd = Command::new(uv);
+                cmd.arg("pip").arg("install").arg("--python").arg(&python);
+                cmd
+            }
+        };
+        cmd.env("RYE_NO_AUTO_SYNC", "1");
+        cmd.arg("--no-deps").args(get_offline_index_args()?);
+        for project in projects {
+            if editable {
+                cmd.arg("-e");
+            }
+            cmd.arg(project);
+        }
+        match output {
+            CommandOutput::Verbose => {
+                cmd.arg("--verbose");
+            }
+            CommandOutput::Quiet => {
+                cmd.arg("-q");
+            }
+            CommandOutput::Normal => {
+                cmd.env("PYTHONWARNINGS", "ignore");
+            }
+        }
+        Ok(cmd)
+    }
+}
+
+#[cfg(test)]
+fn command_line(cmd: &Command) -> Vec<String> {
+    Some(cmd.get_program())
+        .into_iter()
+        .chain(cmd.get_args())
+        .map(|x| x.to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn test_compile_command() {
+    let lock_options = LockOptions {
+        update: vec!["requests".into()],
+        pre: true,
+        no_hashes: true,
+        ..LockOptions::default()
+    };
+    let compile = |backend: Backend| {
+        let cmd = backend
+            .compile_command(
+                &[Path::new("in.txt")],
+                Path::new("out.txt"),
+                &lock_options,
+                true,
+                CommandOutput::Normal,
+            )
+            .unwrap();
+        command_line(&cmd).join(" ")
+    };
+    assert_eq!(
+        compile(Backend::Pip(PathBuf::from("/self"))),
+        "/self/bin/pip-compile --resolver=backtracking --no-annotate --strip-extras \
+         --allow-unsafe --no-emit-index-url --no-header -o out.txt in.txt -q --upgrade-package requests \
+         --pre --pip-args=--no-deps"
+    );
+    assert_eq!(
+        compile(Backend::Uv(PathBuf::from("/bin/uv"))),
+        "/bin/uv pip compile --no-annotate --no-header -o out.txt in.txt -q \
+         --upgrade-package requests --prerelease=allow --no-deps"
+    );
+}
+
+#[test]
+fn test_compile_command_exclude_newer() {
+    let lock_options = LockOptions {
+        exclude_newer: Some("2024-01-01".into()),
+        no_hashes: true,
+        ..LockOptions::default()
+    };
+    let compile = |backend: Backend| {
+        backend.compile_command(
+            &[Path::new("in.txt")],
+            Path::new("out.txt"),
+            &lock_options,
+            false,
+            CommandOutput::Normal,
+        )
+    };
+    let err = compile(Backend::Pip(PathBuf::from("/self"))).unwrap_err();
+    assert!(err.to_string().contains("requires the uv backend"));
+    let cmd = compile(Backend::Uv(PathBuf::from("/bin/uv"))).unwrap();
+    assert_eq!(
+        command_line(&cmd).join(" "),
+        "/bin/uv pip compile --no-annotate --no-header -o out.txt in.txt -q \
+         --exclude-newer 2024-01-01"
+    );
+}
+
+#[test]
+fn test_sync_command() {
+    let sync = |backend: Backend, output| {
+        let cmd = backend
+            .sync_command(
+                Path::new("/venv"),
+                Path::new("/pip"),
+                Path::new("requirements.lock"),
+                true,
+                output,
+            )
+            .unwrap();
+        command_line(&cmd).join(" ")
+    };
+    assert_eq!(
+        sync(Backend::Pip(PathBuf::from("/self")), CommandOutput::Quiet),
+        "/self/bin/pip-sync --python-executable /venv/bin/python \
+         --pip-args=\"--python=/venv/bin/python --require-hashes\" -q requirements.lock"
+    );
+    assert_eq!(
+        sync(
+            Backend::Uv(PathBuf::from("/bin/uv")),
+            CommandOutput::Verbose
+        ),
+        "/bin/uv pip sync --python /venv/bin/python --require-hashes --verbose \
+         requirements.lock"
+    );
+}
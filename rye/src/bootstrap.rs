@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::env::consts::{ARCH, OS};
+use std::ffi::OsString;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -12,9 +13,10 @@ use indicatif::{ProgressBar, ProgressStyle};
 use once_cell::sync::Lazy;
 use tempfile::NamedTempFile;
 
-use crate::config::{get_app_dir, get_canonical_py_path, get_py_bin};
+use crate::cache::use_pip_cache;
+use crate::config::{get_app_dir, get_canonical_py_path, get_default_wheelhouse, get_py_bin};
 use crate::sources::{get_download_url, PythonVersion, PythonVersionRequest};
-use crate::utils::{unpack_tarball, CommandOutput};
+use crate::utils::{unpack_tarball, CommandOutput, VENV_BIN};
 
 pub const SELF_PYTHON_VERSION: PythonVersionRequest = PythonVersionRequest {
     kind: Some(Cow::Borrowed("cpython")),
@@ -23,7 +25,7 @@ pub const SELF_PYTHON_VERSION: PythonVersionRequest = PythonVersionRequest {
     patch: None,
     suffix: None,
 };
-const SELF_VERSION: u64 = 1;
+const SELF_VERSION: u64 = 2;
 const SELF_SITE_PACKAGES: &str = "python3.10/site-packages";
 const SELF_REQUIREMENTS: &str = r#"
 build==0.10.0
@@ -33,6 +35,10 @@ click==8.1.3
 distlib==0.3.6
 filelock==3.12.0
 idna==3.4
+importlib-metadata==6.6.0
+jaraco.classes==3.2.3
+keyring==23.13.1
+more-itertools==9.1.0
 packaging==23.1
 pip-tools==6.13.0
 platformdirs==3.4.0
@@ -42,15 +48,44 @@ tomli==2.0.1
 unearth==0.9.0
 urllib3==1.26.15
 virtualenv==20.22.0
+zipp==3.15.0
 "#;
 
 static FORCED_TO_UPDATE: AtomicBool = AtomicBool::new(false);
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Forbids network access for the rest of the process.
+pub fn set_offline() {
+    OFFLINE.store(true, atomic::Ordering::Relaxed);
+}
+
+/// Checks if rye must not access the network.
+///
+/// This is the case after [`set_offline`] or if `RYE_OFFLINE` is set.
+pub fn is_offline() -> bool {
+    OFFLINE.load(atomic::Ordering::Relaxed)
+        || env::var_os("RYE_OFFLINE").is_some_and(|x| !x.is_empty() && x != "0")
+}
+
+/// Returns the arguments that restrict pip to the local wheelhouse when
+/// running offline.
+pub fn get_offline_index_args() -> Result<Vec<OsString>, Error> {
+    let mut rv = Vec::new();
+    if is_offline() {
+        rv.push("--no-index".into());
+        if let Some(wheelhouse) = get_default_wheelhouse()? {
+            rv.push("--find-links".into());
+            rv.push(wheelhouse.into());
+        }
+    }
+    Ok(rv)
+}
 
 fn is_up_to_date() -> bool {
     static UP_TO_UPDATE: Lazy<bool> = Lazy::new(|| match get_app_dir() {
         Ok(dir) => fs::read_to_string(dir.join("self").join("tool-version.txt"))
             .ok()
-            .map_or(false, |x| x.parse() == Ok(SELF_VERSION)),
+            .is_some_and(|x| x.parse() == Ok(SELF_VERSION)),
         Err(_) => false,
     });
     *UP_TO_UPDATE || FORCED_TO_UPDATE.load(atomic::Ordering::Relaxed)
@@ -61,15 +96,17 @@ pub fn ensure_self_venv(output: CommandOutput) -> Result<PathBuf, Error> {
     let app_dir = get_app_dir().context("could not get app dir")?;
     let venv_dir = app_dir.join("self");
 
+    if venv_dir.is_dir() && is_up_to_date() {
+        return Ok(venv_dir);
+    }
+    if is_offline() {
+        bail!("rye internals need to be bootstrapped which is not possible offline");
+    }
     if venv_dir.is_dir() {
-        if is_up_to_date() {
-            return Ok(venv_dir);
-        } else {
-            if output != CommandOutput::Quiet {
-                eprintln!("detected outdated rye internals. Refreshing");
-            }
-            fs::remove_dir_all(&venv_dir).context("could not remove self-venv for update")?;
+        if output != CommandOutput::Quiet {
+            eprintln!("detected outdated rye internals. Refreshing");
         }
+        fs::remove_dir_all(&venv_dir).context("could not remove self-venv for update")?;
     }
 
     if output != CommandOutput::Quiet {
@@ -109,10 +146,11 @@ fn do_update(output: CommandOutput, venv_dir: &Path, app_dir: &Path) -> Result<(
     if output != CommandOutput::Quiet {
         eprintln!("Upgrading pip");
     }
-    let mut pip_install_cmd = Command::new(venv_dir.join("bin/pip"));
+    let mut pip_install_cmd = Command::new(venv_dir.join(VENV_BIN).join("pip"));
     pip_install_cmd.arg("install");
     pip_install_cmd.arg("--upgrade");
     pip_install_cmd.arg("pip");
+    use_pip_cache(&mut pip_install_cmd)?;
     if output == CommandOutput::Verbose {
         pip_install_cmd.arg("--verbose");
     } else {
@@ -127,11 +165,12 @@ fn do_update(output: CommandOutput, venv_dir: &Path, app_dir: &Path) -> Result<(
     }
     let mut req_file = NamedTempFile::new()?;
     writeln!(req_file, "{}", SELF_REQUIREMENTS)?;
-    let mut pip_install_cmd = Command::new(venv_dir.join("bin/pip"));
+    let mut pip_install_cmd = Command::new(venv_dir.join(VENV_BIN).join("pip"));
     pip_install_cmd
         .arg("install")
         .arg("-r")
         .arg(req_file.path());
+    use_pip_cache(&mut pip_install_cmd)?;
     if output != CommandOutput::Quiet {
         eprintln!("Installing internal dependencies");
     }
@@ -185,7 +224,26 @@ pub fn get_pip_module(venv: &Path) -> PathBuf {
     rv
 }
 
+/// Resolves the toolchain [`fetch`] would use without downloading it.
+///
+/// Returns the version and whether it still needs to be downloaded.
+pub fn resolve_fetch(version: &PythonVersionRequest) -> Result<(PythonVersion, bool), Error> {
+    if let Ok(version) = PythonVersion::try_from(version.clone()) {
+        if get_py_bin(&version)?.is_file() {
+            return Ok((version, false));
+        }
+    }
+    let version = match get_download_url(version, OS, ARCH) {
+        Some((version, _)) => version,
+        None => bail!("unknown version {}", version),
+    };
+    let downloaded = get_py_bin(&version)?.is_file();
+    Ok((version, !downloaded))
+}
+
 /// Fetches a version if missing.
+///
+/// When running offline only already downloaded versions are available.
 pub fn fetch(
     version: &PythonVersionRequest,
     output: CommandOutput,
@@ -216,6 +274,12 @@ pub fn fetch(
         }
         return Ok(version);
     }
+    if is_offline() {
+        bail!(
+            "{} is not downloaded and cannot be fetched offline",
+            version
+        );
+    }
 
     fs::create_dir_all(&target_dir)
         .with_context(|| format!("failed to create target folder {}", target_dir.display()))?;
@@ -0,0 +1,542 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Error};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::config::{get_app_dir, get_pip_cache_dir};
+
+/// Returns the folder with the unpacked wheels that are shared between tools.
+pub fn get_wheel_cache_dir() -> Result<PathBuf, Error> {
+    Ok(get_app_dir()?.join("cache").join("wheels"))
+}
+
+/// Returns the folder with the ephemeral environments of `rye run --with`.
+pub fn get_overlay_cache_dir() -> Result<PathBuf, Error> {
+    Ok(get_app_dir()?.join("cache").join("overlays"))
+}
+
+/// Returns the folder of the pip cache that is shared between projects.
+///
+/// A `PIP_CACHE_DIR` from the environment takes precedence over the config.
+pub fn find_pip_cache_dir() -> Result<PathBuf, Error> {
+    match env::var_os("PIP_CACHE_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => get_pip_cache_dir(),
+    }
+}
+
+/// Makes pip use the shared cache.
+pub fn use_pip_cache(cmd: &mut Command) -> Result<(), Error> {
+    cmd.env("PIP_CACHE_DIR", find_pip_cache_dir()?);
+    Ok(())
+}
+
+/// Removes everything from a pip cache.
+///
+/// Returns the bytes that were (or in dry-run mode would be) removed.
+pub fn clear_pip_cache(cache_dir: &Path, dry_run: bool) -> Result<u64, Error> {
+    let size = dir_size(cache_dir);
+    if !dry_run && cache_dir.is_dir() {
+        fs::remove_dir_all(cache_dir)
+            .with_context(|| format!("unable to remove {}", cache_dir.display()))?;
+    }
+    Ok(size)
+}
+
+/// Returns the number of bytes the files in a folder take up.
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|x| x.ok())
+        .filter_map(|x| x.metadata().ok())
+        .filter(|x| x.is_file())
+        .map(|x| x.len())
+        .sum()
+}
+
+/// The file in an ephemeral environment that records its last use.
+///
+/// Environments without it never finished installing.
+pub const OVERLAY_MARKER: &str = ".rye-last-used";
+
+/// How long an ephemeral environment is kept after it was last used.
+const OVERLAY_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The file in an unpacked wheel that records its last use.
+const WHEEL_MARKER: &str = ".rye-last-used";
+
+/// How long a wheel is kept after it was last used, even if no virtualenv
+/// links to it.  This covers installations that are about to link it.
+const WHEEL_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Unpacks a wheel into the wheel cache unless it's already there.
+///
+/// The key identifies the contents of the wheel, usually its sha256 hash.
+/// Wheels are unpacked into a temporary folder first so that parallel
+/// installations never see half unpacked wheels.  Returns the folder with
+/// the contents of the wheel.
+pub fn unpack_wheel(python: &Path, wheel: &Path, key: &str) -> Result<PathBuf, Error> {
+    let cache_dir = get_wheel_cache_dir()?;
+    let target = cache_dir.join(key);
+    if target.is_dir() {
+        fs::write(target.join(WHEEL_MARKER), "")?;
+        return Ok(target);
+    }
+    fs::create_dir_all(&cache_dir)?;
+    let tmp = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempdir_in(&cache_dir)?;
+    let status = Command::new(python)
+        .arg("-m")
+        .arg("zipfile")
+        .arg("-e")
+        .arg(wheel)
+        .arg(tmp.path())
+        .status()
+        .context("unable to run python")?;
+    if !status.success() {
+        bail!("unable to unpack {}", wheel.display());
+    }
+    fs::write(tmp.path().join(WHEEL_MARKER), "")?;
+    // losing the race against another installation is fine, the contents
+    // are the same.
+    if fs::rename(tmp.path(), &target).is_err() && !target.is_dir() {
+        bail!("unable to move {} into the wheel cache", wheel.display());
+    }
+    Ok(target)
+}
+
+/// The folders of a virtualenv that the files of a wheel go to.
+#[derive(Deserialize, Debug, Clone)]
+pub struct InstallScheme {
+    pub purelib: PathBuf,
+    pub platlib: PathBuf,
+    pub scripts: PathBuf,
+    pub data: PathBuf,
+}
+
+/// Checks if a wheel from the cache can be linked into a virtualenv.
+///
+/// Headers go to a location that depends on the interpreter and gui scripts
+/// need launchers on some platforms, such wheels are left to pip.
+pub fn can_link_wheel(wheel_dir: &Path) -> Result<bool, Error> {
+    for entry in fs::read_dir(wheel_dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("data")) && path.join("headers").exists() {
+            return Ok(false);
+        }
+        if path.extension() == Some(OsStr::new("dist-info")) {
+            if let Ok(entry_points) = fs::read_to_string(path.join("entry_points.txt")) {
+                if !parse_script_entry_points(&entry_points, "gui_scripts").is_empty() {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Installs an unpacked wheel from the cache into a virtualenv.
+///
+/// The files are hardlinked (or copied if that's not possible) into the
+/// folders of the install scheme.  Scripts and the metadata pip keeps about
+/// an installation are written the way pip does.  `direct_url` is the
+/// contents of `direct_url.json` for packages installed from a URL.
+pub fn link_wheel(
+    wheel_dir: &Path,
+    scheme: &InstallScheme,
+    python: &Path,
+    requested: bool,
+    direct_url: Option<&str>,
+) -> Result<(), Error> {
+    let dist_info = fs::read_dir(wheel_dir)?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .find(|x| x.extension() == Some(OsStr::new("dist-info")))
+        .ok_or_else(|| anyhow!("{} is not a wheel", wheel_dir.display()))?;
+    let wheel_info = fs::read_to_string(dist_info.join("WHEEL"))
+        .with_context(|| format!("invalid wheel in {}", wheel_dir.display()))?;
+    let root = if wheel_info.lines().any(|x| {
+        x.replace(' ', "")
+            .eq_ignore_ascii_case("root-is-purelib:true")
+    }) {
+        &scheme.purelib
+    } else {
+        &scheme.platlib
+    };
+    let dist_info_target = root.join(dist_info.file_name().unwrap());
+
+    let mut installed = Vec::new();
+    for entry in WalkDir::new(wheel_dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let rel = path.strip_prefix(wheel_dir)?;
+        let mut components = rel.components();
+        let first = components.next().unwrap().as_os_str();
+        let target = if Path::new(first).extension() == Some(OsStr::new("data")) {
+            let key = components.next().and_then(|x| x.as_os_str().to_str());
+            let rest = components.as_path();
+            match key {
+                Some("purelib") => scheme.purelib.join(rest),
+                Some("platlib") => scheme.platlib.join(rest),
+                Some("data") => scheme.data.join(rest),
+                Some("scripts") => {
+                    let target = scheme.scripts.join(rest);
+                    install_script(path, &target, python)?;
+                    installed.push(target);
+                    continue;
+                }
+                _ => bail!("unsupported file {} in wheel", rel.display()),
+            }
+        } else if rel == Path::new(WHEEL_MARKER)
+            || path.parent() == Some(&dist_info)
+                && matches!(
+                    path.file_name().and_then(|x| x.to_str()),
+                    Some("RECORD" | "INSTALLER" | "REQUESTED" | "direct_url.json")
+                )
+        {
+            continue;
+        } else {
+            root.join(rel)
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::remove_file(&target).ok();
+        if fs::hard_link(path, &target).is_err() {
+            fs::copy(path, &target)
+                .with_context(|| format!("unable to install {}", target.display()))?;
+        }
+        installed.push(target);
+    }
+
+    if let Ok(entry_points) = fs::read_to_string(dist_info.join("entry_points.txt")) {
+        for (name, module, attr) in parse_script_entry_points(&entry_points, "console_scripts") {
+            let target = scheme.scripts.join(name);
+            let body = format!(
+                "# -*- coding: utf-8 -*-\n\
+                 import re\n\
+                 import sys\n\
+                 from {} import {}\n\
+                 if __name__ == \"__main__\":\n    \
+                 sys.argv[0] = re.sub(r\"(-script\\.pyw|\\.exe)?$\", \"\", sys.argv[0])\n    \
+                 sys.exit({}())\n",
+                module,
+                attr.split('.').next().unwrap(),
+                attr
+            );
+            write_script(&target, python, &body)?;
+            installed.push(target);
+        }
+    }
+
+    fs::write(dist_info_target.join("INSTALLER"), "rye\n")?;
+    installed.push(dist_info_target.join("INSTALLER"));
+    if requested {
+        fs::write(dist_info_target.join("REQUESTED"), "")?;
+        installed.push(dist_info_target.join("REQUESTED"));
+    }
+    if let Some(direct_url) = direct_url {
+        fs::write(dist_info_target.join("direct_url.json"), direct_url)?;
+        installed.push(dist_info_target.join("direct_url.json"));
+    }
+    installed.push(dist_info_target.join("RECORD"));
+    let mut record = String::new();
+    for path in installed {
+        let path = pathdiff::diff_paths(&path, root).unwrap_or(path);
+        let path = path.to_string_lossy();
+        if path.contains([',', '"']) {
+            writeln!(record, "\"{}\",,", path.replace('"', "\"\"")).unwrap();
+        } else {
+            writeln!(record, "{},,", path).unwrap();
+        }
+    }
+    fs::write(dist_info_target.join("RECORD"), record)?;
+    Ok(())
+}
+
+/// Parses the scripts of a group (`console_scripts` or `gui_scripts`) of an
+/// `entry_points.txt` file.
+///
+/// Returns the name, module and attribute of every script.
+fn parse_script_entry_points<'a>(
+    contents: &'a str,
+    group: &str,
+) -> Vec<(&'a str, &'a str, &'a str)> {
+    let mut rv = Vec::new();
+    let mut in_scripts = false;
+    for line in contents.lines().map(|x| x.trim()) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            in_scripts = section.trim() == group;
+            continue;
+        }
+        if !in_scripts {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        // extras after the attribute are deprecated and ignored by pip too
+        let value = value.split('[').next().unwrap().trim();
+        if let Some((module, attr)) = value.split_once(':') {
+            rv.push((name.trim(), module.trim(), attr.trim()));
+        }
+    }
+    rv
+}
+
+/// Installs a script from the data of a wheel.
+///
+/// Python scripts are rewritten to use the interpreter of the virtualenv.
+fn install_script(path: &Path, target: &Path, python: &Path) -> Result<(), Error> {
+    let contents = fs::read(path)?;
+    if let Some(rest) = contents
+        .strip_prefix(b"#!pythonw")
+        .or_else(|| contents.strip_prefix(b"#!python"))
+    {
+        let body = match memchr::memchr(b'\n', rest) {
+            Some(idx) => &rest[idx + 1..],
+            None => b"",
+        };
+        return write_script(target, python, &String::from_utf8_lossy(body));
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::remove_file(target).ok();
+    fs::copy(path, target).with_context(|| format!("unable to install {}", target.display()))?;
+    set_executable(target)
+}
+
+/// Writes a python script with a shebang for the given interpreter.
+///
+/// Interpreter paths that are too long or contain spaces cannot be used in a
+/// shebang, such scripts are started through `/bin/sh`.
+fn write_script(target: &Path, python: &Path, body: &str) -> Result<(), Error> {
+    let python = python.to_string_lossy();
+    let shebang = if python.len() > 127 || python.contains(' ') {
+        format!("#!/bin/sh\n'''exec' \"{}\" \"$0\" \"$@\"\n' '''\n", python)
+    } else {
+        format!("#!{}\n", python)
+    };
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::remove_file(target).ok();
+    fs::write(target, format!("{}{}", shebang, body))
+        .with_context(|| format!("unable to write {}", target.display()))?;
+    set_executable(target)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Removes the wheels from the cache that no virtualenv links to anymore.
+///
+/// Returns the number of wheels and the bytes that were (or in dry-run mode
+/// would be) removed.
+pub fn evict_unused_wheels(dry_run: bool) -> Result<(usize, u64), Error> {
+    let cache_dir = get_wheel_cache_dir()?;
+    let mut evicted = 0;
+    let mut size = 0;
+    if !cache_dir.is_dir() {
+        return Ok((evicted, size));
+    }
+    for entry in fs::read_dir(&cache_dir)? {
+        let path = entry?.path();
+        if is_wheel_in_use(&path)? {
+            continue;
+        }
+        size += dir_size(&path);
+        if !dry_run {
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("unable to remove {}", path.display()))?;
+        }
+        evicted += 1;
+    }
+    Ok((evicted, size))
+}
+
+/// Checks if an entry of the wheel cache has to be kept.
+///
+/// Besides the wheels that virtualenvs link to, this keeps the ones that
+/// were used recently and the folders wheels are still being unpacked into.
+fn is_wheel_in_use(path: &Path) -> Result<bool, Error> {
+    let last_used = fs::metadata(path.join(WHEEL_MARKER))
+        .or_else(|_| fs::metadata(path))
+        .and_then(|x| x.modified());
+    if last_used
+        .is_ok_and(|x| SystemTime::now().duration_since(x).unwrap_or_default() < WHEEL_MIN_AGE)
+    {
+        return Ok(true);
+    }
+    for entry in WalkDir::new(path) {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() && link_count(&metadata) > 1 {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &fs::Metadata) -> u64 {
+    u64::MAX
+}
+
+/// Removes the ephemeral environments that were not used for a while.
+///
+/// Returns the number of environments and the bytes that were (or in
+/// dry-run mode would be) removed.
+pub fn evict_unused_overlays(dry_run: bool) -> Result<(usize, u64), Error> {
+    let cache_dir = get_overlay_cache_dir()?;
+    let mut evicted = 0;
+    let mut size = 0;
+    if !cache_dir.is_dir() {
+        return Ok((evicted, size));
+    }
+    for entry in fs::read_dir(&cache_dir)? {
+        let path = entry?.path();
+        let last_used = fs::metadata(path.join(OVERLAY_MARKER)).and_then(|x| x.modified());
+        if last_used.is_ok_and(|x| {
+            SystemTime::now().duration_since(x).unwrap_or_default() < OVERLAY_MAX_AGE
+        }) {
+            continue;
+        }
+        size += dir_size(&path);
+        if !dry_run {
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("unable to remove {}", path.display()))?;
+        }
+        evicted += 1;
+    }
+    Ok((evicted, size))
+}
+
+#[test]
+fn test_link_wheel() {
+    let dir = tempfile::tempdir().unwrap();
+    let wheel_dir = dir.path().join("wheel");
+    let dist_info = wheel_dir.join("demo-1.0.dist-info");
+    fs::create_dir_all(wheel_dir.join("demo")).unwrap();
+    fs::create_dir_all(wheel_dir.join("demo-1.0.data/scripts")).unwrap();
+    fs::create_dir_all(&dist_info).unwrap();
+    fs::write(wheel_dir.join("demo/__init__.py"), "def main(): pass\n").unwrap();
+    fs::write(
+        wheel_dir.join("demo-1.0.data/scripts/demo-tool"),
+        "#!python\nprint(1)\n",
+    )
+    .unwrap();
+    fs::write(dist_info.join("WHEEL"), "Root-Is-Purelib: true\n").unwrap();
+    fs::write(dist_info.join("RECORD"), "demo/__init__.py,,\n").unwrap();
+    fs::write(
+        dist_info.join("entry_points.txt"),
+        "[console_scripts]\ndemo = demo.cli:main.run [extra]\n[other]\nx = y:z\n",
+    )
+    .unwrap();
+
+    fs::write(wheel_dir.join(WHEEL_MARKER), "").unwrap();
+    assert!(can_link_wheel(&wheel_dir).unwrap());
+
+    let venv = dir.path().join("venv");
+    let scheme = InstallScheme {
+        purelib: venv.join("lib/site-packages"),
+        platlib: venv.join("lib/site-packages"),
+        scripts: venv.join("bin"),
+        data: venv.clone(),
+    };
+    let python = venv.join("bin/python");
+    link_wheel(&wheel_dir, &scheme, &python, true, None).unwrap();
+
+    assert!(!scheme.purelib.join(WHEEL_MARKER).exists());
+    let module = scheme.purelib.join("demo/__init__.py");
+    #[cfg(unix)]
+    assert!(same_file::is_same_file(&module, wheel_dir.join("demo/__init__.py")).unwrap());
+    let script = fs::read_to_string(venv.join("bin/demo")).unwrap();
+    assert!(script.starts_with(&format!("#!{}\n", python.display())));
+    assert!(script.contains("from demo.cli import main\n"));
+    assert!(script.contains("sys.exit(main.run())\n"));
+    assert_eq!(
+        fs::read_to_string(venv.join("bin/demo-tool")).unwrap(),
+        format!("#!{}\nprint(1)\n", python.display())
+    );
+    assert_eq!(
+        fs::read_to_string(scheme.purelib.join("demo-1.0.dist-info/RECORD")).unwrap(),
+        "demo/__init__.py,,\n\
+         ../../bin/demo-tool,,\n\
+         demo-1.0.dist-info/WHEEL,,\n\
+         demo-1.0.dist-info/entry_points.txt,,\n\
+         ../../bin/demo,,\n\
+         demo-1.0.dist-info/INSTALLER,,\n\
+         demo-1.0.dist-info/REQUESTED,,\n\
+         demo-1.0.dist-info/RECORD,,\n"
+    );
+}
+
+#[test]
+fn test_can_link_wheel_with_gui_scripts() {
+    let dir = tempfile::tempdir().unwrap();
+    let dist_info = dir.path().join("demo-1.0.dist-info");
+    fs::create_dir_all(&dist_info).unwrap();
+    fs::write(
+        dist_info.join("entry_points.txt"),
+        "[console_scripts]\ndemo = demo:main\n[gui_scripts]\ndemo-gui = demo:gui\n",
+    )
+    .unwrap();
+    assert!(!can_link_wheel(dir.path()).unwrap());
+}
+
+#[test]
+fn test_is_wheel_in_use() {
+    let dir = tempfile::tempdir().unwrap();
+    let wheel_dir = dir.path().join("wheel");
+    fs::create_dir_all(&wheel_dir).unwrap();
+    fs::write(wheel_dir.join("module.py"), "").unwrap();
+
+    // being unpacked or recently used
+    assert!(is_wheel_in_use(&wheel_dir).unwrap());
+    fs::write(wheel_dir.join(WHEEL_MARKER), "").unwrap();
+    assert!(is_wheel_in_use(&wheel_dir).unwrap());
+
+    let long_ago = SystemTime::now() - WHEEL_MIN_AGE * 2;
+    fs::File::options()
+        .write(true)
+        .open(wheel_dir.join(WHEEL_MARKER))
+        .unwrap()
+        .set_modified(long_ago)
+        .unwrap();
+    assert!(!is_wheel_in_use(&wheel_dir).unwrap());
+
+    #[cfg(unix)]
+    {
+        fs::hard_link(wheel_dir.join("module.py"), dir.path().join("linked.py")).unwrap();
+        assert!(is_wheel_in_use(&wheel_dir).unwrap());
+    }
+}
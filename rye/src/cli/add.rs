@@ -7,9 +7,12 @@ use pep440_rs::VersionSpecifiers;
 use pep508_rs::{Requirement, VersionOrUrl};
 use serde::Deserialize;
 
+use crate::auth::{get_index_urls, get_public_index_url, IndexAuth};
 use crate::bootstrap::ensure_self_venv;
-use crate::pyproject::{DependencyKind, PyProject};
-use crate::utils::{format_requirement, CommandOutput};
+use crate::lock::{get_lockfiles, read_lockfile_features, LockOptions};
+use crate::pyproject::{parse_extra_name, DependencyKind, PyProject, SourceKind, SourceRef};
+use crate::sync::{sync, SyncMode, SyncOptions};
+use crate::utils::{format_requirement, CommandOutput, VENV_BIN};
 
 #[derive(Deserialize, Debug)]
 struct Match {
@@ -25,8 +28,8 @@ pub struct Args {
     /// Add this as dev dependency.
     #[arg(long)]
     dev: bool,
-    /// Add this to an optional dependency group.
-    #[arg(long, conflicts_with = "dev")]
+    /// Add this to an optional dependency group, creating it if needed.
+    #[arg(long, conflicts_with = "dev", value_parser = parse_extra_name)]
     optional: Option<String>,
     /// Adds a dependency with a specific feature.
     #[arg(short, long)]
@@ -37,16 +40,22 @@ pub struct Args {
     /// Turns off all output.
     #[arg(short, long, conflicts_with = "verbose")]
     quiet: bool,
+    /// Only update the pyproject.toml but do not lock and sync.
+    #[arg(long)]
+    no_sync: bool,
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
     let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
     let mut unearth_path = ensure_self_venv(output).context("error bootstrapping venv")?;
-    let mut added = Vec::new();
-    unearth_path.push("bin");
+    let mut changes = Vec::new();
+    unearth_path.push(VENV_BIN);
     unearth_path.push("unearth");
 
     let mut pyproject_toml = PyProject::discover()?;
+    let sources = pyproject_toml.sources()?;
+    let source_args = get_unearth_args(&sources);
+    let auth = IndexAuth::new(&get_index_urls(&sources), output)?;
 
     for str_requirement in cmd.requirements {
         let mut requirement = Requirement::from_str(&str_requirement)?;
@@ -58,7 +67,12 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
             }
         }
 
-        let unearth = Command::new(&unearth_path)
+        let mut unearth_cmd = Command::new(&unearth_path);
+        for arg in &source_args {
+            unearth_cmd.arg(arg);
+        }
+        auth.apply(&mut unearth_cmd);
+        let unearth = unearth_cmd
             .arg("--")
             .arg(&str_requirement)
             .stdout(Stdio::piped())
@@ -75,26 +89,137 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
         }
         requirement.name = m.name;
 
-        pyproject_toml.add_dependency(
-            &requirement,
-            if cmd.dev {
-                DependencyKind::Dev
-            } else if let Some(ref section) = cmd.optional {
-                DependencyKind::Optional(section.into())
-            } else {
-                DependencyKind::Normal
-            },
-        )?;
-        added.push(requirement);
+        let kind = if cmd.dev {
+            DependencyKind::Dev
+        } else if let Some(ref section) = cmd.optional {
+            DependencyKind::Optional(section.clone().into())
+        } else {
+            DependencyKind::Normal
+        };
+        // a package is either a regular or a dev dependency, so it moves
+        // over instead of being listed twice.
+        let other_kind = match kind {
+            DependencyKind::Normal => Some(DependencyKind::Dev),
+            DependencyKind::Dev => Some(DependencyKind::Normal),
+            DependencyKind::Optional(_) => None,
+        };
+        let moved = match other_kind {
+            Some(other_kind) => pyproject_toml
+                .remove_dependency(&requirement, other_kind.clone())?
+                .map(|x| (format_requirement(&x).to_string(), other_kind)),
+            None => None,
+        };
+        let replaced = pyproject_toml.add_dependency(&requirement, kind.clone())?;
+        changes.push(Change {
+            requirement: format_requirement(&requirement).to_string(),
+            kind,
+            replaced,
+            moved,
+        });
     }
 
     pyproject_toml.save()?;
 
     if output != CommandOutput::Quiet {
-        for ref requirement in added {
-            println!("Added {}", format_requirement(requirement));
+        for change in &changes {
+            let requirement = &change.requirement;
+            if let Some((ref old, ref from)) = change.moved {
+                println!(
+                    "Moved {} from {} to {}",
+                    old,
+                    dependency_table(from),
+                    dependency_table(&change.kind)
+                );
+            }
+            match change.replaced {
+                None if change.moved.as_ref().is_some_and(|x| x.0 == *requirement) => {}
+                None if change.moved.is_some() => println!("Changed to {}", requirement),
+                None => println!("Added {}", requirement),
+                Some(ref old) if old == requirement => println!("Kept {}", requirement),
+                Some(ref old) => println!("Changed {} to {}", old, requirement),
+            }
         }
     }
 
+    if !cmd.no_sync {
+        // a group that is new to the lockfile is locked right away, whether
+        // it is installed is up to the features selected for the virtualenv.
+        if let Some(ref section) = cmd.optional {
+            let (lockfile, _) = get_lockfiles(&pyproject_toml.workspace_path(), None)?;
+            let mut features = read_lockfile_features(&lockfile);
+            let section = pyproject_toml
+                .optional_group(section)
+                .unwrap_or_else(|| section.clone());
+            if !features.contains(&section) {
+                features.push(section);
+                sync(SyncOptions {
+                    output,
+                    mode: SyncMode::LockOnly,
+                    lock_options: LockOptions {
+                        features: Some(features),
+                        ..LockOptions::default()
+                    },
+                    ..SyncOptions::default()
+                })
+                .context("failed to lock after adding dependencies")?;
+            }
+        }
+        sync(SyncOptions {
+            output,
+            dev: true,
+            mode: SyncMode::Regular,
+            ..SyncOptions::default()
+        })
+        .context("failed to sync after adding dependencies")?;
+    }
+
     Ok(())
 }
+
+/// What adding a requirement changed in the pyproject.toml.
+struct Change {
+    requirement: String,
+    kind: DependencyKind<'static>,
+    /// The entry of the same package that was replaced
+    replaced: Option<String>,
+    /// The entry that was moved over from the other dependency table
+    moved: Option<(String, DependencyKind<'static>)>,
+}
+
+/// Returns the name of the table that holds dependencies of a kind.
+fn dependency_table(kind: &DependencyKind) -> String {
+    match kind {
+        DependencyKind::Normal => "project.dependencies".into(),
+        DependencyKind::Dev => "tool.rye.dev-dependencies".into(),
+        DependencyKind::Optional(section) => {
+            format!("project.optional-dependencies.{}", section)
+        }
+    }
+}
+
+/// Returns the arguments that make unearth look in the package sources.
+///
+/// Unearth has no concept of extra indexes, so PyPI is passed explicitly
+/// unless the default source replaces it.
+fn get_unearth_args(sources: &[SourceRef]) -> Vec<String> {
+    let mut rv = Vec::new();
+    let has_indexes = sources.iter().any(|x| x.kind == SourceKind::Index);
+    if has_indexes && !sources.iter().any(|x| x.name == SourceRef::DEFAULT_NAME) {
+        rv.push("--index-url".into());
+        rv.push("https://pypi.org/simple/".into());
+    }
+    let (default, others): (Vec<_>, Vec<_>) = sources
+        .iter()
+        .partition(|x| x.name == SourceRef::DEFAULT_NAME);
+    for source in default.into_iter().chain(others) {
+        rv.push(match source.kind {
+            SourceKind::Index => "--index-url".into(),
+            SourceKind::FindLinks => "--find-link".into(),
+        });
+        rv.push(match source.kind {
+            SourceKind::Index => get_public_index_url(&source.url),
+            SourceKind::FindLinks => source.url.clone(),
+        });
+    }
+    rv
+}
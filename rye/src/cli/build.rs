@@ -7,7 +7,7 @@ use console::style;
 
 use crate::bootstrap::ensure_self_venv;
 use crate::pyproject::{normalize_package_name, PyProject};
-use crate::utils::CommandOutput;
+use crate::utils::{CommandOutput, VENV_BIN};
 
 /// Builds a package for distribution.
 #[derive(Parser, Debug)]
@@ -92,7 +92,7 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
             );
         }
 
-        let mut build_cmd = Command::new(venv.join("bin/python"));
+        let mut build_cmd = Command::new(venv.join(VENV_BIN).join("python"));
         build_cmd
             .arg("-mbuild")
             .env("NO_COLOR", "1")
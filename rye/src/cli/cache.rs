@@ -0,0 +1,69 @@
+use anyhow::Error;
+use clap::Parser;
+use console::style;
+use indicatif::HumanBytes;
+
+use crate::cache::{clear_pip_cache, dir_size, find_pip_cache_dir};
+
+/// Manages the pip cache shared by all projects and tools.
+///
+/// The location can be changed with `pip-cache-dir` in the `[behavior]`
+/// section of the config.  A `PIP_CACHE_DIR` environment variable takes
+/// precedence over it.
+#[derive(Parser, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: SubCommand,
+}
+
+/// Prints the path to the cache.
+#[derive(Parser, Debug)]
+pub struct DirCommand {}
+
+/// Prints the path to the cache and how much space it takes up.
+#[derive(Parser, Debug)]
+pub struct InfoCommand {}
+
+/// Removes everything from the cache.
+#[derive(Parser, Debug)]
+pub struct ClearCommand {
+    /// Only print how much space would be freed.
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    Dir(DirCommand),
+    Info(InfoCommand),
+    Clear(ClearCommand),
+}
+
+pub fn execute(cmd: Args) -> Result<(), Error> {
+    match cmd.command {
+        SubCommand::Dir(_) => {
+            println!("{}", find_pip_cache_dir()?.display());
+        }
+        SubCommand::Info(_) => {
+            let cache_dir = find_pip_cache_dir()?;
+            println!("path: {}", style(cache_dir.display()).cyan());
+            println!("size: {}", HumanBytes(dir_size(&cache_dir)));
+        }
+        SubCommand::Clear(args) => clear(args)?,
+    }
+    Ok(())
+}
+
+fn clear(cmd: ClearCommand) -> Result<(), Error> {
+    let size = clear_pip_cache(&find_pip_cache_dir()?, cmd.dry_run)?;
+    println!(
+        "{} {}",
+        if cmd.dry_run {
+            "would reclaim"
+        } else {
+            "reclaimed"
+        },
+        HumanBytes(size)
+    );
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::io;
+
+use anyhow::{bail, Error};
+use clap::Parser;
+use console::Term;
+
+use crate::auth::{get_index_host, set_credential};
+use crate::utils::CommandOutput;
+
+/// Manages the rye configuration.
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct Args {
+    /// Stores credentials for a package index in the system keyring.
+    ///
+    /// The password is prompted for or read from stdin.  Credentials are
+    /// looked up by host when the index asks for authentication.
+    #[arg(long, value_name = "INDEX_URL")]
+    set_credential: Option<String>,
+    /// The username to store with `--set-credential`.
+    #[arg(long, requires = "set_credential")]
+    username: Option<String>,
+}
+
+pub fn execute(cmd: Args) -> Result<(), Error> {
+    if let Some(index_url) = cmd.set_credential {
+        let host = get_index_host(&index_url)?;
+        let term = Term::stderr();
+        let username = match cmd.username {
+            Some(username) => username,
+            None if term.is_term() => {
+                term.write_str("Username: ")?;
+                term.read_line()?
+            }
+            None => bail!("--username is required when not running in a terminal"),
+        };
+        let password = if term.is_term() {
+            term.write_str("Password: ")?;
+            term.read_secure_line()?
+        } else {
+            let mut password = String::new();
+            io::stdin().read_line(&mut password)?;
+            password.trim_end_matches(['\r', '\n']).to_string()
+        };
+        if username.is_empty() || password.is_empty() {
+            bail!("username and password must not be empty");
+        }
+        set_credential(&index_url, &username, &password, CommandOutput::Normal)?;
+        eprintln!("stored credentials for {} in the keyring", host);
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Error};
+use clap::Parser;
+
+use crate::lock::{export_lockfile, get_lockfiles, read_lockfile_features, LockOptions};
+use crate::pyproject::PyProject;
+use crate::sync::{sync, SyncMode, SyncOptions};
+use crate::utils::CommandOutput;
+
+/// Exports the locked dependencies as a requirements file.
+///
+/// The file can be installed with `pip install -r` without rye.  The
+/// projects themselves are not included.
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Write the requirements to this file instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Leave out the dev dependencies.
+    #[arg(long)]
+    no_dev: bool,
+    /// Only include these optional dependency groups (comma separated).
+    ///
+    /// By default all groups of the lockfile are included.
+    #[arg(long, value_delimiter = ',')]
+    features: Option<Vec<String>>,
+    /// Leave out the hashes.
+    #[arg(long)]
+    no_hashes: bool,
+    /// Leave out the comment at the top.
+    #[arg(long)]
+    no_header: bool,
+    /// Update the lockfiles first, creating them if they do not exist.
+    #[arg(long)]
+    lock: bool,
+    /// Export the lockfiles specific to this platform (linux, macos or windows).
+    #[arg(long)]
+    platform: Option<String>,
+}
+
+pub fn execute(cmd: Args) -> Result<(), Error> {
+    let pyproject = PyProject::discover()?;
+    if cmd.lock {
+        sync(SyncOptions {
+            output: CommandOutput::Quiet,
+            mode: SyncMode::LockOnly,
+            lock_options: LockOptions {
+                platform: cmd.platform.clone(),
+                ..LockOptions::default()
+            },
+            ..SyncOptions::default()
+        })?;
+    }
+
+    let (lockfile, dev_lockfile) =
+        get_lockfiles(&pyproject.workspace_path(), cmd.platform.as_deref())?;
+    let lockfile = if cmd.no_dev { lockfile } else { dev_lockfile };
+    let contents = match fs::read_to_string(&lockfile) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => bail!(
+            "lockfile {} does not exist, run `rye lock` or pass --lock to create it",
+            lockfile.display()
+        ),
+        Err(err) => {
+            return Err(Error::new(err).context(format!("unable to read {}", lockfile.display())))
+        }
+    };
+
+    let features = cmd.features.map(|features| {
+        features
+            .iter()
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>()
+    });
+    let locked_features = read_lockfile_features(&lockfile);
+    for feature in features.iter().flatten() {
+        if !locked_features.contains(feature) {
+            bail!(
+                "feature '{}' is not in lockfile {}, run `rye lock --features` to add it",
+                feature,
+                lockfile.display()
+            );
+        }
+    }
+
+    let mut requirements = String::new();
+    if !cmd.no_header {
+        requirements.push_str(&format!(
+            "# generated by `rye export` from {}\n",
+            lockfile.file_name().unwrap_or_default().to_string_lossy()
+        ));
+    }
+    requirements.push_str(&export_lockfile(
+        &contents,
+        features.as_deref(),
+        !cmd.no_hashes,
+    ));
+    match cmd.output {
+        Some(path) => fs::write(&path, requirements)
+            .with_context(|| format!("unable to write {}", path.display()))?,
+        None => print!("{}", requirements),
+    }
+    Ok(())
+}
@@ -1,13 +1,15 @@
 use std::path::PathBuf;
 use std::{env, fs};
 
-use anyhow::{bail, Context, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use clap::{Parser, ValueEnum};
 use console::style;
 use minijinja::{context, Environment};
 use serde::Serialize;
 
-use crate::config::get_default_author;
+use crate::config::{get_default_author, get_pinnable_version, load_python_version_from};
+use crate::pyproject::is_valid_package_name;
+use crate::sources::{PythonVersion, PythonVersionRequest};
 
 #[derive(ValueEnum, Copy, Clone, Serialize, Debug)]
 #[value(rename_all = "snake_case")]
@@ -15,7 +17,18 @@ use crate::config::get_default_author;
 pub enum BuildSystem {
     Hatchling,
     Setuptools,
-    Filt,
+    #[value(alias = "filt")]
+    Flit,
+}
+
+/// What kind of project to scaffold.
+#[derive(Copy, Clone, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectKind {
+    /// A package to publish
+    Lib,
+    /// An application with a command
+    Script,
 }
 
 /// Creates a new python project.
@@ -24,12 +37,29 @@ pub struct Args {
     /// Where to place the project (defaults to current path)
     #[arg(default_value = ".")]
     path: PathBuf,
+    /// The name of the project (defaults to the name of the folder)
+    #[arg(long)]
+    name: Option<String>,
     /// Which interpreter version should be used?
+    ///
+    /// Defaults to the pinned version or the latest known one.
     #[arg(short, long)]
     py: Option<String>,
     /// Which build system should be used?
     #[arg(long, default_value = "hatchling")]
     build_system: BuildSystem,
+    /// Overwrite an existing pyproject.toml.
+    #[arg(short, long)]
+    force: bool,
+    /// Create a library package to publish (the default).
+    #[arg(long, conflicts_with = "script")]
+    lib: bool,
+    /// Create an application with a command to run.
+    #[arg(long)]
+    script: bool,
+    /// Do not create a README.md.
+    #[arg(long)]
+    no_readme: bool,
 }
 
 /// The pyproject.toml template
@@ -45,9 +75,21 @@ authors = [
 ]
 {%- endif %}
 dependencies = []
+{%- if readme %}
 readme = "README.md"
+{%- endif %}
 requires-python = {{ requires_python }}
 license = { text = {{ license }} }
+{%- if kind == "lib" %}
+classifiers = [
+    "Programming Language :: Python :: 3",
+    "License :: OSI Approved :: MIT License",
+]
+{%- else %}
+
+[project.scripts]
+{{ name }} = {{ entry_point }}
+{%- endif %}
 
 [build-system]
 {%- if build_system == "hatchling" %}
@@ -56,13 +98,18 @@ build-backend = "hatchling.build"
 {%- elif build_system == "setuptools" %}
 requires = ["setuptools>=61.0"]
 build-backend = "setuptools.build_meta"
-{%- elif build_system == "filt" %}
+{%- elif build_system == "flit" %}
 requires = ["flit_core>=3.4"]
-build-backend = "filt_core.buildapi"
+build-backend = "flit_core.buildapi"
 {%- endif %}
 
 [tool.rye]
 managed = true
+{%- if kind == "script" %}
+
+[tool.rye.scripts]
+dev = { call = {{ entry_point }} }
+{%- endif %}
 
 "#;
 
@@ -74,6 +121,22 @@ Describe your project here.
 
 "#;
 
+const INIT_PY_TEMPLATE: &str = r#"{%- if kind == "lib" -%}
+def hello() -> str:
+    return "Hello from {{ name }}!"
+{%- else -%}
+def main() -> None:
+    print("Hello from {{ name }}!")
+{%- endif %}
+
+"#;
+
+const MAIN_PY_TEMPLATE: &str = r#"from {{ package }} import main
+
+main()
+
+"#;
+
 const GITIGNORE_TEMPLATE: &str = r#"# python generated files
 __pycache__/
 *.py[oc]
@@ -87,6 +150,38 @@ wheels/
 
 "#;
 
+/// What goes into the pyproject.toml of a new project.
+struct Project<'a> {
+    name: &'a str,
+    package: &'a str,
+    kind: ProjectKind,
+    requires_python: String,
+    author: Option<(String, String)>,
+    license: &'a str,
+    build_system: BuildSystem,
+    readme: bool,
+}
+
+/// Renders the pyproject.toml of a new project.
+fn render_pyproject(project: &Project) -> Result<String, Error> {
+    let env = Environment::new();
+    Ok(env.render_named_str(
+        "pyproject.json",
+        TOML_TEMPLATE,
+        context! {
+            name => project.name,
+            version => "0.1.0",
+            author => project.author,
+            requires_python => project.requires_python,
+            license => project.license,
+            build_system => project.build_system,
+            kind => project.kind,
+            readme => project.readme,
+            entry_point => format!("{}:main", project.package),
+        },
+    )?)
+}
+
 pub fn execute(cmd: Args) -> Result<(), Error> {
     let env = Environment::new();
     let dir = env::current_dir()?.join(cmd.path);
@@ -94,37 +189,93 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
     let readme = dir.join("README.md");
     let gitignore = dir.join(".gitignore");
 
-    if toml.is_file() {
-        bail!("pyproject.toml already exists");
+    let python_version = dir.join(".python-version");
+
+    if toml.is_file() && !cmd.force {
+        bail!("pyproject.toml already exists, pass --force to overwrite it");
+    }
+
+    let name = match cmd.name {
+        Some(name) => name,
+        None => slug::slugify(
+            dir.file_name()
+                .map(|x| x.to_string_lossy())
+                .unwrap_or_default(),
+        ),
+    };
+    if !is_valid_package_name(&name) {
+        bail!(
+            "invalid project name '{}', pass a valid one with --name",
+            name
+        );
     }
 
+    // the project uses the requested python, otherwise the one that is
+    // already pinned for the folder or the latest one.
+    let py_ver = match cmd.py {
+        Some(ref py) => {
+            let req: PythonVersionRequest = py.parse()?;
+            get_pinnable_version(&req)
+                .ok_or_else(|| anyhow!("unsupported/unknown version for this platform"))?
+                .parse::<PythonVersion>()?
+        }
+        None => load_python_version_from(&dir).unwrap_or_else(PythonVersion::latest_cpython),
+    };
+
     // fail silently if it already exists or cannot be created.
     fs::create_dir_all(&dir).ok();
 
     // Write pyproject.toml
-    let py = cmd.py.as_deref().unwrap_or("3.8");
-    let name = slug::slugify(dir.file_name().unwrap().to_string_lossy());
-    let version = "0.1.0";
-    let requires_python = format!(">= {}", py);
-    let author = get_default_author();
+    let kind = if cmd.script {
+        ProjectKind::Script
+    } else {
+        ProjectKind::Lib
+    };
+    let package = name.replace(['-', '.'], "_").to_ascii_lowercase();
     let license = "MIT";
-
-    let rv = env.render_named_str(
-        "pyproject.json",
-        TOML_TEMPLATE,
-        context! {
-            name,
-            version,
-            author,
-            requires_python,
-            license,
-            build_system => cmd.build_system,
-        },
-    )?;
+    let rv = render_pyproject(&Project {
+        name: &name,
+        package: &package,
+        kind,
+        requires_python: format!(">= {}.{}", py_ver.major, py_ver.minor),
+        author: get_default_author(),
+        license,
+        build_system: cmd.build_system,
+        readme: !cmd.no_readme,
+    })?;
     fs::write(&toml, rv).context("failed to write pyproject.toml")?;
 
+    // pin the python version unless the folder has its own pin
+    if cmd.py.is_some() || !python_version.is_file() {
+        let pin =
+            get_pinnable_version(&py_ver.clone().into()).unwrap_or_else(|| py_ver.to_string());
+        fs::write(&python_version, format!("{}\n", pin))
+            .context("failed to write .python-version file")?;
+    }
+
+    // create the package if it is missing.  Libraries are marked as typed,
+    // applications can be run with `python -m`.
+    let package_dir = dir.join("src").join(&package);
+    if !package_dir.is_dir() {
+        fs::create_dir_all(&package_dir)
+            .with_context(|| format!("failed to create {}", package_dir.display()))?;
+        let rv = env.render_named_str("__init__.py", INIT_PY_TEMPLATE, context! { name, kind })?;
+        fs::write(package_dir.join("__init__.py"), rv).context("failed to write __init__.py")?;
+        match kind {
+            ProjectKind::Lib => {
+                fs::write(package_dir.join("py.typed"), "").context("failed to write py.typed")?
+            }
+            ProjectKind::Script => {
+                let rv =
+                    env.render_named_str("__main__.py", MAIN_PY_TEMPLATE, context! { package })?;
+                fs::write(package_dir.join("__main__.py"), rv)
+                    .context("failed to write __main__.py")?;
+            }
+        }
+    }
+
     // create a readme if one is missing
-    if !readme.is_file() {
+    if !cmd.no_readme && !readme.is_file() {
         let rv = env.render_named_str(
             "README.txt",
             README_TEMPLATE,
@@ -150,3 +301,41 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_render_pyproject() {
+    use toml_edit::Document;
+
+    let mut project = Project {
+        name: "my-app",
+        package: "my_app",
+        kind: ProjectKind::Script,
+        requires_python: ">= 3.11".into(),
+        author: None,
+        license: "MIT",
+        build_system: BuildSystem::Hatchling,
+        readme: false,
+    };
+    let doc: Document = render_pyproject(&project).unwrap().parse().unwrap();
+    assert_eq!(
+        doc["project"]["scripts"]["my-app"].as_str(),
+        Some("my_app:main")
+    );
+    assert_eq!(
+        doc["tool"]["rye"]["scripts"]["dev"]["call"].as_str(),
+        Some("my_app:main")
+    );
+    assert!(doc["project"].get("readme").is_none());
+
+    project.kind = ProjectKind::Lib;
+    project.readme = true;
+    project.build_system = BuildSystem::Flit;
+    let doc: Document = render_pyproject(&project).unwrap().parse().unwrap();
+    assert!(doc["project"].get("scripts").is_none());
+    assert!(doc["tool"]["rye"].get("scripts").is_none());
+    assert_eq!(doc["project"]["readme"].as_str(), Some("README.md"));
+    assert_eq!(
+        doc["build-system"]["build-backend"].as_str(),
+        Some("flit_core.buildapi")
+    );
+}
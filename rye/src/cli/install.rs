@@ -1,22 +1,99 @@
-use anyhow::Error;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::{env, fs, thread};
+
+use anyhow::{anyhow, bail, Context, Error};
 use clap::Parser;
-use pep508_rs::Requirement;
+use console::style;
 
-use crate::installer::install;
+use crate::bootstrap::{ensure_self_venv, is_offline};
+use crate::installer::{
+    expand_version_shorthand, install, parse_editable_requirement, parse_hashed_requirements,
+    parse_tool_requirement, IncludeDeps, InstallOptions,
+};
+use crate::pyproject::normalize_package_name;
 use crate::sources::PythonVersionRequest;
-use crate::utils::CommandOutput;
+use crate::tools::get_installed_tool;
+use crate::utils::{CommandOutput, QuietExit};
 
 /// Installs a package as global tool.
 #[derive(Parser, Debug)]
 pub struct Args {
-    /// The package to install.
-    requirement: String,
+    /// The packages to install as PEP 508 requirements, direct URLs or local
+    /// paths (with --editable).  e.g. 'black' or 'git+https://github.com/psf/black'
+    ///
+    /// `black@23.12.1` is short for `black==23.12.1` and `black@latest`
+    /// reinstalls the latest version even if black is already installed.
+    #[arg(required_unless_present = "requirements")]
+    requirement: Vec<String>,
+    /// Install every tool listed in a requirements file.
+    #[arg(short, long, conflicts_with = "editable")]
+    requirements: Option<PathBuf>,
+    /// Install a single tool from a requirements file in which every
+    /// requirement is pinned and hashed.  The tool is the one named on the
+    /// command line or the first requirement in the file.
+    #[arg(long, requires = "requirements", conflicts_with_all = ["editable", "constraint"])]
+    require_hashes: bool,
+    /// Install the package in editable mode from a local path.
+    #[arg(short, long)]
+    editable: bool,
     /// Optionally the Python version to use.
     #[arg(short, long)]
     python: Option<String>,
     /// Force install the package even if it's already there.
     #[arg(short, long)]
     force: bool,
+    /// Also install the scripts of all dependencies.
+    #[arg(long)]
+    include_deps: bool,
+    /// Also install the scripts of a specific dependency.
+    #[arg(long, conflicts_with = "include_deps")]
+    include_dep: Vec<String>,
+    /// Only install the given scripts of the package.
+    #[arg(long)]
+    only_script: Vec<String>,
+    /// Install a script under another name.  e.g. 'black=blk'
+    #[arg(long)]
+    script_alias: Vec<String>,
+    /// Include pre-releases and keep tracking them on upgrades.
+    #[arg(long)]
+    pre: bool,
+    /// Take over scripts that conflict with the shims of other tools.
+    #[arg(long)]
+    force_shims: bool,
+    /// Constrain the versions of the tool's dependencies with a constraints file.
+    #[arg(short, long)]
+    constraint: Option<PathBuf>,
+    /// The package index to install from.  Credentials can be referenced
+    /// from the environment with `${VAR}`.
+    #[arg(long)]
+    index_url: Option<String>,
+    /// Additional package indexes to install from.
+    #[arg(long)]
+    extra_index_url: Vec<String>,
+    /// Install from local folders of wheels.
+    #[arg(long)]
+    find_links: Vec<PathBuf>,
+    /// Do not access the network (requires --find-links or a wheelhouse).
+    #[arg(long)]
+    offline: bool,
+    /// Do not use pip's cache or the wheel cache shared with other tools.
+    #[arg(long)]
+    no_cache: bool,
+    /// Only show what would be installed.  Exits with 2 if the package is
+    /// already installed.
+    #[arg(long)]
+    dry_run: bool,
+    /// How many tools to install in parallel (defaults to the number of cores).
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Extra arguments to pass to pip (shell quoted).
+    #[arg(long, allow_hyphen_values = true)]
+    pip_args: Vec<String>,
     /// Enables verbose diagnostics.
     #[arg(short, long)]
     verbose: bool,
@@ -27,18 +104,479 @@ pub struct Args {
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
     let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
-    let py_ver: PythonVersionRequest = match cmd.python {
-        Some(ref py) => py.parse()?,
-        None => PythonVersionRequest {
-            kind: None,
-            major: 3,
-            minor: None,
-            patch: None,
-            suffix: None,
+    let py_ver: Option<PythonVersionRequest> = match cmd.python {
+        Some(ref py) => Some(py.parse()?),
+        None => None,
+    };
+    let mut pip_args = Vec::new();
+    for args in &cmd.pip_args {
+        pip_args.extend(shlex::split(args).ok_or_else(|| anyhow!("invalid pip args '{}'", args))?);
+    }
+
+    let include_deps = if cmd.include_deps {
+        IncludeDeps::All
+    } else if !cmd.include_dep.is_empty() {
+        IncludeDeps::Only(cmd.include_dep)
+    } else {
+        IncludeDeps::None
+    };
+
+    let mut script_aliases = BTreeMap::new();
+    for alias in &cmd.script_alias {
+        match alias.split_once('=') {
+            Some((name, alias)) if !name.is_empty() && !alias.is_empty() => {
+                script_aliases.insert(name.to_string(), alias.to_string());
+            }
+            _ => bail!(
+                "invalid script alias '{}', expected original=newname",
+                alias
+            ),
+        }
+    }
+
+    let mut options = InstallOptions {
+        force: cmd.force,
+        include_deps,
+        editable: cmd.editable,
+        pip_args,
+        force_shims: cmd.force_shims,
+        constraints: cmd.constraint,
+        hashed_requirements: if cmd.require_hashes {
+            cmd.requirements.clone()
+        } else {
+            None
         },
+        index_url: cmd.index_url,
+        extra_index_urls: cmd.extra_index_url,
+        find_links: cmd.find_links,
+        offline: cmd.offline || is_offline(),
+        pre: cmd.pre,
+        no_cache: cmd.no_cache,
+        dry_run: cmd.dry_run,
+        only_scripts: cmd.only_script,
+        script_aliases,
     };
-    let requirement: Requirement = cmd.requirement.parse()?;
 
-    install(requirement, &py_ver, cmd.force, output)?;
+    let jobs = match cmd.jobs {
+        Some(jobs) => jobs,
+        None => thread::available_parallelism().map_or(1, |x| x.get()),
+    };
+
+    if let Some(ref path) = options.hashed_requirements {
+        let requirements = parse_hashed_requirements(path)?;
+        let requirement = match cmd.requirement.as_slice() {
+            [] => requirements.into_iter().next(),
+            [name] => requirements
+                .into_iter()
+                .find(|x| normalize_package_name(&x.name) == normalize_package_name(name)),
+            _ => bail!("only a single tool can be installed with --require-hashes"),
+        };
+        let requirement = requirement.ok_or_else(|| {
+            anyhow!(
+                "{} not found in {}",
+                cmd.requirement.first().map_or("tool", |x| x.as_str()),
+                path.display()
+            )
+        })?;
+        install(requirement, py_ver.as_ref(), &options, output)?;
+        return Ok(());
+    }
+
+    if let Some(ref path) = cmd.requirements {
+        if !cmd.requirement.is_empty() {
+            bail!("packages cannot be combined with --requirements");
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        let lines = parse_tools_file(&contents);
+        return install_many(lines, true, py_ver.as_ref(), &options, jobs, output);
+    }
+
+    if cmd.requirement.len() > 1 {
+        let lines = cmd
+            .requirement
+            .into_iter()
+            .map(|requirement| ToolLine {
+                requirement,
+                python: None,
+            })
+            .collect();
+        return install_many(lines, false, py_ver.as_ref(), &options, jobs, output);
+    }
+
+    let requirement = cmd.requirement.first().map_or("", |x| x.as_str());
+    let requirement = if cmd.editable {
+        parse_editable_requirement(Path::new(requirement), output)?
+    } else {
+        let (requirement, latest) = expand_version_shorthand(requirement);
+        options.force |= latest;
+        parse_tool_requirement(&requirement, output)?
+    };
+    install(requirement, py_ver.as_ref(), &options, output)?;
+    Ok(())
+}
+
+/// Installs multiple tools, running up to `jobs` installations at once.
+///
+/// Failures do not stop the installation of the remaining tools.  When
+/// installing in parallel, every installation runs in a rye process of its
+/// own whose output is printed line by line, prefixed with the tool.
+fn install_many(
+    lines: Vec<ToolLine>,
+    skip_installed: bool,
+    py_ver: Option<&PythonVersionRequest>,
+    options: &InstallOptions,
+    jobs: usize,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    // bootstrapping is not safe to run concurrently
+    ensure_self_venv(output)?;
+
+    let jobs = jobs.clamp(1, lines.len().max(1));
+    let queue = Mutex::new(lines.into_iter());
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let line = match lock(&queue).next() {
+                    Some(line) => line,
+                    None => break,
+                };
+                let result =
+                    install_tool_line(&line, skip_installed, py_ver, options, jobs > 1, output);
+                if output != CommandOutput::Quiet {
+                    let prefix = style(format!("[{}]", line.requirement)).cyan();
+                    match result {
+                        Ok(true) if jobs > 1 => eprintln!("{} installed", prefix),
+                        Ok(false) => eprintln!("{} already installed", prefix),
+                        Err(_) if jobs > 1 => eprintln!("{} {}", prefix, style("failed").red()),
+                        _ => {}
+                    }
+                }
+                lock(&results).push((line, result));
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap_or_else(|x| x.into_inner());
+    let installed = results.iter().filter(|x| matches!(x.1, Ok(true))).count();
+    let skipped = results.iter().filter(|x| matches!(x.1, Ok(false))).count();
+    let failed: Vec<_> = results
+        .iter()
+        .filter_map(|(line, result)| result.as_ref().err().map(|err| (line, err)))
+        .collect();
+
+    if output != CommandOutput::Quiet {
+        eprintln!(
+            "{} installed, {} skipped, {} failed",
+            installed,
+            skipped,
+            failed.len()
+        );
+    }
+    for (line, err) in &failed {
+        eprintln!("{} {}: {:#}", style("failed:").red(), line.requirement, err);
+    }
+    if !failed.is_empty() {
+        bail!("failed to install {} tool(s)", failed.len());
+    }
     Ok(())
 }
+
+/// Locks a mutex, even if another installation panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|x| x.into_inner())
+}
+
+/// Installs a single tool from a list of tools.
+///
+/// With `in_child` the tool is installed by another rye process.  Returns
+/// `false` if the tool was skipped because it's already installed.
+fn install_tool_line(
+    line: &ToolLine,
+    skip_installed: bool,
+    py_ver: Option<&PythonVersionRequest>,
+    options: &InstallOptions,
+    in_child: bool,
+    output: CommandOutput,
+) -> Result<bool, Error> {
+    let mut force = options.force;
+    // resolving the name of a tool only reports errors in parallel mode, its
+    // output would not be prefixed.
+    let parse_output = if in_child {
+        CommandOutput::Quiet
+    } else {
+        output
+    };
+    let mut requirement = if options.editable {
+        parse_editable_requirement(Path::new(&line.requirement), parse_output)?
+    } else {
+        let (requirement, latest) = expand_version_shorthand(&line.requirement);
+        force |= latest;
+        parse_tool_requirement(&requirement, parse_output)?
+    };
+    // markers are meant for packages, not for tools
+    requirement.marker = None;
+    if skip_installed
+        && !force
+        && get_installed_tool(&requirement.name)?.is_some_and(|x| !x.is_incomplete())
+    {
+        return Ok(false);
+    }
+    let line_py_ver = match line.python {
+        Some(ref py) => Some(py.parse::<PythonVersionRequest>()?),
+        None => None,
+    };
+    let options = InstallOptions {
+        force,
+        ..options.clone()
+    };
+    if in_child {
+        return install_in_child(
+            &requirement.name,
+            &line.requirement,
+            line_py_ver.as_ref().or(py_ver),
+            &options,
+            output,
+        );
+    }
+    match install(
+        requirement,
+        line_py_ver.as_ref().or(py_ver),
+        &options,
+        output,
+    ) {
+        // a dry run signals an already installed package that way
+        Err(err) if err.is::<QuietExit>() => Ok(false),
+        Err(err) => Err(err),
+        Ok(()) => Ok(true),
+    }
+}
+
+/// Installs a tool with another rye process.
+///
+/// The output of the process is printed line by line, prefixed with the name
+/// of the tool.  Returns `false` if a dry run found the tool installed.
+fn install_in_child(
+    name: &str,
+    requirement: &str,
+    py_ver: Option<&PythonVersionRequest>,
+    options: &InstallOptions,
+    output: CommandOutput,
+) -> Result<bool, Error> {
+    let mut cmd = Command::new(env::current_exe()?);
+    cmd.arg("install")
+        .args(install_args(py_ver, options, output))
+        .arg("--")
+        .arg(requirement)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if console::colors_enabled_stderr() {
+        cmd.env("CLICOLOR_FORCE", "1");
+    }
+    let mut child = cmd.spawn().context("unable to run rye")?;
+    let prefix = style(format!("[{}]", name)).cyan().to_string();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    thread::scope(|scope| {
+        scope.spawn(|| print_prefixed(stdout, &prefix, false));
+        print_prefixed(stderr, &prefix, true);
+    });
+    let status = child.wait()?;
+    match status.code() {
+        Some(0) => Ok(true),
+        // a dry run signals an already installed package that way
+        Some(2) if options.dry_run => Ok(false),
+        _ => bail!("installation failed ({})", status),
+    }
+}
+
+/// Prints the lines of a stream with a prefix.
+fn print_prefixed(stream: impl Read, prefix: &str, to_stderr: bool) {
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        if to_stderr {
+            eprintln!("{} {}", prefix, line);
+        } else {
+            println!("{} {}", prefix, line);
+        }
+    }
+}
+
+/// Returns the arguments of `rye install` for the given options.
+fn install_args(
+    py_ver: Option<&PythonVersionRequest>,
+    options: &InstallOptions,
+    output: CommandOutput,
+) -> Vec<OsString> {
+    let mut rv: Vec<OsString> = Vec::new();
+    let mut flag = |flag: &str, value: Option<OsString>| {
+        rv.push(flag.into());
+        rv.extend(value);
+    };
+    if let Some(py_ver) = py_ver {
+        flag("--python", Some(py_ver.to_string().into()));
+    }
+    if options.force {
+        flag("--force", None);
+    }
+    match options.include_deps {
+        IncludeDeps::None => {}
+        IncludeDeps::Only(ref deps) => {
+            for dep in deps {
+                flag("--include-dep", Some(dep.into()));
+            }
+        }
+        IncludeDeps::All => flag("--include-deps", None),
+    }
+    if options.editable {
+        flag("--editable", None);
+    }
+    for script in &options.only_scripts {
+        flag("--only-script", Some(script.into()));
+    }
+    for (script, alias) in &options.script_aliases {
+        flag(
+            "--script-alias",
+            Some(format!("{}={}", script, alias).into()),
+        );
+    }
+    if options.pre {
+        flag("--pre", None);
+    }
+    if options.force_shims {
+        flag("--force-shims", None);
+    }
+    if let Some(ref constraints) = options.constraints {
+        flag("--constraint", Some(constraints.into()));
+    }
+    if let Some(ref index_url) = options.index_url {
+        flag("--index-url", Some(index_url.into()));
+    }
+    for index_url in &options.extra_index_urls {
+        flag("--extra-index-url", Some(index_url.into()));
+    }
+    for path in &options.find_links {
+        flag("--find-links", Some(path.into()));
+    }
+    if options.offline {
+        flag("--offline", None);
+    }
+    if options.no_cache {
+        flag("--no-cache", None);
+    }
+    if options.dry_run {
+        flag("--dry-run", None);
+    }
+    for arg in &options.pip_args {
+        flag(&format!("--pip-args={}", shlex::quote(arg)), None);
+    }
+    match output {
+        CommandOutput::Normal => {}
+        CommandOutput::Verbose => flag("--verbose", None),
+        CommandOutput::Quiet => flag("--quiet", None),
+    }
+    rv
+}
+
+/// A tool requirement from a requirements file.
+#[derive(Debug, PartialEq, Eq)]
+struct ToolLine {
+    requirement: String,
+    python: Option<String>,
+}
+
+/// Parses a requirements file with one tool per line.
+///
+/// Comments are ignored, except for `# rye: python=3.11` which selects the
+/// interpreter for the tool on that line.
+fn parse_tools_file(contents: &str) -> Vec<ToolLine> {
+    let mut rv = Vec::new();
+    for line in contents.lines() {
+        // like pip, only a hash at the start or after whitespace starts a
+        // comment so that url fragments are retained.
+        let comment_start = line.char_indices().find_map(|(idx, c)| {
+            (c == '#' && (idx == 0 || line[..idx].ends_with(char::is_whitespace))).then_some(idx)
+        });
+        let (requirement, comment) = match comment_start {
+            Some(idx) => (&line[..idx], Some(&line[idx..])),
+            None => (line, None),
+        };
+        let requirement = requirement.trim();
+        if requirement.is_empty() {
+            continue;
+        }
+        let python = comment
+            .and_then(|x| x.trim_start_matches('#').trim().strip_prefix("rye:"))
+            .and_then(|x| x.split_whitespace().find_map(|x| x.strip_prefix("python=")))
+            .map(|x| x.to_string());
+        rv.push(ToolLine {
+            requirement: requirement.to_string(),
+            python,
+        });
+    }
+    rv
+}
+
+#[test]
+fn test_parse_tools_file() {
+    let lines = parse_tools_file(
+        "# my tools\nblack>=23\ngit+https://github.com/psf/black#egg=black\n\nruff  # rye: python=3.11\nhttpie; sys_platform == 'linux'\n",
+    );
+    assert_eq!(
+        lines,
+        vec![
+            ToolLine {
+                requirement: "black>=23".into(),
+                python: None,
+            },
+            ToolLine {
+                requirement: "git+https://github.com/psf/black#egg=black".into(),
+                python: None,
+            },
+            ToolLine {
+                requirement: "ruff".into(),
+                python: Some("3.11".into()),
+            },
+            ToolLine {
+                requirement: "httpie; sys_platform == 'linux'".into(),
+                python: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_install_args() {
+    let options = InstallOptions {
+        include_deps: IncludeDeps::Only(vec!["six".into()]),
+        script_aliases: [("black".to_string(), "blk".to_string())].into(),
+        index_url: Some("https://${TOKEN}@example.com/simple".into()),
+        pip_args: vec!["--no-binary".into(), ":all: x".into()],
+        dry_run: true,
+        ..InstallOptions::default()
+    };
+    let py_ver: PythonVersionRequest = "3.11".parse().unwrap();
+    let args = install_args(Some(&py_ver), &options, CommandOutput::Quiet);
+    assert_eq!(
+        args.iter()
+            .map(|x| x.to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        [
+            "--python",
+            "3.11",
+            "--include-dep",
+            "six",
+            "--script-alias",
+            "black=blk",
+            "--index-url",
+            "https://${TOKEN}@example.com/simple",
+            "--dry-run",
+            "--pip-args=--no-binary",
+            "--pip-args=\":all: x\"",
+            "--quiet",
+        ]
+    );
+}
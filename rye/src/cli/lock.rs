@@ -1,7 +1,11 @@
+use std::path::PathBuf;
+
 use anyhow::Error;
 use clap::Parser;
 
-use crate::lock::LockOptions;
+use crate::backend::BackendKind;
+use crate::bootstrap::set_offline;
+use crate::lock::{parse_exclude_newer, LockOptions};
 use crate::sync::{sync, SyncMode, SyncOptions};
 use crate::utils::CommandOutput;
 
@@ -14,19 +18,62 @@ pub struct Args {
     /// Turns off all output.
     #[arg(short, long, conflicts_with = "verbose")]
     quiet: bool,
-    /// Update a specific package.
+    /// Update a specific package, keeping the other pins (can be repeated).
     #[arg(long)]
     update: Vec<String>,
     /// Update all packages to the latest
     #[arg(long)]
     update_all: bool,
-    /// Update to pre-release versions
+    /// Allow pre-release versions (also `tool.rye.allow-prereleases`).
     #[arg(long)]
     pre: bool,
+    /// Only print the changes to the pinned versions.
+    #[arg(long)]
+    dry_run: bool,
+    /// Include these optional dependency groups (comma separated).
+    ///
+    /// The selection is kept for later syncs, pass an empty value to clear it.
+    #[arg(long, value_delimiter = ',', conflicts_with = "all_features")]
+    features: Option<Vec<String>>,
+    /// Include all optional dependency groups.
+    #[arg(long)]
+    all_features: bool,
+    /// Do not include hashes in the lockfiles.
+    #[arg(long)]
+    no_hashes: bool,
+    /// Resolve with a constraints file (also `tool.rye.constraints`).
+    #[arg(long)]
+    constraint: Vec<PathBuf>,
+    /// Use lockfiles specific to this platform (linux, macos or windows).
+    ///
+    /// They are used automatically on their platform once they exist.
+    #[arg(long)]
+    platform: Option<String>,
+    /// Ignore distributions uploaded after this date (requires uv).
+    ///
+    /// This is a date like 2024-01-01 or an RFC 3339 timestamp.  It is kept
+    /// in the lockfile for later locks.
+    #[arg(long, value_parser = parse_exclude_newer)]
+    exclude_newer: Option<String>,
+    /// Stop ignoring distributions uploaded after the date of the lockfile.
+    #[arg(long, conflicts_with = "exclude_newer")]
+    clear_exclude_newer: bool,
+    /// The tool to resolve and install packages with (also `behavior.use-uv`).
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
+    /// Do not access the network (also enabled by `RYE_OFFLINE`).
+    ///
+    /// Packages are only installed from the configured wheelhouse and
+    /// toolchains must already be downloaded.
+    #[arg(long)]
+    offline: bool,
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
     let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
+    if cmd.offline {
+        set_offline();
+    }
     sync(SyncOptions {
         output,
         mode: SyncMode::LockOnly,
@@ -34,6 +81,16 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
             update: cmd.update,
             update_all: cmd.update_all,
             pre: cmd.pre,
+            dry_run: cmd.dry_run,
+            features: cmd.features,
+            all_features: cmd.all_features,
+            no_hashes: cmd.no_hashes,
+            constraints: cmd.constraint,
+            platform: cmd.platform,
+            backend: cmd.backend,
+            exclude_newer: cmd.exclude_newer,
+            clear_exclude_newer: cmd.clear_exclude_newer,
+            ..LockOptions::default()
         },
         ..SyncOptions::default()
     })?;
@@ -3,6 +3,9 @@ use clap::Parser;
 
 mod add;
 mod build;
+mod cache;
+mod config;
+mod export;
 mod fetch;
 mod init;
 mod install;
@@ -15,7 +18,9 @@ mod shim;
 mod show;
 mod sync;
 mod toolchain;
+mod tools;
 mod uninstall;
+mod version;
 
 use git_testament::git_testament;
 
@@ -34,9 +39,13 @@ struct Args {
 }
 
 #[derive(Parser, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     Add(add::Args),
     Build(build::Args),
+    Cache(cache::Args),
+    Config(config::Args),
+    Export(export::Args),
     Fetch(fetch::Args),
     Init(init::Args),
     Install(install::Args),
@@ -47,9 +56,11 @@ enum Command {
     Show(show::Args),
     Sync(sync::Args),
     Toolchain(toolchain::Args),
+    Tools(tools::Args),
     #[command(name = "self")]
     Rye(rye::Args),
     Uninstall(uninstall::Args),
+    Version(version::Args),
 }
 
 pub fn execute() -> Result<(), Error> {
@@ -68,6 +79,9 @@ pub fn execute() -> Result<(), Error> {
     match cmd {
         Command::Add(cmd) => add::execute(cmd),
         Command::Build(cmd) => build::execute(cmd),
+        Command::Cache(cmd) => cache::execute(cmd),
+        Command::Config(cmd) => config::execute(cmd),
+        Command::Export(cmd) => export::execute(cmd),
         Command::Fetch(cmd) => fetch::execute(cmd),
         Command::Init(cmd) => init::execute(cmd),
         Command::Install(cmd) => install::execute(cmd),
@@ -78,8 +92,10 @@ pub fn execute() -> Result<(), Error> {
         Command::Show(cmd) => show::execute(cmd),
         Command::Sync(cmd) => sync::execute(cmd),
         Command::Toolchain(cmd) => toolchain::execute(cmd),
+        Command::Tools(cmd) => tools::execute(cmd),
         Command::Rye(cmd) => rye::execute(cmd),
         Command::Uninstall(cmd) => uninstall::execute(cmd),
+        Command::Version(cmd) => version::execute(cmd),
     }
 }
 
@@ -4,31 +4,96 @@ use std::fs;
 use anyhow::Context;
 use anyhow::{anyhow, Error};
 use clap::Parser;
+use console::style;
 
-use crate::config::get_pinnable_version;
+use crate::config::{find_python_version_file, get_pinnable_version, load_python_version_file};
 use crate::pyproject::PyProject;
-use crate::sources::PythonVersionRequest;
+use crate::sources::{PythonVersion, PythonVersionRequest};
+use crate::sync::{collect_requires_python, format_requires_python, pick_python_version};
 
 /// Pins a Python version to this project.
+///
+/// The version is written to a `.python-version` file next to the
+/// pyproject.toml, or the one of the workspace.  Without a version the
+/// current pin is printed.
 #[derive(Parser, Debug)]
 pub struct Args {
     /// The version of Python to pin.
-    version: String,
+    version: Option<String>,
+    /// Also set `requires-python` of the project to this version or newer.
+    #[arg(long, requires = "version")]
+    update_requires_python: bool,
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
-    let req: PythonVersionRequest = cmd.version.parse()?;
+    let Some(version) = cmd.version else {
+        return print_pin();
+    };
+    let req: PythonVersionRequest = version.parse()?;
     let to_write = get_pinnable_version(&req)
         .ok_or_else(|| anyhow!("unsupported/unknown version for this platform"))?;
 
-    let version_file = match PyProject::discover() {
-        Ok(proj) => proj.root_path().join(".python-version"),
-        Err(_) => env::current_dir()?.join(".python-version"),
+    let pyproject = PyProject::discover().ok();
+    let version_file = match pyproject {
+        Some(ref proj) => proj.workspace_path().join(".python-version"),
+        None => env::current_dir()?.join(".python-version"),
     };
     fs::write(&version_file, format!("{}\n", to_write))
         .context("failed to write .python-version file")?;
 
     eprintln!("pinned {} in {}", to_write, version_file.display());
 
+    if let Some(mut pyproject) = pyproject {
+        let py_ver: PythonVersion = to_write.parse()?;
+        if cmd.update_requires_python {
+            let requires_python = format!(">= {}.{}", py_ver.major, py_ver.minor);
+            pyproject.set_requires_python(&requires_python);
+            pyproject.save()?;
+            eprintln!("updated requires-python to '{}'", requires_python);
+            // members of the workspace see the change when loaded again
+            pyproject = PyProject::load(&pyproject.toml_path())?;
+        }
+        let unsatisfied = collect_requires_python(&pyproject)?
+            .into_iter()
+            .filter(|(_, specifiers)| !py_ver.satisfies(specifiers))
+            .collect::<Vec<_>>();
+        if !unsatisfied.is_empty() {
+            eprintln!(
+                "{} Python {} does not satisfy requires-python {}, syncing will fail",
+                style("warning:").yellow().bold(),
+                py_ver,
+                format_requires_python(&unsatisfied)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the Python version that applies and where it comes from.
+fn print_pin() -> Result<(), Error> {
+    if let Some(version_file) = find_python_version_file(&env::current_dir()?) {
+        let py_ver = load_python_version_file(&version_file)?;
+        println!("{} (pinned in {})", py_ver, version_file.display());
+        return Ok(());
+    }
+    match PyProject::discover() {
+        Ok(pyproject) => {
+            let requires_python = collect_requires_python(&pyproject)?;
+            let py_ver = pick_python_version(&requires_python)?;
+            if requires_python.is_empty() {
+                println!("{} (not pinned, latest version)", py_ver);
+            } else {
+                println!(
+                    "{} (not pinned, latest version satisfying requires-python)",
+                    py_ver
+                );
+            }
+        }
+        Err(_) => println!(
+            "{} (not pinned, latest version)",
+            PythonVersion::latest_cpython()
+        ),
+    }
     Ok(())
 }
@@ -1,10 +1,12 @@
 use std::str::FromStr;
 
-use anyhow::Error;
+use anyhow::{Context, Error};
 use clap::Parser;
+use console::style;
 use pep508_rs::Requirement;
 
 use crate::pyproject::{DependencyKind, PyProject};
+use crate::sync::{sync, SyncMode, SyncOptions};
 use crate::utils::{format_requirement, CommandOutput};
 
 /// Removes a package from this project.
@@ -18,12 +20,18 @@ pub struct Args {
     /// Remove this from an optional dependency group.
     #[arg(long, conflicts_with = "dev")]
     optional: Option<String>,
+    /// Delete the optional dependency group if it ends up empty.
+    #[arg(long, requires = "optional")]
+    prune: bool,
     /// Enables verbose diagnostics.
     #[arg(short, long)]
     verbose: bool,
     /// Turns off all output.
     #[arg(short, long, conflicts_with = "verbose")]
     quiet: bool,
+    /// Only update the pyproject.toml but do not lock and sync.
+    #[arg(long)]
+    no_sync: bool,
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
@@ -44,15 +52,41 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
             },
         )? {
             removed_packages.push(removed);
+        } else if output != CommandOutput::Quiet {
+            eprintln!(
+                "{} {} is not a dependency",
+                style("warning:").yellow(),
+                requirement.name
+            );
         }
     }
 
+    let mut pruned = None;
+    if let Some(ref section) = cmd.optional {
+        if cmd.prune && pyproject_toml.prune_optional_group(section) {
+            pruned = Some(section);
+        }
+    }
     pyproject_toml.save()?;
 
     if output != CommandOutput::Quiet {
-        for requirement in removed_packages {
-            println!("Removed {}", format_requirement(&requirement));
+        for requirement in &removed_packages {
+            println!("Removed {}", format_requirement(requirement));
         }
+        if let Some(section) = pruned {
+            println!("Removed empty group {}", section);
+        }
+    }
+
+    // syncing uninstalls the packages that nothing depends on anymore
+    if !cmd.no_sync && !removed_packages.is_empty() {
+        sync(SyncOptions {
+            output,
+            dev: true,
+            mode: SyncMode::Regular,
+            ..SyncOptions::default()
+        })
+        .context("failed to sync after removing dependencies")?;
     }
 
     Ok(())
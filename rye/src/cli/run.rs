@@ -1,16 +1,44 @@
-use std::env;
-use std::ffi::{CString, OsString};
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::prelude::OsStrExt;
-#[cfg(target_os = "windows")]
-use std::os::windows::prelude::OsStrExt;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::{OsStr, OsString};
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::str::FromStr;
+use std::{env, fs};
 
 use anyhow::{bail, Context, Error};
 use clap::Parser;
 use console::style;
+use pep508_rs::Requirement;
+use tempfile::TempDir;
+
+use crate::bootstrap::{ensure_self_venv, get_offline_index_args};
+use crate::cache::{get_overlay_cache_dir, use_pip_cache, OVERLAY_MARKER};
+
+use crate::pyproject::{normalize_package_name, PyProject, Script, ScriptOptions};
+use crate::sync::{autosync, get_venv_python_version, is_autosync_disabled_by_env};
+use crate::tools::find_tool_script;
+#[cfg(not(target_os = "windows"))]
+use crate::utils::exec_args;
+use crate::utils::{
+    expand_env_vars, format_requirement, load_env_file, CommandOutput, QuietExit, VENV_BIN,
+};
 
-use crate::pyproject::{PyProject, Script};
-use crate::sync::{sync, SyncOptions};
+const CALL_SCRIPT: &str = r#"
+import sys
+target = sys.argv.pop(1)
+sys.argv[0] = target
+module, _, func = target.partition(":")
+if func:
+    import importlib
+    obj = importlib.import_module(module)
+    for attr in func.split("."):
+        obj = getattr(obj, attr)
+    sys.exit(obj())
+else:
+    import runpy
+    runpy.run_module(module, run_name="__main__", alter_sys=True)
+"#;
 
 /// Runs a command installed into this package.
 #[derive(Parser, Debug)]
@@ -19,6 +47,56 @@ pub struct Args {
     /// List all commands
     #[arg(short, long)]
     list: bool,
+    /// Enables verbose diagnostics.  When listing, this also shows the
+    /// commands of scripts with a description.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Only list the scripts declared in the pyproject.toml.
+    #[arg(long, conflicts_with = "all")]
+    only_rye: bool,
+    /// Also list the python, pip and activation scripts of the virtualenv.
+    #[arg(long)]
+    all: bool,
+    /// Turns off the output of rye itself, the output of the command is
+    /// left untouched.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Use the project of this folder instead of the current one.  Scripts
+    /// still run in the current folder.
+    #[arg(short = 'C', long = "directory", value_name = "DIR")]
+    directory: Option<PathBuf>,
+    /// Use the project of this pyproject.toml.
+    #[arg(long, value_name = "PATH", conflicts_with = "directory")]
+    pyproject: Option<PathBuf>,
+    /// Use this package of the workspace.
+    #[arg(short, long)]
+    package: Option<String>,
+    /// Load environment variables from this file.  Can be given multiple
+    /// times, later files take precedence.
+    #[arg(long, value_name = "PATH")]
+    env_file: Vec<PathBuf>,
+    /// Let variables from env files override the ones already set.
+    #[arg(long)]
+    env_file_override: bool,
+    /// Set an environment variable as `KEY=VALUE` or unset it with `KEY`.
+    #[arg(short, long = "env", value_name = "KEY=VALUE")]
+    env: Vec<String>,
+    /// Run with an additional requirement which is installed into an
+    /// ephemeral environment on top of the virtualenv.  Can be given
+    /// multiple times.
+    #[arg(long = "with", value_name = "REQUIREMENT")]
+    with: Vec<String>,
+    /// Do not fall back to globally installed tools.
+    #[arg(long)]
+    no_global: bool,
+    /// Do not sync the virtualenv before running.  This is also the case
+    /// if `RYE_NO_AUTO_SYNC` is set.
+    #[arg(long)]
+    no_sync: bool,
+    /// Print the names of scripts starting with this prefix for shell
+    /// completion.
+    #[arg(long, value_name = "PREFIX", hide = true)]
+    complete: Option<String>,
     /// The command to run
     #[command(subcommand)]
     cmd: Option<Command>,
@@ -31,92 +109,825 @@ enum Command {
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
-    let pyproject = PyProject::discover()?;
+    let pyproject =
+        find_pyproject(cmd.directory.as_deref(), cmd.pyproject.as_deref()).and_then(|project| {
+            match cmd.package {
+                Some(ref package) => find_package(project, package),
+                None => Ok(project),
+            }
+        });
+
+    // completion must be fast and never fail, so there is no syncing and
+    // outside of projects there is nothing to complete.
+    if let Some(ref prefix) = cmd.complete {
+        if let Ok(pyproject) = pyproject {
+            let mut names: Vec<_> = pyproject
+                .list_scripts()
+                .into_iter()
+                .filter(|x| x.starts_with(prefix.as_str()))
+                .collect();
+            names.sort();
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        return Ok(());
+    }
+    let pyproject = pyproject?;
 
     // make sure we have the minimal virtualenv.
-    sync(SyncOptions::python_only()).context("failed to sync ahead of run")?;
+    if cmd.no_sync || is_autosync_disabled_by_env() {
+        if !pyproject.venv_path().is_dir() {
+            bail!(
+                "virtualenv {} does not exist, run `rye sync` to create it",
+                pyproject.venv_path().display()
+            );
+        }
+    } else {
+        autosync(
+            &pyproject,
+            CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose),
+        )
+        .context("failed to sync ahead of run")?;
+    }
     let venv_bin = pyproject.venv_bin_path();
 
     if cmd.list || cmd.cmd.is_none() {
-        return list_scripts(&pyproject);
+        return list_scripts(&pyproject, cmd.verbose, cmd.only_rye, cmd.all);
     }
-    let mut args = match cmd.cmd {
+    let args = match cmd.cmd {
         Some(Command::External(args)) => args,
         None => unreachable!(),
     };
 
+    // when we spawn into a script, we implicitly activate the virtualenv to make
+    // the life of tools easier that expect to be in one.  The environment of
+    // rye itself is changed so that spawned processes inherit it too.
+    activate_venv(&pyproject.venv_path(), &venv_bin)?;
+    let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
+    if !cmd.with.is_empty() {
+        let overlay = ensure_overlay(&pyproject, &cmd.with, output)?;
+        for (key, path) in [
+            ("PYTHONPATH", overlay.clone()),
+            ("PATH", overlay.join(VENV_BIN)),
+        ] {
+            let mut paths = vec![path];
+            if let Some(value) = env::var_os(key) {
+                paths.extend(env::split_paths(&value));
+            }
+            env::set_var(key, env::join_paths(paths)?);
+        }
+    }
+
+    if let Some(var) = cmd.env.iter().find(|x| x.is_empty() || x.starts_with('=')) {
+        bail!("invalid environment variable '{}', expected KEY=VALUE", var);
+    }
     let short_name = args[0].to_string_lossy().to_string();
+    let resolver = Resolver {
+        pyproject: &pyproject,
+        venv_bin: &venv_bin,
+        global_tools: !cmd.no_global,
+        output,
+    };
+    let plan = resolve_script(&resolver, args, &mut Vec::new())?;
+    let run_env = RunEnv {
+        env_file_defaults: match pyproject.script_defaults().env_file {
+            Some(path) => load_env_file(&pyproject.root_path().join(path))?,
+            None => Vec::new(),
+        },
+        env_file_extra: cmd
+            .env_file
+            .iter()
+            .map(|path| load_env_file(path))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect(),
+        env_file_override: cmd.env_file_override,
+        overrides: cmd
+            .env
+            .iter()
+            .map(|x| match x.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (x.to_string(), None),
+            })
+            .collect(),
+    };
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        // a single command replaces rye, everything else is spawned
+        if let Plan::Command(ref args, ref options, ref env_file_vars) = plan {
+            for (key, value) in run_env.changes(options, env_file_vars) {
+                match value {
+                    Some(value) => env::set_var(key, value),
+                    None => env::remove_var(key),
+                }
+            }
+            if let Some(ref cwd) = options.cwd {
+                env::set_current_dir(cwd)
+                    .with_context(|| format!("unable to change to {}", cwd.display()))?;
+            }
+            let args = exec_args(args)?;
+            let Err(err) = nix::unistd::execvp(&args[0], &args);
+            if err == nix::Error::ENOENT {
+                bail!(
+                    "No script with name '{}' found in virtualenv or on PATH",
+                    short_name
+                );
+            }
+            return Err(err.into());
+        }
+    }
+
+    // Windows cannot replace the current process, so commands are always
+    // spawned there and the exit code is passed on.
+    #[cfg(target_os = "windows")]
+    ignore_ctrl_c();
+    run_plan(&plan, &run_env)
+}
+
+/// Activates a virtualenv in the environment of rye.
+///
+/// Scripts often invoke `rye run` again.  The virtualenv that a previous
+/// invocation activated is recorded in `RYE_ACTIVE_VENV` so that its bin
+/// folder can be taken off the `PATH` again instead of piling up entries.
+fn activate_venv(venv: &Path, venv_bin: &Path) -> Result<(), Error> {
+    let previous_bin = env::var_os("RYE_ACTIVE_VENV").map(|x| PathBuf::from(x).join(VENV_BIN));
+    let mut paths = vec![venv_bin.to_path_buf()];
+    if let Some(path) = env::var_os("PATH") {
+        paths.extend(
+            env::split_paths(&path).filter(|x| x != venv_bin && Some(x) != previous_bin.as_ref()),
+        );
+    }
+    env::set_var("PATH", env::join_paths(paths)?);
+    env::set_var("VIRTUAL_ENV", venv);
+    env::set_var("RYE_ACTIVE_VENV", venv);
+    env::remove_var("PYTHONHOME");
+    Ok(())
+}
+
+/// Returns the ephemeral environment with extra requirements for a project.
+///
+/// The packages are installed into a folder that is put on the `PYTHONPATH`
+/// of the virtualenv.  Environments are cached by the set of requirements
+/// and the Python version, and evicted by `rye tools gc`.
+fn ensure_overlay(
+    pyproject: &PyProject,
+    requirements: &[String],
+    output: CommandOutput,
+) -> Result<PathBuf, Error> {
+    let mut requirements = requirements
+        .iter()
+        .map(|x| {
+            Requirement::from_str(x)
+                .map(|x| format_requirement(&x).to_string())
+                .with_context(|| format!("invalid requirement '{}'", x))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    requirements.sort();
+    requirements.dedup();
+
+    let py_ver = get_venv_python_version(&pyproject.venv_path())?;
+    let mut hasher = DefaultHasher::new();
+    hasher.write(py_ver.to_string().as_bytes());
+    for requirement in &requirements {
+        hasher.write_u8(0);
+        hasher.write(requirement.as_bytes());
+    }
+    let cache_dir = get_overlay_cache_dir()?;
+    let overlay = cache_dir.join(format!("{:016x}", hasher.finish()));
+
+    if !overlay.join(OVERLAY_MARKER).is_file() {
+        let self_venv = ensure_self_venv(output)?;
+        if output != CommandOutput::Quiet {
+            eprintln!(
+                "Installing {} into an ephemeral environment",
+                style(requirements.join(", ")).cyan()
+            );
+        }
+        fs::create_dir_all(&cache_dir)?;
+        let dir = TempDir::new_in(&cache_dir)?;
+        let mut cmd = ProcessCommand::new(self_venv.join(VENV_BIN).join("pip"));
+        cmd.arg("--python")
+            .arg(pyproject.venv_bin_path().join("python"))
+            .arg("install")
+            .arg("--target")
+            .arg(dir.path())
+            .args(get_offline_index_args()?)
+            .env("PYTHONWARNINGS", "ignore");
+        use_pip_cache(&mut cmd)?;
+        match output {
+            CommandOutput::Verbose => cmd.arg("--verbose"),
+            CommandOutput::Quiet => cmd.arg("-q"),
+            CommandOutput::Normal => &mut cmd,
+        };
+        cmd.args(&requirements);
+        let status = cmd.status().context("unable to run pip")?;
+        if !status.success() {
+            bail!("failed to install ephemeral requirements");
+        }
+        fs::remove_dir_all(&overlay).ok();
+        fs::rename(dir.into_path(), &overlay)
+            .with_context(|| format!("unable to create {}", overlay.display()))?;
+    }
+
+    // this also records the last use for the eviction
+    fs::write(overlay.join(OVERLAY_MARKER), "")?;
+    Ok(overlay)
+}
+
+/// Finds the project to run in.
+fn find_pyproject(directory: Option<&Path>, pyproject: Option<&Path>) -> Result<PyProject, Error> {
+    match (directory, pyproject) {
+        (Some(dir), _) => PyProject::discover_from(dir),
+        (_, Some(path)) => PyProject::load(
+            &path
+                .canonicalize()
+                .with_context(|| format!("unable to find {}", path.display()))?,
+        ),
+        (None, None) => PyProject::discover(),
+    }
+}
+
+/// Finds a package in the workspace of a project.
+fn find_package(project: PyProject, package: &str) -> Result<PyProject, Error> {
+    let workspace = match project.workspace() {
+        Some(workspace) => workspace.clone(),
+        None if project.normalized_name() == Some(normalize_package_name(package)) => {
+            return Ok(project)
+        }
+        None => bail!("unknown project '{}'", package),
+    };
+    if let Some(project) = workspace.get_project(package)? {
+        return Ok(project);
+    }
+    let mut names = Vec::new();
+    for project in workspace.iter_projects() {
+        names.extend(project?.normalized_name());
+    }
+    names.sort();
+    bail!(
+        "unknown project '{}' (available: {})",
+        package,
+        names.join(", ")
+    );
+}
+
+/// The environment changes that apply to all commands.
+struct RunEnv {
+    /// Variables from the env file of the script defaults
+    env_file_defaults: Vec<(String, String)>,
+    /// Variables from the env files given on the command line
+    env_file_extra: Vec<(String, String)>,
+    /// Should variables from env files override the ones already set?
+    env_file_override: bool,
+    /// Variables given on the command line, `None` unsets them
+    overrides: Vec<(String, Option<String>)>,
+}
+
+impl RunEnv {
+    /// Returns the variables to set or unset (`None`) for a command, in the
+    /// order they have to be applied.
+    ///
+    /// Env files come first: the command line ones win over the script's own
+    /// which win over the defaults, and unless overriding, variables that are
+    /// already set are left alone.  Then the env table of the script applies
+    /// and finally the variables of the command line.
+    fn changes(
+        &self,
+        options: &ScriptOptions,
+        env_file_vars: &[(String, String)],
+    ) -> Vec<(String, Option<String>)> {
+        let mut rv: Vec<(String, Option<String>)> = Vec::new();
+        for (key, value) in self
+            .env_file_defaults
+            .iter()
+            .chain(env_file_vars)
+            .chain(&self.env_file_extra)
+        {
+            rv.retain(|x| &x.0 != key);
+            rv.push((key.clone(), Some(value.clone())));
+        }
+        if !self.env_file_override {
+            rv.retain(|x| env::var_os(&x.0).is_none());
+        }
+        rv.extend(
+            options
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), Some(expand_env_vars(value)))),
+        );
+        rv.extend(options.env_remove.iter().map(|key| (key.clone(), None)));
+        rv.extend(self.overrides.iter().cloned());
+        rv
+    }
+}
+
+/// How deeply scripts can reference other scripts.
+const MAX_SCRIPT_DEPTH: usize = 32;
+
+/// What running a script comes down to.
+enum Plan {
+    /// A single command with the variables of its env file
+    Command(Vec<OsString>, ScriptOptions, Vec<(String, String)>),
+    /// Scripts that run one after another until one fails
+    Chain(Vec<Plan>),
+    /// A script with pre and post hooks
+    Hooked {
+        pre: Vec<Plan>,
+        main: Box<Plan>,
+        post: Vec<Plan>,
+        post_always: bool,
+    },
+}
+
+/// What scripts are resolved against.
+struct Resolver<'a> {
+    pyproject: &'a PyProject,
+    venv_bin: &'a Path,
+    /// Fall back to globally installed tools?
+    global_tools: bool,
+    output: CommandOutput,
+}
+
+/// Resolves a script into the plan to run it.
+///
+/// Chains and hooks reference other scripts which are resolved as well.
+/// `stack` holds the scripts that are being resolved to detect cycles.
+fn resolve_script(
+    resolver: &Resolver,
+    mut args: Vec<OsString>,
+    stack: &mut Vec<String>,
+) -> Result<Plan, Error> {
+    let Resolver {
+        pyproject,
+        venv_bin,
+        ..
+    } = *resolver;
+    // names that are not valid unicode cannot refer to scripts and are
+    // only used as commands.
+    let name = args[0].to_string_lossy().to_string();
+    let script_name = args[0].to_str();
+    if stack.contains(&name) {
+        bail!(
+            "scripts reference each other: {} -> {}",
+            stack.join(" -> "),
+            name
+        );
+    }
+    if stack.len() >= MAX_SCRIPT_DEPTH {
+        bail!("scripts are nested too deeply: {}", stack.join(" -> "));
+    }
+    let mut options = ScriptOptions::default();
+
+    // interpreter names always refer to the python of the virtualenv
+    if let Some((major, minor)) = script_name.and_then(parse_interpreter_name) {
+        let py_ver = get_venv_python_version(&pyproject.venv_path())?;
+        if major.is_some_and(|x| x != py_ver.major) || minor.is_some_and(|x| x != py_ver.minor) {
+            bail!(
+                "cannot run {} as the virtualenv of the project uses {}",
+                name,
+                py_ver
+            );
+        }
+        args[0] = find_venv_script(venv_bin, "python")
+            .unwrap_or_else(|| venv_bin.join("python"))
+            .into();
+        return Ok(Plan::Command(args, options, Vec::new()));
+    }
 
     // do we have a custom script to invoke?
-    match pyproject.get_script_cmd(&args[0].to_string_lossy()) {
-        Some(Script::Cmd(script_args)) if !script_args.is_empty() => {
-            let script_target = venv_bin.join(&script_args[0]);
-            if script_target.is_file() {
-                args = Some(script_target.as_os_str().to_owned())
-                    .into_iter()
-                    .chain(script_args.into_iter().map(OsString::from).skip(1))
-                    .chain(args.into_iter().skip(1))
-                    .collect();
-            } else {
-                args = script_args
-                    .into_iter()
-                    .map(OsString::from)
-                    .chain(args.into_iter().skip(1))
-                    .collect();
+    let script = match script_name {
+        Some(script_name) => pyproject.get_script_cmd(script_name)?,
+        None => None,
+    };
+    match script {
+        Some(Script::Chain(chain, _)) => {
+            if args.len() > 1 {
+                bail!("chained script '{}' does not accept arguments", name);
+            }
+            stack.push(name);
+            let chain = resolve_scripts(resolver, &chain, stack)?;
+            stack.pop();
+            return Ok(Plan::Chain(chain));
+        }
+        Some(Script::Cmd(script_args, script_options)) if !script_args.is_empty() => {
+            options = script_options;
+            let extra_args = args.split_off(1);
+            args = substitute_args(&script_args, extra_args)
+                .with_context(|| format!("unable to run script '{}'", name))?;
+            if args.is_empty() {
+                bail!("script '{}' expands to an empty command", name);
+            }
+            if let Some(script_target) = find_venv_script(venv_bin, &args[0]) {
+                args[0] = script_target.into();
             }
         }
+        Some(Script::Call(target, script_options)) => {
+            options = script_options;
+            let python =
+                find_venv_script(venv_bin, "python").unwrap_or_else(|| venv_bin.join("python"));
+            args = [
+                python.into(),
+                "-c".into(),
+                CALL_SCRIPT.into(),
+                target.into(),
+            ]
+            .into_iter()
+            .chain(args.into_iter().skip(1))
+            .collect();
+        }
         Some(Script::External(_)) => {
-            args[0] = venv_bin.join(&args[0]).into();
+            args[0] = find_venv_script(venv_bin, &args[0])
+                .unwrap_or_else(|| venv_bin.join(&args[0]))
+                .into();
+        }
+        // other commands are looked up in the virtualenv first, then in the
+        // globally installed tools and then on the PATH, unless they are
+        // given as path.
+        _ if Path::new(&args[0]).components().count() == 1 => {
+            let path = match find_venv_script(venv_bin, &args[0]) {
+                Some(path) => Some(path),
+                None => find_global_tool(resolver, script_name)?,
+            };
+            args[0] = match path.or_else(|| find_in_path(&args[0])) {
+                Some(path) => path.into(),
+                None => bail!(
+                    "No script with name '{}' found in virtualenv ({}) or on PATH",
+                    name,
+                    venv_bin.display()
+                ),
+            };
         }
         _ => {}
     }
 
-    let args = args
+    let env_file_vars = match options.env_file {
+        Some(ref path) => load_env_file(&pyproject.root_path().join(path))?,
+        None => Vec::new(),
+    };
+    if options.pre.is_empty() && options.post.is_empty() {
+        return Ok(Plan::Command(args, options, env_file_vars));
+    }
+    stack.push(name);
+    let pre = resolve_scripts(resolver, &options.pre, stack)?;
+    let post = resolve_scripts(resolver, &options.post, stack)?;
+    stack.pop();
+    let post_always = options.post_always;
+    Ok(Plan::Hooked {
+        pre,
+        main: Box::new(Plan::Command(args, options, env_file_vars)),
+        post,
+        post_always,
+    })
+}
+
+/// Parses `python`, `python3` or `python3.11` into the requested major and
+/// minor version.
+fn parse_interpreter_name(name: &str) -> Option<(Option<u8>, Option<u8>)> {
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+    let version = name.strip_prefix("python")?;
+    if version.is_empty() {
+        return Some((None, None));
+    }
+    if !version.bytes().all(|x| x.is_ascii_digit() || x == b'.') {
+        return None;
+    }
+    let (major, minor) = match version.split_once('.') {
+        Some((major, minor)) => (major, Some(minor.parse().ok()?)),
+        None => (version, None),
+    };
+    Some((Some(major.parse().ok()?), minor))
+}
+
+/// An argument placeholder in a script command.
+enum Placeholder {
+    /// `{args}`: all arguments
+    All,
+    /// `{argN}`: the argument at index N
+    Nth(usize),
+}
+
+/// Parses the placeholder at the start of a string.
+///
+/// Returns the placeholder and its length.
+fn parse_placeholder(s: &str) -> Option<(Placeholder, usize)> {
+    let end = s.find('}')?;
+    let placeholder = match s.strip_prefix('{')?[..end - 1].strip_prefix("arg")? {
+        "s" => Placeholder::All,
+        idx if !idx.is_empty() && idx.bytes().all(|x| x.is_ascii_digit()) => {
+            Placeholder::Nth(idx.parse().ok()?)
+        }
+        _ => return None,
+    };
+    Some((placeholder, end + 1))
+}
+
+/// Substitutes the argument placeholders of a script command.
+///
+/// `{args}` as a token of its own expands to all arguments, within a token
+/// they are joined with spaces.  `{arg0}`, `{arg1}` etc. expand to single
+/// arguments.  Placeholders are escaped by doubling the braces, for
+/// instance `{{args}}`.  Without placeholders the arguments are appended.
+fn substitute_args(cmd: &[String], args: Vec<OsString>) -> Result<Vec<OsString>, Error> {
+    let mut rv = Vec::new();
+    let mut substituted = false;
+    for token in cmd {
+        if token == "{args}" {
+            rv.extend(args.iter().cloned());
+            substituted = true;
+            continue;
+        }
+        let mut arg = OsString::new();
+        let mut rest = token.as_str();
+        while let Some(start) = rest.find('{') {
+            arg.push(&rest[..start]);
+            rest = &rest[start..];
+            if let Some((_, len)) =
+                parse_placeholder(&rest[1..]).filter(|(_, len)| rest[1 + len..].starts_with('}'))
+            {
+                arg.push(&rest[1..1 + len]);
+                rest = &rest[len + 2..];
+            } else if let Some((placeholder, len)) = parse_placeholder(rest) {
+                match placeholder {
+                    Placeholder::All => {
+                        for (idx, value) in args.iter().enumerate() {
+                            if idx > 0 {
+                                arg.push(" ");
+                            }
+                            arg.push(value);
+                        }
+                    }
+                    Placeholder::Nth(idx) => match args.get(idx) {
+                        Some(value) => arg.push(value),
+                        None => bail!("missing argument for {}", &rest[..len]),
+                    },
+                }
+                substituted = true;
+                rest = &rest[len..];
+            } else {
+                arg.push("{");
+                rest = &rest[1..];
+            }
+        }
+        arg.push(rest);
+        rv.push(arg);
+    }
+    if !substituted {
+        rv.extend(args);
+    }
+    Ok(rv)
+}
+
+/// Resolves scripts that are referenced by name.
+fn resolve_scripts(
+    resolver: &Resolver,
+    names: &[String],
+    stack: &mut Vec<String>,
+) -> Result<Vec<Plan>, Error> {
+    names
         .iter()
-        .filter_map(|x| CString::new(x.as_bytes()).ok())
-        .collect::<Vec<_>>();
-    let path = CString::new(args[0].as_bytes())?;
+        .map(|name| resolve_script(resolver, vec![name.into()], stack))
+        .collect()
+}
 
-    // when we spawn into a script, we implicitly activate the virtualenv to make
-    // the life of tools easier that expect to be in one.
-    env::set_var("VIRTUAL_ENV", &*pyproject.venv_path());
-    if let Some(path) = env::var_os("PATH") {
-        let mut new_path = venv_bin.as_os_str().to_owned();
-        new_path.push(":");
-        new_path.push(path);
-        env::set_var("PATH", new_path);
-    } else {
-        env::set_var("PATH", &*venv_bin);
+/// Finds the script of a globally installed tool if allowed.
+fn find_global_tool(resolver: &Resolver, name: Option<&str>) -> Result<Option<PathBuf>, Error> {
+    let name = match name {
+        Some(name) if resolver.global_tools => name,
+        _ => return Ok(None),
+    };
+    let rv = find_tool_script(name)?;
+    if rv.is_some() && resolver.output != CommandOutput::Quiet {
+        eprintln!(
+            "{}",
+            style(format!("note: using globally installed tool {}", name)).dim()
+        );
     }
-    env::remove_var("PYTHONHOME");
+    Ok(rv)
+}
 
-    #[cfg(not(target_os = "windows"))]
-    if let Err(err) = nix::unistd::execv(&path, &args) {
-        if err == nix::Error::ENOENT {
-            bail!("No script with name '{}' found in virtualenv", short_name);
+/// Runs a plan by spawning its commands.
+///
+/// The first failing command stops the plan, unless post hooks should run
+/// regardless.  The exit code of the failed command is passed on.
+fn run_plan(plan: &Plan, run_env: &RunEnv) -> Result<(), Error> {
+    let run_all = |plans: &[Plan]| plans.iter().try_for_each(|x| run_plan(x, run_env));
+    match plan {
+        Plan::Command(args, options, env_file_vars) => spawn_command(
+            args,
+            options.cwd.as_deref(),
+            &run_env.changes(options, env_file_vars),
+        ),
+        Plan::Chain(plans) => run_all(plans),
+        Plan::Hooked {
+            pre,
+            main,
+            post,
+            post_always,
+        } => {
+            run_all(pre)?;
+            let rv = run_plan(main, run_env);
+            if rv.is_ok() || *post_always {
+                let post_rv = run_all(post);
+                // the failure of the script itself takes precedence
+                return rv.and(post_rv);
+            }
+            rv
         }
-        return Err(err.into());
     }
+}
 
-    Ok(())
+/// Spawns a command with changes to the environment and waits for it.
+///
+/// If the command fails, rye exits with the same exit code.
+fn spawn_command(
+    args: &[OsString],
+    cwd: Option<&Path>,
+    env: &[(String, Option<String>)],
+) -> Result<(), Error> {
+    let mut cmd = ProcessCommand::new(&args[0]);
+    cmd.args(&args[1..]);
+    if let Some(cwd) = cwd {
+        cmd.current_dir(cwd);
+    }
+    for (key, value) in env {
+        match value {
+            Some(value) => cmd.env(key, value),
+            None => cmd.env_remove(key),
+        };
+    }
+    let status = cmd
+        .status()
+        .with_context(|| format!("unable to run {}", args[0].to_string_lossy()))?;
+    match status.code() {
+        Some(0) => Ok(()),
+        code => Err(QuietExit(code.unwrap_or(1)).into()),
+    }
+}
+
+/// Finds a script in the bin folder of the virtualenv.
+///
+/// On Windows the name is also tried with the executable extensions as
+/// scripts are not invoked with their extension there.
+fn find_venv_script(venv_bin: &Path, name: impl AsRef<OsStr>) -> Option<PathBuf> {
+    let path = venv_bin.join(name.as_ref());
+    if path.is_file() {
+        return Some(path);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        for ext in [".exe", ".cmd", ".bat"] {
+            let mut file_name = name.as_ref().to_os_string();
+            file_name.push(ext);
+            let path = venv_bin.join(file_name);
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// Finds an executable on the PATH.
+fn find_in_path(name: &OsStr) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path).find_map(|dir| {
+        #[cfg(not(target_os = "windows"))]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = dir.join(name);
+            path.metadata()
+                .is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+                .then_some(path)
+        }
+        #[cfg(target_os = "windows")]
+        {
+            let exts = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+            Some("")
+                .into_iter()
+                .chain(exts.split(';'))
+                .map(|ext| {
+                    let mut file_name = name.to_os_string();
+                    file_name.push(ext);
+                    dir.join(file_name)
+                })
+                .find(|path| path.is_file())
+        }
+    })
+}
+
+/// Makes rye survive Ctrl+C while the spawned command handles it.
+#[cfg(target_os = "windows")]
+fn ignore_ctrl_c() {
+    extern "system" {
+        fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+    }
+
+    // a handler is not inherited by the child unlike ignoring the signal
+    unsafe extern "system" fn handler(_ctrl_type: u32) -> i32 {
+        1
+    }
+
+    unsafe {
+        SetConsoleCtrlHandler(Some(handler), 1);
+    }
 }
 
-fn list_scripts(pyproject: &PyProject) -> Result<(), Error> {
+fn list_scripts(
+    pyproject: &PyProject,
+    verbose: bool,
+    only_rye: bool,
+    all: bool,
+) -> Result<(), Error> {
     let mut scripts: Vec<_> = pyproject
         .list_scripts()
         .into_iter()
         .filter_map(|name| {
-            let script = pyproject.get_script_cmd(&name)?;
+            let script = pyproject.get_script_cmd(&name).ok()??;
+            if let Script::External(_) = script {
+                if only_rye
+                    || (!all
+                        && ["python", "pip", "activate"]
+                            .iter()
+                            .any(|x| name.starts_with(x)))
+                {
+                    return None;
+                }
+            }
             Some((name, script))
         })
         .collect();
-    scripts.sort_by(|a, b| a.0.to_ascii_lowercase().cmp(&b.0.to_ascii_lowercase()));
+    scripts.sort_by_key(|a| a.0.to_ascii_lowercase());
+    let width = scripts
+        .iter()
+        .filter(|(_, script)| script.options().is_some_and(|x| x.help.is_some()))
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
     for (name, script) in scripts {
         if matches!(script, Script::External(_)) {
-            println!("{}", name);
+            println!("{} {}", name, style("(installed)").dim());
+            continue;
+        }
+        let mut hooks = Vec::new();
+        if let Some(options) = script.options() {
+            if !options.pre.is_empty() {
+                hooks.push(format!("pre: {}", options.pre.join(", ")));
+            }
+            if !options.post.is_empty() {
+                hooks.push(format!(
+                    "post{}: {}",
+                    if options.post_always { " (always)" } else { "" },
+                    options.post.join(", ")
+                ));
+            }
+        }
+        let hooks = if hooks.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", style(format!("[{}]", hooks.join("; "))).dim())
+        };
+        let origin = if pyproject.is_inherited_script(&name) {
+            format!(" {}", style("(from workspace root)").dim())
         } else {
-            println!("{} ({})", name, style(script).dim());
+            String::new()
+        };
+        match script.options().and_then(|x| x.help.as_deref()) {
+            Some(help) if verbose => println!(
+                "{:width$}  {} ({}){}{}",
+                name,
+                help,
+                style(&script).dim(),
+                hooks,
+                origin
+            ),
+            Some(help) => println!("{:width$}  {}{}", name, style(help).dim(), origin),
+            None => println!("{} ({}){}{}", name, style(&script).dim(), hooks, origin),
         }
     }
     Ok(())
 }
+
+#[test]
+fn test_substitute_args() {
+    let cmd = |s: &str| s.split(' ').map(|x| x.to_string()).collect::<Vec<_>>();
+    let args = vec![OsString::from("a b"), OsString::from("c")];
+    assert_eq!(
+        substitute_args(&cmd("run {args} --rm --opt={args} -x{arg1}"), args.clone()).unwrap(),
+        ["run", "a b", "c", "--rm", "--opt=a b c", "-xc"]
+    );
+    assert_eq!(
+        substitute_args(&cmd("echo {{args}} {arg} {"), args.clone()).unwrap(),
+        ["echo", "{args}", "{arg}", "{", "a b", "c"]
+    );
+    assert_eq!(
+        substitute_args(&cmd("echo {{arg0}}={arg0}"), args.clone()).unwrap(),
+        ["echo", "{arg0}=a b"]
+    );
+    assert!(substitute_args(&cmd("echo {arg2}"), args).is_err());
+}
@@ -1,7 +1,7 @@
 use std::process::Command;
 
 use anyhow::{bail, Context, Error};
-use clap::Parser;
+use clap::{CommandFactory, Parser, ValueEnum};
 
 /// Rye self management
 #[derive(Parser, Debug)]
@@ -27,17 +27,82 @@ pub struct UpdateCommand {
     force: bool,
 }
 
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Prints a shell completion script.
+///
+/// Besides the commands this completes the scripts of the current project
+/// for `rye run`.
+#[derive(Parser, Debug)]
+pub struct CompletionCommand {
+    /// The shell to generate the completion script for.
+    shell: Shell,
+}
+
 #[derive(Parser, Debug)]
 enum SubCommand {
     Update(UpdateCommand),
+    Completion(CompletionCommand),
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
     match cmd.command {
         SubCommand::Update(args) => update(args),
+        SubCommand::Completion(args) => completion(args),
     }
 }
 
+const BASH_COMPLETION: &str = r#"_rye() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+    elif [ "$COMP_CWORD" -eq 2 ] && [ "${COMP_WORDS[1]}" = run ]; then
+        COMPREPLY=($(rye run --complete "$cur" 2>/dev/null))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}
+complete -F _rye rye
+"#;
+
+const ZSH_COMPLETION: &str = r#"#compdef rye
+_rye() {
+    if (( CURRENT == 2 )); then
+        compadd -- {commands}
+    elif (( CURRENT == 3 )) && [[ ${words[2]} == run ]]; then
+        compadd -- ${(f)"$(rye run --complete "${words[CURRENT]}" 2>/dev/null)"}
+    else
+        _files
+    fi
+}
+compdef _rye rye
+"#;
+
+const FISH_COMPLETION: &str = r#"complete -c rye -n __fish_use_subcommand -f -a "{commands}"
+complete -c rye -n "__fish_seen_subcommand_from run; and test (count (commandline -opc)) -eq 2" -f -a "(rye run --complete (commandline -ct) 2>/dev/null)"
+"#;
+
+fn completion(args: CompletionCommand) -> Result<(), Error> {
+    let commands = super::Args::command()
+        .get_subcommands()
+        .filter(|x| !x.is_hide_set())
+        .map(|x| x.get_name().to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let script = match args.shell {
+        Shell::Bash => BASH_COMPLETION,
+        Shell::Zsh => ZSH_COMPLETION,
+        Shell::Fish => FISH_COMPLETION,
+    };
+    print!("{}", script.replace("{commands}", &commands));
+    Ok(())
+}
+
 fn update(args: UpdateCommand) -> Result<(), Error> {
     let mut cmd = Command::new("cargo");
     cmd.arg("install")
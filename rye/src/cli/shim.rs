@@ -1,17 +1,13 @@
 use std::env;
-use std::ffi::{CString, OsStr, OsString};
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::prelude::OsStrExt;
-#[cfg(target_os = "windows")]
-use std::os::windows::prelude::OsStrExt;
+use std::ffi::{OsStr, OsString};
 
 use anyhow::{bail, Context, Error};
 use same_file::is_same_file;
 
 use crate::bootstrap::{ensure_self_venv, get_pip_runner};
 use crate::pyproject::PyProject;
-use crate::sync::{sync, SyncOptions};
-use crate::utils::CommandOutput;
+use crate::sync::autosync;
+use crate::utils::{exec_args, CommandOutput, VENV_BIN};
 
 fn detect_shim() -> Option<(String, Vec<OsString>)> {
     // Shims are detected if the executable is linked into
@@ -43,7 +39,7 @@ fn get_pip_shim(
 ) -> Result<Vec<OsString>, Error> {
     let venv = ensure_self_venv(output)?;
     let runner = get_pip_runner(&venv);
-    let python = pyproject.venv_path().join("bin/python");
+    let python = pyproject.venv_path().join(VENV_BIN).join("python");
 
     // pip likes to emit deprecation warnings
     env::set_var("PYTHONWARNINGS", "ignore");
@@ -85,7 +81,7 @@ fn get_shim_target(target: &str, mut args: Vec<OsString>) -> Result<Option<Vec<O
     };
 
     // make sure we have the minimal virtualenv.
-    sync(SyncOptions::python_only()).context("sync ahead of shim resolution failed")?;
+    autosync(&pyproject, CommandOutput::Normal).context("sync ahead of shim resolution failed")?;
 
     let path = pyproject.venv_path().join("bin").join(target);
 
@@ -108,12 +104,8 @@ pub fn execute_shim() -> Result<(), Error> {
     if let Some((shim_name, args)) = detect_shim() {
         if let Some(args) = get_shim_target(&shim_name, args)? {
             let target = &args[0];
-            let args = args
-                .iter()
-                .filter_map(|x| CString::new(x.as_bytes()).ok())
-                .collect::<Vec<_>>();
-            let path = CString::new(args[0].as_bytes())?;
-            nix::unistd::execv(&path, &args)
+            let args = exec_args(&args)?;
+            nix::unistd::execv(&args[0], &args)
                 .with_context(|| format!("unable to spawn shim {}", target.to_string_lossy()))?;
         } else {
             bail!("target shim binary not found");
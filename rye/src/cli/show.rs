@@ -6,9 +6,11 @@ use clap::Parser;
 use console::style;
 
 use crate::bootstrap::ensure_self_venv;
-use crate::config::load_python_version;
+use crate::config::{find_python_version_file, load_python_version_file};
+use crate::lock::{get_lockfiles, is_lockfile_up_to_date, LockMode};
 use crate::pyproject::PyProject;
-use crate::utils::CommandOutput;
+use crate::sync::{get_venv_editable, get_venv_python_version};
+use crate::utils::{CommandOutput, VENV_BIN};
 
 /// Prints the current state of the project.
 #[derive(Parser, Debug)]
@@ -29,10 +31,76 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
         "project: {}",
         style(project.name().unwrap_or("<unnamed>")).yellow()
     );
-    println!("path: {}", style(project.root_path().display()).cyan());
-    println!("venv: {}", style(project.venv_path().display()).cyan());
-    if let Some(ver) = load_python_version() {
-        println!("pinned python: {}", style(ver).cyan());
+    match project.version() {
+        Ok(version) => println!("version: {}", style(version).cyan()),
+        Err(err) => println!(
+            "version: {}",
+            style(format!("unknown ({})", first_line(&err))).red()
+        ),
+    }
+    println!("pyproject: {}", style(project.toml_path().display()).cyan());
+
+    let venv = project.venv_path();
+    if venv.is_dir() {
+        println!("venv: {}", style(venv.display()).cyan());
+    } else {
+        println!(
+            "venv: {} {}",
+            style(venv.display()).cyan(),
+            style("(missing)").yellow()
+        );
+    }
+    let install_mode = |editable| if editable { "editable" } else { "regular" };
+    match get_venv_editable(&venv) {
+        // `rye sync --no-editable` overrides the configured mode
+        Some(installed) if installed != project.editable() => println!(
+            "install mode: {} ({} in venv)",
+            style(install_mode(project.editable())).cyan(),
+            style(install_mode(installed)).yellow()
+        ),
+        _ => println!(
+            "install mode: {}",
+            style(install_mode(project.editable())).cyan()
+        ),
+    }
+
+    let pinned = find_python_version_file(&project.root_path())
+        .map(|pin_file| (load_python_version_file(&pin_file), pin_file));
+    let venv_python = get_venv_python_version(&venv).ok();
+    match venv_python {
+        Some(ref ver) => match pinned {
+            Some((Ok(ref pin), _)) if pin != ver => println!(
+                "venv python: {} {}",
+                style(ver).yellow(),
+                style("(does not match the pin)").yellow()
+            ),
+            _ => println!("venv python: {}", style(ver).cyan()),
+        },
+        None if venv.is_dir() => println!("venv python: {}", style("unknown").red()),
+        None => println!("venv python: {}", style("missing").yellow()),
+    }
+    match pinned {
+        Some((Ok(pin), pin_file)) => println!(
+            "pinned python: {} ({})",
+            style(pin).cyan(),
+            style(pin_file.display()).dim()
+        ),
+        Some((Err(err), _)) => println!(
+            "pinned python: {}",
+            style(format!("invalid ({})", first_line(&err))).red()
+        ),
+        None => println!("pinned python: {}", style("not pinned").dim()),
+    }
+
+    match get_lockfiles(&project.workspace_path(), None) {
+        Ok((lockfile, dev_lockfile)) => {
+            print_lockfile_status(&project, "lockfile", LockMode::Production, &lockfile);
+            print_lockfile_status(&project, "dev lockfile", LockMode::Dev, &dev_lockfile);
+        }
+        Err(err) => println!(
+            "lockfile: {}",
+            style(format!("unknown ({})", first_line(&err))).red()
+        ),
     }
 
     if let Some(workspace) = project.workspace() {
@@ -41,7 +109,16 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
             style(project.workspace_path().display()).cyan()
         );
         println!("  members:");
-        let mut projects = workspace.iter_projects().collect::<Result<Vec<_>, _>>()?;
+        let mut projects = Vec::new();
+        for child in workspace.iter_projects() {
+            match child {
+                Ok(child) => projects.push(child),
+                Err(err) => println!(
+                    "    {}",
+                    style(format!("invalid member ({})", first_line(&err))).red()
+                ),
+            }
+        }
         projects.sort_by(|a, b| a.root_path().cmp(&b.root_path()));
         for child in projects {
             let root_path = child.root_path();
@@ -61,6 +138,30 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
     Ok(())
 }
 
+/// Prints if a lockfile exists and was generated from the current project.
+fn print_lockfile_status(project: &PyProject, label: &str, lock_mode: LockMode, lockfile: &Path) {
+    let status = if !lockfile.is_file() {
+        style("missing".to_string()).yellow()
+    } else {
+        match is_lockfile_up_to_date(project, lock_mode, lockfile) {
+            Ok(true) => style("up to date".to_string()).green(),
+            Ok(false) => style("out of date".to_string()).yellow(),
+            Err(err) => style(format!("unknown ({})", first_line(&err))).red(),
+        }
+    };
+    println!(
+        "{}: {} ({})",
+        label,
+        status,
+        style(lockfile.display()).dim()
+    );
+}
+
+/// Returns the first line of an error for a one line summary.
+fn first_line(err: &Error) -> String {
+    err.to_string().lines().next().unwrap_or("").to_string()
+}
+
 fn print_installed_deps(project: &PyProject) -> Result<(), Error> {
     let python = project.venv_bin_path().join("python");
     if !python.is_file() {
@@ -68,7 +169,7 @@ fn print_installed_deps(project: &PyProject) -> Result<(), Error> {
     }
     let self_venv = ensure_self_venv(CommandOutput::Normal)?;
 
-    let status = Command::new(self_venv.join("bin/pip"))
+    let status = Command::new(self_venv.join(VENV_BIN).join("pip"))
         .arg("--python")
         .arg(&python)
         .arg("freeze")
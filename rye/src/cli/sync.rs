@@ -1,6 +1,10 @@
+use std::path::PathBuf;
+
 use anyhow::Error;
 use clap::Parser;
 
+use crate::backend::BackendKind;
+use crate::bootstrap::set_offline;
 use crate::lock::LockOptions;
 use crate::sync::{sync, SyncMode, SyncOptions};
 use crate::utils::CommandOutput;
@@ -11,7 +15,7 @@ pub struct Args {
     /// Force the environment to be re-created
     #[arg(short, long)]
     force: bool,
-    /// Do not include dev dependencies.
+    /// Do not include dev dependencies, uninstalling them if present.
     #[arg(long)]
     no_dev: bool,
     /// Enables verbose diagnostics.
@@ -20,19 +24,91 @@ pub struct Args {
     /// Turns off all output.
     #[arg(short, long, conflicts_with = "verbose")]
     quiet: bool,
-    /// Update a specific package.
+    /// Update a specific package, keeping the other pins (can be repeated).
     #[arg(long)]
     update: Vec<String>,
     /// Update all packages to the latest
     #[arg(long)]
     update_all: bool,
-    /// Update to pre-release versions
+    /// Allow pre-release versions (also `tool.rye.allow-prereleases`).
     #[arg(long)]
     pre: bool,
+    /// Only print what would change in the lockfiles and the virtualenv.
+    ///
+    /// Nothing is written.  Exits with 1 if the virtualenv is not up to date.
+    #[arg(long)]
+    dry_run: bool,
+    /// Include these optional dependency groups (comma separated).
+    ///
+    /// Only these groups are installed from the lockfile and the ones it does
+    /// not have yet are added to it.  The selection is kept for later syncs,
+    /// pass an empty value to install none of them.
+    #[arg(long, value_delimiter = ',', conflicts_with = "all_features")]
+    features: Option<Vec<String>>,
+    /// Include all optional dependency groups.
+    #[arg(long)]
+    all_features: bool,
+    /// Do not include hashes in the lockfiles.
+    #[arg(long)]
+    no_hashes: bool,
+    /// Resolve with a constraints file (also `tool.rye.constraints`).
+    #[arg(long)]
+    constraint: Vec<PathBuf>,
+    /// Use lockfiles specific to this platform (linux, macos or windows).
+    ///
+    /// They are used automatically on their platform once they exist.
+    #[arg(long)]
+    platform: Option<String>,
+    /// The tool to resolve and install packages with (also `behavior.use-uv`).
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
+    /// Do not access the network (also enabled by `RYE_OFFLINE`).
+    ///
+    /// Packages are only installed from the configured wheelhouse and
+    /// toolchains must already be downloaded.
+    #[arg(long)]
+    offline: bool,
+    /// Install from the existing lockfiles without updating them.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "update",
+            "update_all",
+            "pre",
+            "features",
+            "all_features",
+            "no_hashes",
+            "constraint",
+        ]
+    )]
+    no_lock: bool,
+    /// Only install the dependencies but not the project itself.
+    #[arg(long)]
+    no_install_project: bool,
+    /// Install the project regularly instead of editable.
+    ///
+    /// This can be made the default with `tool.rye.editable = false`.
+    #[arg(long)]
+    no_editable: bool,
+    /// Set the prompt of an existing virtualenv to the project name.
+    ///
+    /// New virtualenvs always get this prompt.
+    #[arg(long)]
+    update_prompt: bool,
+    /// Fail if a package could be installed from another package source than
+    /// the one it was locked from.
+    #[arg(long)]
+    strict_sources: bool,
+    /// Do not run the post-sync hooks (`tool.rye.hooks.post-sync`).
+    #[arg(long)]
+    no_hooks: bool,
 }
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
     let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
+    if cmd.offline {
+        set_offline();
+    }
     sync(SyncOptions {
         output,
         dev: !cmd.no_dev,
@@ -46,7 +122,22 @@ pub fn execute(cmd: Args) -> Result<(), Error> {
             update: cmd.update,
             update_all: cmd.update_all,
             pre: cmd.pre,
+            dry_run: cmd.dry_run,
+            features: cmd.features,
+            all_features: cmd.all_features,
+            no_hashes: cmd.no_hashes,
+            constraints: cmd.constraint,
+            platform: cmd.platform,
+            backend: cmd.backend,
+            ..LockOptions::default()
         },
+        pyproject: None,
+        no_lock: cmd.no_lock,
+        no_install_project: cmd.no_install_project,
+        no_editable: cmd.no_editable,
+        update_prompt: cmd.update_prompt,
+        strict_sources: cmd.strict_sources,
+        no_hooks: cmd.no_hooks,
     })?;
     Ok(())
 }
@@ -0,0 +1,642 @@
+use std::ffi::OsString;
+use std::fs;
+
+use anyhow::{bail, Context, Error};
+use clap::Parser;
+use console::style;
+use indicatif::HumanBytes;
+use pep508_rs::Requirement;
+
+use crate::cache::{clear_pip_cache, evict_unused_overlays, evict_unused_wheels};
+use crate::config::get_pip_cache_dir;
+use crate::installer::{reinstall, upgrade, UpgradeOutcome};
+use crate::sources::PythonVersionRequest;
+use crate::tools::{
+    check_shims, create_shim, did_you_mean, find_dangling_shims, find_tool_script,
+    get_installed_tool, get_shims_dir, list_installed_tools, read_shim_target, script_matches,
+    ShimProblem,
+};
+#[cfg(not(target_os = "windows"))]
+use crate::utils::exec_args;
+use crate::utils::CommandOutput;
+#[cfg(target_os = "windows")]
+use crate::utils::QuietExit;
+
+/// Helper utility to manage global tools.
+#[derive(Parser, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: SubCommand,
+}
+
+/// List all installed tools
+#[derive(Parser, Debug)]
+pub struct ListCommand {
+    /// Also show all the scripts installed by the tools
+    #[arg(long)]
+    include_scripts: bool,
+    /// Also show the Python version of the tools
+    #[arg(long)]
+    include_python: bool,
+    /// Also show the disk usage of the tools
+    #[arg(long)]
+    sizes: bool,
+}
+
+/// Upgrades installed tools.
+#[derive(Parser, Debug)]
+pub struct UpgradeCommand {
+    /// The tool to upgrade, optionally with a new version specifier.
+    /// e.g. 'black' or 'black>=23'
+    #[arg(required_unless_present = "all")]
+    name: Option<String>,
+    /// Upgrade all installed tools.
+    #[arg(long, conflicts_with = "name")]
+    all: bool,
+    /// Rebuild the tool on another Python version.
+    #[arg(short, long)]
+    python: Option<String>,
+    /// Include pre-releases and keep tracking them.
+    #[arg(long)]
+    pre: bool,
+    /// Only show what would be upgraded.
+    #[arg(long)]
+    dry_run: bool,
+    /// Enables verbose diagnostics.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Turns off all output.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+/// Shows details about an installed tool.
+#[derive(Parser, Debug)]
+pub struct ShowCommand {
+    /// The name of the tool.
+    name: String,
+}
+
+/// Rebuilds the virtualenvs of all tools, e.g. after a toolchain change.
+#[derive(Parser, Debug)]
+pub struct ReinstallAllCommand {
+    /// Only rebuild tools whose interpreter or toolchain is missing.
+    #[arg(long)]
+    only_broken: bool,
+    /// Enables verbose diagnostics.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Turns off all output.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+/// Removes leftovers of aborted installations and reports disk usage.
+///
+/// This removes shims that point to tools that no longer exist, virtualenvs
+/// of installations that never finished, wheels in the shared cache that
+/// no tool uses anymore, ephemeral environments of `rye run --with` that
+/// were not used for a week and the pip cache.
+#[derive(Parser, Debug)]
+pub struct GcCommand {
+    /// Only show what would be removed.
+    #[arg(long)]
+    dry_run: bool,
+    /// Also clear the pip cache of rye.
+    ///
+    /// A `PIP_CACHE_DIR` from the environment is never cleared.
+    #[arg(long)]
+    pip_cache: bool,
+    /// Turns off all output.
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// Verifies that all shims resolve to installed tools.
+#[derive(Parser, Debug)]
+pub struct CheckCommand {
+    /// Recreate missing shims and remove shims of missing scripts.
+    #[arg(long)]
+    fix: bool,
+}
+
+/// Runs a script of an installed tool without going through the shims.
+#[derive(Parser, Debug)]
+pub struct RunCommand {
+    /// The name of the script (or tool) to run.
+    name: String,
+    /// The arguments to pass to the script.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<OsString>,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    List(ListCommand),
+    Show(ShowCommand),
+    Upgrade(UpgradeCommand),
+    ReinstallAll(ReinstallAllCommand),
+    Gc(GcCommand),
+    Check(CheckCommand),
+    Run(RunCommand),
+}
+
+pub fn execute(cmd: Args) -> Result<(), Error> {
+    match cmd.command {
+        SubCommand::List(args) => list(args),
+        SubCommand::Show(args) => show(args),
+        SubCommand::Upgrade(args) => upgrade_tool(args),
+        SubCommand::ReinstallAll(args) => reinstall_all(args),
+        SubCommand::Gc(args) => gc(args),
+        SubCommand::Check(args) => check(args),
+        SubCommand::Run(args) => run(args),
+    }
+}
+
+fn list(cmd: ListCommand) -> Result<(), Error> {
+    let shim_dir = get_shims_dir()?;
+
+    for tool in list_installed_tools()? {
+        let mut name = tool.name().to_string();
+        let receipt = tool.receipt();
+        if let Some(ref receipt) = receipt {
+            let extras = receipt.extras();
+            if !extras.is_empty() {
+                name = format!("{}[{}]", name, extras.join(","));
+            }
+        }
+        let editable_path = receipt.as_ref().and_then(|x| x.editable_path());
+
+        let mut line = style(name).cyan().to_string();
+        if tool.is_incomplete() {
+            line = format!("{} {}", line, style("(incomplete installation)").red());
+        } else if tool.is_broken() {
+            line = format!("{} {}", line, style("(broken: interpreter missing)").red());
+        } else {
+            let version = match tool.version() {
+                Some(version) => version,
+                None => style("(unknown version)").dim().to_string(),
+            };
+            line = format!("{} {}", line, version);
+            if let Some(path) = editable_path {
+                line = format!(
+                    "{} {}",
+                    line,
+                    style(format!("(editable: {})", path.display())).dim()
+                );
+            }
+        }
+        if cmd.include_python {
+            let python = receipt
+                .as_ref()
+                .and_then(|x| x.python.as_deref())
+                .unwrap_or("unknown python");
+            line = format!("{} {}", line, style(format!("[{}]", python)).dim());
+        }
+        if cmd.sizes {
+            line = format!("{} {}", line, HumanBytes(tool.disk_usage()));
+        }
+        println!("{}", line);
+
+        if cmd.include_scripts {
+            for shim in tool.list_shims(&shim_dir)? {
+                if let Some(name) = shim.file_name() {
+                    println!("  {}", name.to_string_lossy());
+                }
+            }
+        }
+    }
+
+    let problems = check_shims()?.len();
+    if problems > 0 {
+        eprintln!(
+            "{} found {} problem(s) with shims, run `rye tools check` for details",
+            style("warning:").yellow(),
+            problems
+        );
+    }
+
+    Ok(())
+}
+
+fn show(cmd: ShowCommand) -> Result<(), Error> {
+    let shim_dir = get_shims_dir()?;
+    let tool = match get_installed_tool(&cmd.name)? {
+        Some(tool) => tool,
+        None => bail!(
+            "tool {} is not installed{} (use `rye install {}` to install it)",
+            cmd.name,
+            did_you_mean(&cmd.name)?,
+            cmd.name
+        ),
+    };
+    let receipt = tool.receipt();
+
+    println!("{}", style(tool.name()).cyan());
+    println!("  venv: {}", tool.venv_path().display());
+    match receipt.as_ref().and_then(|x| x.python.as_deref()) {
+        Some(python) => println!("  python: {}", python),
+        None => println!("  python: {}", style("unknown").dim()),
+    }
+    if tool.is_broken() {
+        println!(
+            "  version: {}",
+            style("(broken: interpreter missing)").red()
+        );
+    } else {
+        match tool.version() {
+            Some(version) => println!("  version: {}", version),
+            None => println!("  version: {}", style("unknown").dim()),
+        }
+    }
+    match receipt {
+        Some(ref receipt) => {
+            println!("  requirement: {}", receipt.requirement);
+            if let Some(path) = receipt.editable_path() {
+                println!("  editable: {}", path.display());
+            }
+            if !receipt.pip_args.is_empty() {
+                println!("  pip args: {}", receipt.pip_args.join(" "));
+            }
+        }
+        None => println!("  requirement: {}", style("unknown").dim()),
+    }
+
+    // the receipt is the source of truth for which shims should exist,
+    // older installations only have the shims that are still linked.
+    let shims = match receipt {
+        Some(ref receipt) if !receipt.shims.is_empty() => {
+            receipt.shims.iter().map(|x| shim_dir.join(x)).collect()
+        }
+        _ => tool.list_shims(&shim_dir)?,
+    };
+    println!("  shims:");
+    for shim in shims {
+        let mut name = shim
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if let Some((original, _)) = receipt.as_ref().and_then(|x| {
+            x.script_aliases
+                .iter()
+                .find(|(_, alias)| script_matches(&shim, alias))
+        }) {
+            name = format!("{} (alias of {})", name, original);
+        }
+        match read_shim_target(&shim) {
+            Some(target) if target.strip_prefix(tool.venv_path()).is_err() => println!(
+                "    {} {}",
+                name,
+                style(format!("(repointed to {})", target.display())).red()
+            ),
+            Some(target) if !target.is_file() => println!(
+                "    {} -> {} {}",
+                name,
+                target.display(),
+                style("(target missing)").red()
+            ),
+            Some(target) => println!("    {} -> {}", name, target.display()),
+            None => println!("    {} {}", name, style("(missing)").red()),
+        }
+    }
+
+    Ok(())
+}
+
+fn upgrade_tool(cmd: UpgradeCommand) -> Result<(), Error> {
+    let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
+
+    let python: Option<PythonVersionRequest> = match cmd.python {
+        Some(ref py) => Some(py.parse()?),
+        None => None,
+    };
+
+    if cmd.all {
+        return upgrade_all(python.as_ref(), cmd.pre, cmd.dry_run, output);
+    }
+
+    let requirement: Requirement = cmd.name.as_deref().unwrap_or_default().parse()?;
+    let name = requirement.name.clone();
+    let requirement = if requirement.version_or_url.is_some() || requirement.extras.is_some() {
+        Some(requirement)
+    } else {
+        None
+    };
+    let outcome = upgrade(
+        &name,
+        requirement,
+        python.as_ref(),
+        cmd.pre,
+        cmd.dry_run,
+        output,
+    )?;
+    if output != CommandOutput::Quiet {
+        print_outcome(&outcome, cmd.dry_run);
+    }
+    Ok(())
+}
+
+fn upgrade_all(
+    python: Option<&PythonVersionRequest>,
+    pre: bool,
+    dry_run: bool,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    let mut outcomes = Vec::new();
+    let mut failed = Vec::new();
+
+    for tool in list_installed_tools()? {
+        if output != CommandOutput::Quiet {
+            eprintln!("Checking {}", style(tool.name()).cyan());
+        }
+        match upgrade(tool.name(), None, python, pre, dry_run, output) {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(err) => failed.push((tool.name().to_string(), err)),
+        }
+    }
+
+    if output != CommandOutput::Quiet {
+        if !outcomes.is_empty() {
+            eprintln!();
+        }
+        for outcome in &outcomes {
+            print_outcome(outcome, dry_run);
+        }
+    }
+    for (name, err) in &failed {
+        eprintln!("{} {}: {}", style("failed:").red(), name, err);
+    }
+    if !failed.is_empty() {
+        bail!("failed to upgrade {} tool(s)", failed.len());
+    }
+
+    Ok(())
+}
+
+fn print_outcome(outcome: &UpgradeOutcome, dry_run: bool) {
+    let old = outcome.old_version.as_deref().unwrap_or("unknown");
+    match outcome.new_version {
+        Some(_) if !outcome.changed() => println!(
+            "{} {} {}",
+            style(&outcome.name).cyan(),
+            old,
+            style("(up to date)").dim()
+        ),
+        Some(ref new) => println!(
+            "{} {} -> {}{}",
+            style(&outcome.name).cyan(),
+            old,
+            style(new).green(),
+            if dry_run { " (dry run)" } else { "" }
+        ),
+        None if dry_run => println!(
+            "{} {} {}",
+            style(&outcome.name).cyan(),
+            old,
+            style("(would reinstall from source)").dim()
+        ),
+        None => println!("{} {}", style(&outcome.name).cyan(), old),
+    }
+}
+
+fn reinstall_all(cmd: ReinstallAllCommand) -> Result<(), Error> {
+    let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
+    let mut rebuilt = Vec::new();
+    let mut untouched = Vec::new();
+    let mut failed = Vec::new();
+
+    for tool in list_installed_tools()? {
+        if cmd.only_broken && !tool.needs_rebuild() {
+            untouched.push(tool.name().to_string());
+            continue;
+        }
+        if output != CommandOutput::Quiet {
+            eprintln!("Rebuilding {}", style(tool.name()).cyan());
+        }
+        match reinstall(&tool, output) {
+            Ok(()) => rebuilt.push(tool.name().to_string()),
+            Err(err) => failed.push((tool.name().to_string(), err)),
+        }
+    }
+
+    if output != CommandOutput::Quiet {
+        for name in &rebuilt {
+            println!("{} {}", style(name).cyan(), style("(rebuilt)").green());
+        }
+        for name in &untouched {
+            println!("{} {}", style(name).cyan(), style("(untouched)").dim());
+        }
+    }
+    for (name, err) in &failed {
+        eprintln!("{} {}: {:#}", style("failed:").red(), name, err);
+    }
+    if !failed.is_empty() {
+        bail!("failed to rebuild {} tool(s)", failed.len());
+    }
+
+    Ok(())
+}
+
+fn gc(cmd: GcCommand) -> Result<(), Error> {
+    let shim_dir = get_shims_dir()?;
+    let action = if cmd.dry_run {
+        "would remove"
+    } else {
+        "removed"
+    };
+    let mut reclaimed = 0;
+
+    for tool in list_installed_tools()? {
+        let size = tool.disk_usage();
+        if !tool.is_leftover(&shim_dir)? {
+            if !cmd.quiet {
+                println!("{} {}", style(tool.name()).cyan(), HumanBytes(size));
+            }
+            continue;
+        }
+        if !cmd.dry_run {
+            fs::remove_dir_all(tool.venv_path())
+                .with_context(|| format!("unable to remove {}", tool.venv_path().display()))?;
+        }
+        reclaimed += size;
+        if !cmd.quiet {
+            println!(
+                "{} {} {}",
+                action,
+                style(tool.venv_path().display()).cyan(),
+                style(format!("({}, aborted installation)", HumanBytes(size))).dim()
+            );
+        }
+    }
+
+    // run after removing virtualenvs so that their shims are included
+    for shim in find_dangling_shims()? {
+        if !cmd.dry_run {
+            fs::remove_file(&shim)
+                .with_context(|| format!("unable to remove shim {}", shim.display()))?;
+        }
+        if !cmd.quiet {
+            println!("{} {}", action, style(shim.display()).cyan());
+        }
+    }
+
+    let (evicted, size) = evict_unused_wheels(cmd.dry_run)?;
+    if !cmd.quiet && evicted > 0 {
+        println!(
+            "{} {} cached wheel(s) no tool uses anymore {}",
+            action,
+            evicted,
+            style(format!("({})", HumanBytes(size))).dim()
+        );
+    }
+    reclaimed += size;
+
+    let (evicted, size) = evict_unused_overlays(cmd.dry_run)?;
+    if !cmd.quiet && evicted > 0 {
+        println!(
+            "{} {} ephemeral environment(s) of `rye run --with` {}",
+            action,
+            evicted,
+            style(format!("({})", HumanBytes(size))).dim()
+        );
+    }
+    reclaimed += size;
+
+    // only the cache rye owns, projects share it too
+    if cmd.pip_cache {
+        let cache_dir = get_pip_cache_dir()?;
+        let size = clear_pip_cache(&cache_dir, cmd.dry_run)?;
+        if !cmd.quiet && size > 0 {
+            println!(
+                "{} pip cache {} {}",
+                action,
+                style(cache_dir.display()).cyan(),
+                style(format!("({})", HumanBytes(size))).dim()
+            );
+        }
+        reclaimed += size;
+    }
+
+    if !cmd.quiet && reclaimed > 0 {
+        println!(
+            "{} {}",
+            if cmd.dry_run {
+                "would reclaim"
+            } else {
+                "reclaimed"
+            },
+            HumanBytes(reclaimed)
+        );
+    }
+    Ok(())
+}
+
+fn run(cmd: RunCommand) -> Result<(), Error> {
+    let script = match find_tool_script(&cmd.name)? {
+        Some(script) => script,
+        None if get_installed_tool(&cmd.name)?.is_some() => {
+            bail!("tool {} has no script named {}", cmd.name, cmd.name)
+        }
+        None => bail!(
+            "{} is not installed{}. Install it with `rye install {}`",
+            cmd.name,
+            did_you_mean(&cmd.name)?,
+            cmd.name
+        ),
+    };
+
+    // replace the process so that exit codes and signals pass through
+    #[cfg(not(target_os = "windows"))]
+    {
+        let args = exec_args(
+            &Some(script.as_os_str().to_owned())
+                .into_iter()
+                .chain(cmd.args)
+                .collect::<Vec<_>>(),
+        )?;
+        let Err(err) = nix::unistd::execv(&args[0], &args);
+        Err(Error::new(err).context(format!("unable to run {}", script.display())))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let status = std::process::Command::new(&script)
+            .args(&cmd.args)
+            .status()
+            .with_context(|| format!("unable to run {}", script.display()))?;
+        match status.code() {
+            Some(0) => Ok(()),
+            code => Err(QuietExit(code.unwrap_or(1)).into()),
+        }
+    }
+}
+
+fn check(cmd: CheckCommand) -> Result<(), Error> {
+    let mut unresolved = 0;
+
+    for problem in check_shims()? {
+        match problem {
+            ShimProblem::MissingTarget { shim, target } => {
+                let mut status = "";
+                if cmd.fix {
+                    fs::remove_file(&shim)
+                        .with_context(|| format!("unable to remove shim {}", shim.display()))?;
+                    status = " (removed)";
+                } else {
+                    unresolved += 1;
+                }
+                println!(
+                    "{} {} -> {}{}",
+                    style("missing target:").red(),
+                    shim.display(),
+                    target.display(),
+                    status
+                );
+            }
+            ShimProblem::MissingShim { shim, tool, script } => {
+                let status = match script {
+                    Some(ref script) if cmd.fix => {
+                        create_shim(script, &shim)?;
+                        " (recreated)".to_string()
+                    }
+                    Some(_) => {
+                        unresolved += 1;
+                        "".to_string()
+                    }
+                    None => {
+                        unresolved += 1;
+                        format!(" (script is gone, use `rye install --force {}`)", tool)
+                    }
+                };
+                println!(
+                    "{} {} of {}{}",
+                    style("missing shim:").red(),
+                    shim.display(),
+                    tool,
+                    status
+                );
+            }
+            ShimProblem::ForeignShim { shim, target } => {
+                println!(
+                    "{} {} -> {}",
+                    style("foreign shim:").yellow(),
+                    shim.display(),
+                    target.display()
+                );
+            }
+        }
+    }
+
+    if unresolved > 0 {
+        if cmd.fix {
+            bail!("unable to fix {} shim problem(s)", unresolved);
+        } else {
+            bail!(
+                "found {} shim problem(s), use --fix to repair them",
+                unresolved
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,14 +1,26 @@
-use anyhow::Error;
+use anyhow::{bail, Error};
 use clap::Parser;
 
-use crate::installer::uninstall;
-use crate::utils::CommandOutput;
+use crate::installer::{uninstall, uninstall_all};
+use crate::tools::list_installed_tools;
+use crate::utils::{confirm, CommandOutput};
 
 /// Uninstalls a global tool.
 #[derive(Parser, Debug)]
 pub struct Args {
     /// The package to uninstall
-    name: String,
+    #[arg(required_unless_present = "all")]
+    name: Option<String>,
+    /// Uninstall all tools and remove dangling shims.
+    #[arg(long, conflicts_with = "name")]
+    all: bool,
+    /// Only show what would be removed.  Exits with 2 if the package is
+    /// not installed.
+    #[arg(long, conflicts_with = "all")]
+    dry_run: bool,
+    /// Do not ask for confirmation.
+    #[arg(short, long)]
+    yes: bool,
     /// Enables verbose diagnostics.
     #[arg(short, long)]
     verbose: bool,
@@ -19,6 +31,15 @@ pub struct Args {
 
 pub fn execute(cmd: Args) -> Result<(), Error> {
     let output = CommandOutput::from_quiet_and_verbose(cmd.quiet, cmd.verbose);
-    uninstall(&cmd.name, output)?;
+
+    if cmd.all {
+        let count = list_installed_tools()?.len();
+        if count > 0 && !cmd.yes && !confirm(&format!("Uninstall all {} tool(s)?", count), false)? {
+            bail!("aborted (pass --yes to skip the confirmation)");
+        }
+        uninstall_all(output)?;
+    } else {
+        uninstall(cmd.name.as_deref().unwrap_or_default(), cmd.dry_run, output)?;
+    }
     Ok(())
 }
@@ -0,0 +1,100 @@
+use anyhow::{bail, Error};
+use clap::{Parser, ValueEnum};
+use pep440_rs::Version;
+
+use crate::pyproject::{write_version_file, PyProject, VersionSource};
+
+/// Prints or bumps the version of the project.
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Bump the version and write it to the pyproject.toml or version file.
+    #[arg(short, long, value_enum)]
+    bump: Option<Bump>,
+}
+
+/// The part of a version to bump.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+}
+
+pub fn execute(cmd: Args) -> Result<(), Error> {
+    let mut pyproject = PyProject::discover()?;
+    let version = pyproject.version()?;
+    match cmd.bump {
+        Some(bump) => {
+            let new_version = bump_version(&version, bump)?;
+            match pyproject.version_source()? {
+                VersionSource::Static => {
+                    pyproject.set_version(&new_version);
+                    pyproject.save()?;
+                }
+                VersionSource::File(path) => write_version_file(&path, &new_version)?,
+                VersionSource::Git => bail!(
+                    "the version of the project comes from git tags, tag a commit to change it:\n  \
+                     git tag v{}",
+                    new_version
+                ),
+            }
+            println!("{} → {}", version, new_version);
+        }
+        None => println!("{}", version),
+    }
+    Ok(())
+}
+
+/// Bumps a part of a version.
+///
+/// Pre-releases and development releases of a version become the version
+/// itself if it is what the bump would reach, for instance `1.2.3rc1` turns
+/// into `1.2.3` with a patch bump.  The lower parts are reset and the
+/// suffixes and local labels dropped.
+fn bump_version(version: &Version, bump: Bump) -> Result<Version, Error> {
+    if version.release.is_empty() {
+        bail!("version {} has no release segment", version);
+    }
+    let idx = match bump {
+        Bump::Major => 0,
+        Bump::Minor => 1,
+        Bump::Patch => 2,
+    };
+    let mut release = version.release.clone();
+    if release.len() <= idx {
+        release.resize(idx + 1, 0);
+    }
+    let pre_release = version.pre.is_some() || (version.dev.is_some() && version.post.is_none());
+    if !pre_release || release[idx + 1..].iter().any(|&x| x != 0) {
+        release[idx] += 1;
+    }
+    for part in &mut release[idx + 1..] {
+        *part = 0;
+    }
+    Ok(Version {
+        epoch: version.epoch,
+        ..Version::from_release(release)
+    })
+}
+
+#[test]
+fn test_bump_version() {
+    use std::str::FromStr;
+
+    let bump = |version: &str, bump| {
+        bump_version(&Version::from_str(version).unwrap(), bump)
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(bump("1.2.3", Bump::Patch), "1.2.4");
+    assert_eq!(bump("1.2.3", Bump::Minor), "1.3.0");
+    assert_eq!(bump("1.2.3", Bump::Major), "2.0.0");
+    assert_eq!(bump("1.2", Bump::Patch), "1.2.1");
+    assert_eq!(bump("1.2.3rc1", Bump::Patch), "1.2.3");
+    assert_eq!(bump("1.2.3rc1", Bump::Minor), "1.3.0");
+    assert_eq!(bump("1.3.0b2", Bump::Minor), "1.3.0");
+    assert_eq!(bump("2.0.0.dev4", Bump::Major), "2.0.0");
+    assert_eq!(bump("1.2.3.post1", Bump::Patch), "1.2.4");
+    assert_eq!(bump("1.2.3.post1.dev1", Bump::Patch), "1.2.4");
+    assert_eq!(bump("1!1.2.3+local", Bump::Patch), "1!1.2.4");
+}
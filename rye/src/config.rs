@@ -3,10 +3,11 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::{env, fs};
 
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, Context, Error};
 use once_cell::sync::Lazy;
+use toml_edit::Document;
 
-use crate::sources::{get_download_url, PythonVersion, PythonVersionRequest};
+use crate::sources::{get_download_url, matches_version, PythonVersion, PythonVersionRequest};
 
 static APP_DIR: Lazy<Option<PathBuf>> =
     Lazy::new(|| simple_home_dir::home_dir().map(|x| x.join(".rye")));
@@ -18,6 +19,128 @@ pub fn get_app_dir() -> Result<&'static Path, Error> {
         .ok_or_else(|| anyhow!("cannot determine app directory"))
 }
 
+/// Loads the global configuration from `config.toml` in the app directory.
+///
+/// A missing config file is treated like an empty one.
+pub fn load_config() -> Result<Document, Error> {
+    let path = get_app_dir()?.join("config.toml");
+    match fs::read_to_string(&path) {
+        Ok(contents) => contents
+            .parse()
+            .with_context(|| format!("failed to parse {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Document::new()),
+        Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Returns the default package indexes from the config.
+///
+/// These are configured in the `[install]` section as `index-url` and
+/// `extra-index-url` (a list).
+pub fn get_default_indexes() -> Result<(Option<String>, Vec<String>), Error> {
+    let config = load_config()?;
+    let install = config.get("install");
+    let index_url = install
+        .and_then(|x| x.get("index-url"))
+        .and_then(|x| x.as_str())
+        .map(|x| x.to_string());
+    let extra_index_urls = install
+        .and_then(|x| x.get("extra-index-url"))
+        .and_then(|x| x.as_array())
+        .map(|x| {
+            x.iter()
+                .filter_map(|x| x.as_str())
+                .map(|x| x.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((index_url, extra_index_urls))
+}
+
+/// Returns the default wheelhouse from the config.
+///
+/// This is a local folder of wheels configured as `wheelhouse` in the
+/// `[install]` section which is used as default for `--find-links`.
+pub fn get_default_wheelhouse() -> Result<Option<PathBuf>, Error> {
+    Ok(load_config()?
+        .get("install")
+        .and_then(|x| x.get("wheelhouse"))
+        .and_then(|x| x.as_str())
+        .map(PathBuf::from))
+}
+
+/// Returns if generated lockfiles should include hashes.
+///
+/// This is enabled by default and can be turned off with `generate-hashes`
+/// in the `[lock]` section for indexes that do not expose digests.
+pub fn get_generate_hashes() -> Result<bool, Error> {
+    Ok(load_config()?
+        .get("lock")
+        .and_then(|x| x.get("generate-hashes"))
+        .and_then(|x| x.as_bool())
+        .unwrap_or(true))
+}
+
+/// Returns if uv should be used to resolve and install packages.
+///
+/// This is configured as `use-uv` in the `[behavior]` section.
+pub fn get_use_uv() -> Result<bool, Error> {
+    Ok(load_config()?
+        .get("behavior")
+        .and_then(|x| x.get("use-uv"))
+        .and_then(|x| x.as_bool())
+        .unwrap_or(false))
+}
+
+/// Checks if virtualenvs are synced automatically when dependencies change.
+pub fn get_autosync() -> Result<bool, Error> {
+    Ok(load_config()?
+        .get("behavior")
+        .and_then(|x| x.get("autosync"))
+        .and_then(|x| x.as_bool())
+        .unwrap_or(true))
+}
+
+/// Returns the names of the files or folders that mark the folders where
+/// the search for projects stops.
+///
+/// These are `.git` and `.hg` and the ones configured with
+/// `discovery-boundaries` in the `[behavior]` section.  Setting
+/// `RYE_NO_DISCOVERY_BOUNDARY` turns them off.
+pub fn get_discovery_boundaries() -> Vec<String> {
+    if env::var_os("RYE_NO_DISCOVERY_BOUNDARY").is_some_and(|x| !x.is_empty() && x != "0") {
+        return Vec::new();
+    }
+    let mut rv = vec![".git".to_string(), ".hg".to_string()];
+    if let Ok(config) = load_config() {
+        rv.extend(
+            config
+                .get("behavior")
+                .and_then(|x| x.get("discovery-boundaries"))
+                .and_then(|x| x.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|x| x.as_str().map(|x| x.to_string())),
+        );
+    }
+    rv
+}
+
+/// Returns the folder pip caches downloads and built wheels in.
+///
+/// This is `pip-cache` in the app directory unless configured as
+/// `pip-cache-dir` in the `[behavior]` section.  Relative paths are
+/// resolved against the app directory.
+pub fn get_pip_cache_dir() -> Result<PathBuf, Error> {
+    let app_dir = get_app_dir()?;
+    Ok(load_config()?
+        .get("behavior")
+        .and_then(|x| x.get("pip-cache-dir"))
+        .and_then(|x| x.as_str())
+        .map(|x| app_dir.join(x))
+        .unwrap_or_else(|| app_dir.join("pip-cache")))
+}
+
 /// Returns the cache directory for a particular python version that can be downloaded.
 pub fn get_canonical_py_path(version: &PythonVersion) -> Result<PathBuf, Error> {
     let mut rv = get_app_dir()?.to_path_buf();
@@ -108,10 +231,10 @@ pub fn get_default_author() -> Option<(String, String)> {
 
     for line in std::str::from_utf8(&rv.stdout).ok()?.lines() {
         match line.split_once(' ') {
-            Some((key, value)) if key == "user.email" => {
+            Some(("user.email", value)) => {
                 email = Some(value.to_string());
             }
-            Some((key, value)) if key == "user.name" => {
+            Some(("user.name", value)) => {
                 name = Some(value.to_string());
             }
             _ => {}
@@ -121,21 +244,88 @@ pub fn get_default_author() -> Option<(String, String)> {
     Some((name?, email.unwrap_or_else(|| "".into())))
 }
 
-/// Reads the current `.python-version` file.
-pub fn load_python_version() -> Option<PythonVersion> {
-    let mut here = env::current_dir().ok()?;
+/// Reads the `.python-version` file that applies to a folder.
+///
+/// Files that cannot be understood are ignored.  Use
+/// [`load_python_version_file`] to report those.
+pub fn load_python_version_from(path: &Path) -> Option<PythonVersion> {
+    let ver_file = find_python_version_file(path)?;
+    load_python_version_file(&ver_file).ok()
+}
+
+/// Reads a `.python-version` file and resolves it to a concrete version.
+///
+/// Like with pyenv the first line that is not empty or a comment holds the
+/// version.  It can be a version request such as `3.11` in which case the
+/// latest matching toolchain that is registered, or otherwise the latest one
+/// that can be downloaded, is picked.
+pub fn load_python_version_file(path: &Path) -> Result<PythonVersion, Error> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let line = contents
+        .lines()
+        .map(|x| x.trim())
+        .find(|x| !x.is_empty() && !x.starts_with('#'))
+        .ok_or_else(|| anyhow!("{} does not contain a Python version", path.display()))?;
+    let req: PythonVersionRequest = line
+        .parse()
+        .with_context(|| format!("invalid Python version '{}' in {}", line, path.display()))?;
+    resolve_python_version(&req).ok_or_else(|| {
+        anyhow!(
+            "Python version '{}' pinned in {} is not available",
+            req,
+            path.display()
+        )
+    })
+}
+
+/// Resolves a version request to a concrete version.
+fn resolve_python_version(req: &PythonVersionRequest) -> Option<PythonVersion> {
+    if let Ok(ver) = PythonVersion::try_from(req.clone()) {
+        return Some(ver);
+    }
+    let mut known = list_known_toolchains().unwrap_or_default();
+    known.retain(|x| matches_version(req, x));
+    known.sort();
+    known
+        .pop()
+        .or_else(|| get_download_url(req, OS, ARCH).map(|(version, _)| version))
+}
+
+/// Finds the `.python-version` file that applies to a folder.
+///
+/// This is the one in the folder or the closest one of its parents.  Like
+/// project discovery the search ends with the root of a repository.
+pub fn find_python_version_file(path: &Path) -> Option<PathBuf> {
+    let boundaries = get_discovery_boundaries();
+    let mut here = path.to_path_buf();
 
     loop {
         let ver_file = here.join(".python-version");
-        if let Ok(contents) = fs::read_to_string(&ver_file) {
-            let ver = contents.trim().parse().ok()?;
-            return Some(ver);
+        if ver_file.is_file() {
+            return Some(ver_file);
         }
 
-        if !here.pop() {
+        if boundaries.iter().any(|x| here.join(x).exists()) || !here.pop() {
             break;
         }
     }
 
     None
 }
+
+#[test]
+fn test_load_python_version_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".python-version");
+
+    fs::write(&path, "# pinned for CI\n\ncpython@3.11.7\n3.10.1\n").unwrap();
+    assert_eq!(
+        load_python_version_file(&path).unwrap().to_string(),
+        "cpython@3.11.7"
+    );
+    fs::write(&path, "system\n").unwrap();
+    assert!(load_python_version_file(&path).is_err());
+    fs::write(&path, "\n").unwrap();
+    assert!(load_python_version_file(&path).is_err());
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Error};
+use curl::easy::{Easy, List};
+use once_cell::sync::Lazy;
+use pep440_rs::Version;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::auth::get_index_credential;
+use crate::pyproject::normalize_package_name;
+use crate::utils::{expand_env_vars, CommandOutput};
+
+static LINK_TEXT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<a\b[^>]*>([^<]+)</a>").unwrap());
+
+/// The simple API of PyPI which is used unless a default source is set.
+pub const PYPI_SIMPLE_URL: &str = "https://pypi.org/simple/";
+
+#[derive(Deserialize)]
+struct ProjectPage {
+    files: Vec<ProjectFile>,
+}
+
+#[derive(Deserialize)]
+struct ProjectFile {
+    filename: String,
+}
+
+/// Returns the normalized name and version of a distribution file.
+pub fn parse_dist_filename(filename: &str) -> Option<(String, String)> {
+    let dist = if let Some(stem) = filename.strip_suffix(".whl") {
+        let mut parts = stem.splitn(3, '-');
+        parts.next().zip(parts.next())
+    } else {
+        [".tar.gz", ".zip", ".tar.bz2"]
+            .iter()
+            .find_map(|ext| filename.strip_suffix(ext))
+            .and_then(|stem| stem.rsplit_once('-'))
+    };
+    dist.map(|(name, version)| (normalize_package_name(name), version.to_string()))
+}
+
+/// Lists the versions of a package that an index offers distributions of.
+///
+/// This uses the simple repository API of the index.  A package the index
+/// does not know has no versions.
+pub fn list_index_versions(
+    index_url: &str,
+    package: &str,
+    output: CommandOutput,
+) -> Result<BTreeSet<String>, Error> {
+    let package = normalize_package_name(package);
+    let url = format!(
+        "{}/{}/",
+        expand_env_vars(index_url).trim_end_matches('/'),
+        package
+    );
+    if output == CommandOutput::Verbose {
+        eprintln!("looking up {} on {}", package, index_url);
+    }
+
+    let mut handle = Easy::new();
+    handle.url(&url)?;
+    handle.follow_location(true)?;
+    handle.connect_timeout(Duration::from_secs(10))?;
+    let mut headers = List::new();
+    headers.append("Accept: application/vnd.pypi.simple.v1+json, text/html;q=0.1")?;
+    handle.http_headers(headers)?;
+    if let Some((username, password)) = get_index_credential(index_url, output)? {
+        handle.username(&username)?;
+        handle.password(&password)?;
+    }
+    let mut body = Vec::new();
+    {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer
+            .perform()
+            .with_context(|| format!("unable to look up {} on {}", package, index_url))?;
+    }
+    match handle.response_code()? {
+        200 => {}
+        404 => return Ok(BTreeSet::new()),
+        code => bail!(
+            "unable to look up {} on {} (status {})",
+            package,
+            index_url,
+            code
+        ),
+    }
+
+    let body = String::from_utf8_lossy(&body);
+    let filenames = match serde_json::from_str::<ProjectPage>(&body) {
+        Ok(page) => page.files.into_iter().map(|x| x.filename).collect(),
+        Err(_) => LINK_TEXT_RE
+            .captures_iter(&body)
+            .map(|x| x[1].trim().to_string())
+            .collect::<Vec<_>>(),
+    };
+    Ok(filenames
+        .iter()
+        .filter_map(|x| parse_dist_filename(x))
+        .filter(|(name, _)| *name == package)
+        .map(|(_, version)| version)
+        .collect())
+}
+
+/// Checks if a set of versions contains a version, ignoring differences in
+/// how the versions are spelled.
+pub fn has_version(versions: &BTreeSet<String>, version: &str) -> bool {
+    match Version::from_str(version) {
+        Ok(version) => versions
+            .iter()
+            .any(|x| Version::from_str(x).is_ok_and(|x| x == version)),
+        Err(_) => versions.contains(version),
+    }
+}
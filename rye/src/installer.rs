@@ -1,152 +1,1722 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::fs;
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::fs::symlink;
-#[cfg(target_os = "windows")]
-use std::os::windows::fs::symlink_file;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Context, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use console::style;
-use pep508_rs::Requirement;
+use pep508_rs::{Requirement, VersionOrUrl};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use url::Url;
 
-use crate::bootstrap::{ensure_self_venv, fetch};
-use crate::config::get_app_dir;
+use crate::auth::{get_configured_index_urls, get_public_index_url, IndexAuth};
+use crate::bootstrap::{ensure_self_venv, fetch, resolve_fetch};
+use crate::cache::{
+    can_link_wheel, get_wheel_cache_dir, link_wheel, unpack_wheel, use_pip_cache, InstallScheme,
+};
+use crate::config::{
+    get_app_dir, get_default_indexes, get_default_wheelhouse, get_py_bin, list_known_toolchains,
+};
 use crate::pyproject::normalize_package_name;
-use crate::sources::PythonVersionRequest;
+use crate::sources::{matches_version, PythonVersion, PythonVersionRequest};
 use crate::sync::create_virtualenv;
-use crate::utils::CommandOutput;
+use crate::tools::{
+    create_shim, did_you_mean, find_dangling_shims, get_installed_tool, get_shim_path,
+    get_shims_dir, get_tools_dir, list_installed_tools, read_shim_target, script_matches,
+    InstalledTool, ReplacedShim, ToolReceipt, TOOL_INCOMPLETE_MARKER,
+};
+use crate::utils::{
+    confirm, format_command, format_requirement, CommandOutput, Progress, QuietExit, VENV_BIN,
+};
 
 const FIND_SCRIPT_SCRIPT: &str = r#"
 import os
+import re
 import sys
-from importlib.metadata import distribution
+from importlib.metadata import PackageNotFoundError, distribution, distributions
 
-dist = distribution(sys.argv[1])
-for file in dist.files:
-    print(os.path.normpath(dist.locate_file(file)))
+def normalize(name):
+    return re.sub(r"[-_.]+", "-", name).lower()
+
+def extra_dependencies(dist, extras):
+    for req in dist.requires or ():
+        req, _, marker = req.partition(";")
+        m = re.search(r"extra\s*==\s*['\"]([^'\"]+)['\"]", marker)
+        if m is not None and normalize(m.group(1)) in extras:
+            # dependencies might be excluded by other markers
+            try:
+                yield distribution(re.match(r"\s*([A-Za-z0-9._-]+)", req).group(1))
+            except PackageNotFoundError:
+                pass
+
+name, extras, deps = sys.argv[1], sys.argv[2], sys.argv[3:]
+if deps == ["--all"]:
+    dists = list(distributions())
+else:
+    dist = distribution(name)
+    extras = set(normalize(x) for x in extras.split(",") if x)
+    dists = [dist] + list(extra_dependencies(dist, extras))
+    dists.extend(map(distribution, deps))
+for dist in dists:
+    # editable installs or broken RECORD files leave no file list behind
+    if dist.files is None:
+        print("!" + dist.metadata["Name"])
+        continue
+    for file in dist.files:
+        print(os.path.normpath(dist.locate_file(file)))
 "#;
 
+#[derive(Deserialize, Debug)]
+struct InstallReport {
+    install: Vec<InstallReportItem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstallReportItem {
+    metadata: InstallReportMetadata,
+    #[serde(default)]
+    download_info: Option<DownloadInfo>,
+    #[serde(default)]
+    is_direct: bool,
+    #[serde(default)]
+    requested: bool,
+}
+
+/// Where a distribution comes from, in the format of `direct_url.json`.
+#[derive(Serialize, Deserialize, Debug)]
+struct DownloadInfo {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive_info: Option<ArchiveInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchiveInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hashes: Option<BTreeMap<String, String>>,
+}
+
+impl DownloadInfo {
+    /// Returns the file name and sha256 hash if this is a wheel.
+    fn wheel(&self) -> Option<(String, &str)> {
+        let archive_info = self.archive_info.as_ref()?;
+        let hash = archive_info
+            .hashes
+            .as_ref()
+            .and_then(|x| x.get("sha256"))
+            .map(|x| x.as_str())
+            .or_else(|| archive_info.hash.as_deref()?.strip_prefix("sha256="))?;
+        let url = Url::parse(&self.url).ok()?;
+        let filename = percent_decode_str(url.path_segments()?.next_back()?)
+            .decode_utf8()
+            .ok()?
+            .into_owned();
+        filename.ends_with(".whl").then_some((filename, hash))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct InstallReportMetadata {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IndexMatch {
+    version: String,
+}
+
+/// Parses the requirement for a tool installation.
+///
+/// In addition to regular PEP 508 requirements this also accepts direct
+/// references such as `git+https://github.com/psf/black@main`.  As the name
+/// of the distribution is not known in that case, it's either taken from the
+/// `#egg=` fragment or determined by asking pip what would get installed.
+pub fn parse_tool_requirement(s: &str, output: CommandOutput) -> Result<Requirement, Error> {
+    let err = match s.parse::<Requirement>() {
+        Ok(requirement) => return Ok(requirement),
+        Err(err) => err,
+    };
+
+    // single letter schemes are windows drive letters
+    let url = match Url::parse(s) {
+        Ok(url) if url.scheme().len() > 1 => url,
+        _ => return Err(err.into()),
+    };
+    let name = match url
+        .fragment()
+        .and_then(|x| x.split('&').find_map(|x| x.strip_prefix("egg=")))
+    {
+        Some(name) => name.to_string(),
+        None => find_direct_reference_name(s, output)?,
+    };
+
+    format!("{} @ {}", name, s)
+        .parse()
+        .with_context(|| format!("invalid direct reference {}", s))
+}
+
+/// Translates the pipx style `name@version` shorthand into a requirement.
+///
+/// `name@latest` turns into an unconstrained requirement, which is signalled
+/// by returning `true` as the latest version should then be installed even if
+/// the tool is already there.  Anything else, including direct references,
+/// is returned unchanged.
+pub fn expand_version_shorthand(s: &str) -> (Cow<'_, str>, bool) {
+    let (name, version) = match s.split_once('@') {
+        Some(x) => x,
+        None => return (Cow::Borrowed(s), false),
+    };
+    let (bare_name, extras) = match name.split_once('[') {
+        Some((bare_name, extras)) => (bare_name, Some(extras)),
+        None => (name, None),
+    };
+    let is_name = !bare_name.is_empty()
+        && bare_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && extras.is_none_or(|x| x.ends_with(']') && !x[..x.len() - 1].contains(['[', ']']));
+    let is_version = !version.is_empty()
+        && !version.contains(|c: char| c.is_whitespace() || matches!(c, '/' | ':' | '@'));
+    if !is_name || !is_version {
+        (Cow::Borrowed(s), false)
+    } else if version == "latest" {
+        (Cow::Borrowed(name), true)
+    } else {
+        (Cow::Owned(format!("{}=={}", name, version)), false)
+    }
+}
+
+/// Parses a hash-locked requirements file.
+///
+/// Every requirement has to carry at least one `--hash`, otherwise an error
+/// listing all offending lines is returned.  Lines with pip options are
+/// skipped.
+pub fn parse_hashed_requirements(path: &Path) -> Result<Vec<Requirement>, Error> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("unable to read {}", path.display()))?;
+    let mut rv = Vec::new();
+    let mut errors = Vec::new();
+    let mut logical_line = String::new();
+    let mut start = 0;
+    for (idx, line) in contents.lines().enumerate() {
+        if logical_line.is_empty() {
+            start = idx + 1;
+        }
+        // continuation lines are common in files generated with hashes
+        if let Some(line) = line.strip_suffix('\\') {
+            logical_line.push_str(line);
+            logical_line.push(' ');
+            continue;
+        }
+        logical_line.push_str(line);
+        let line = std::mem::take(&mut logical_line);
+        let line = match line.find(" #") {
+            Some(idx) => &line[..idx],
+            None => &line,
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
+            continue;
+        }
+        let (requirement, hashes) = match line.find("--hash") {
+            Some(idx) => (&line[..idx], &line[idx..]),
+            None => (line, ""),
+        };
+        if !hashes.split_whitespace().any(|x| x.starts_with("--hash=")) {
+            errors.push(format!(
+                "line {}: '{}' has no hash",
+                start,
+                requirement.trim()
+            ));
+            continue;
+        }
+        match requirement.trim().parse::<Requirement>() {
+            Ok(requirement) => rv.push(requirement),
+            Err(err) => errors.push(format!("line {}: {}", start, err)),
+        }
+    }
+    if !errors.is_empty() {
+        bail!(
+            "{} is not fully hash-locked:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        );
+    }
+    Ok(rv)
+}
+
+/// Creates the requirement for an editable installation from a local path.
+pub fn parse_editable_requirement(
+    path: &Path,
+    output: CommandOutput,
+) -> Result<Requirement, Error> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("unable to find {}", path.display()))?;
+    let url = Url::from_file_path(&path)
+        .map_err(|_| anyhow!("unable to install from {}", path.display()))?;
+    let name = find_direct_reference_name(&path.to_string_lossy(), output)?;
+
+    format!("{} @ {}", name, url)
+        .parse()
+        .with_context(|| format!("invalid editable path {}", path.display()))
+}
+
+/// Asks pip for the name of the distribution behind a direct reference.
+fn find_direct_reference_name(url: &str, output: CommandOutput) -> Result<String, Error> {
+    let self_venv = ensure_self_venv(output)?;
+    let report_file = NamedTempFile::new()?;
+
+    if output != CommandOutput::Quiet {
+        eprintln!("Determining package name of {}", style(url).cyan());
+    }
+    let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip"));
+    cmd.arg("install")
+        .arg("--dry-run")
+        .arg("--no-deps")
+        .arg("--ignore-installed")
+        .arg("--report")
+        .arg(report_file.path())
+        .env("PYTHONWARNINGS", "ignore")
+        .env("PIP_DISABLE_PIP_VERSION_CHECK", "1");
+    use_pip_cache(&mut cmd)?;
+    if output == CommandOutput::Verbose {
+        cmd.arg("--verbose");
+    } else {
+        cmd.arg("-q");
+    }
+    cmd.arg("--").arg(url);
+    let status = cmd.status().context("unable to run pip")?;
+    if !status.success() {
+        bail!("unable to determine package name of {}", url);
+    }
+
+    let report: InstallReport = serde_json::from_slice(&fs::read(report_file.path())?)
+        .context("unable to parse pip installation report")?;
+    report
+        .install
+        .into_iter()
+        .next()
+        .map(|item| item.metadata.name)
+        .ok_or_else(|| anyhow!("unable to determine package name of {}", url))
+}
+
+/// Controls which distributions contribute scripts to the shims.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IncludeDeps {
+    /// Only the scripts of the requested package
+    #[default]
+    None,
+    /// Scripts of the requested package and the given dependencies
+    Only(Vec<String>),
+    /// Scripts of every distribution in the tool venv
+    All,
+}
+
+/// Serializes toolchain fetches of parallel installations.
+static FETCH_LOCK: Mutex<()> = Mutex::new(());
+
+/// Serializes shim creation of parallel installations.
+static SHIM_LOCK: Mutex<()> = Mutex::new(());
+
+/// The index pip uses unless configured otherwise.
+const DEFAULT_INDEX_URL: &str = "https://pypi.org/simple/";
+
+/// The python version tools are installed with unless requested otherwise.
+const DEFAULT_TOOL_PYTHON: PythonVersionRequest = PythonVersionRequest {
+    kind: None,
+    major: 3,
+    minor: None,
+    patch: None,
+    suffix: None,
+};
+
+/// Controls how a tool is installed.
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// Force install the package even if it's already there.
+    pub force: bool,
+    /// Which distributions contribute scripts.
+    pub include_deps: IncludeDeps,
+    /// Install the package from a local path in editable mode.
+    pub editable: bool,
+    /// Extra arguments passed to pip.
+    pub pip_args: Vec<String>,
+    /// Take over conflicting shims of other tools.
+    pub force_shims: bool,
+    /// A constraints file to pass to pip.
+    pub constraints: Option<PathBuf>,
+    /// A hash-locked requirements file to install from instead.
+    pub hashed_requirements: Option<PathBuf>,
+    /// The package index to install from.
+    pub index_url: Option<String>,
+    /// Additional package indexes to install from.
+    pub extra_index_urls: Vec<String>,
+    /// Local folders of wheels to install from.
+    pub find_links: Vec<PathBuf>,
+    /// Install without network access.
+    pub offline: bool,
+    /// Allow pre-releases.
+    pub pre: bool,
+    /// Do not use pip's cache or the wheel cache shared with other tools.
+    pub no_cache: bool,
+    /// Only show what would be done.
+    pub dry_run: bool,
+    /// Only link these scripts.
+    pub only_scripts: Vec<String>,
+    /// Link scripts under other names.
+    pub script_aliases: BTreeMap<String, String>,
+}
+
 pub fn install(
-    requirement: Requirement,
-    py_ver: &PythonVersionRequest,
-    force: bool,
+    mut requirement: Requirement,
+    py_ver: Option<&PythonVersionRequest>,
+    options: &InstallOptions,
     output: CommandOutput,
 ) -> Result<(), Error> {
-    let app_dir = get_app_dir()?;
-    let shim_dir = app_dir.join("shims");
-    let self_venv = ensure_self_venv(output)?;
-    let tool_dir = app_dir.join("tools");
+    // upgrades run from arbitrary directories, so the path is made absolute
+    let mut constraints = match options.constraints {
+        Some(ref path) => {
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("unable to find constraints file {}", path.display()))?;
+            if !path.is_file() {
+                bail!("constraints file {} is not a file", path.display());
+            }
+            Some(path)
+        }
+        None => None,
+    };
+    let mut hashed_requirements = match options.hashed_requirements {
+        Some(ref path) => Some(
+            path.canonicalize()
+                .with_context(|| format!("unable to find requirements file {}", path.display()))?,
+        ),
+        None => None,
+    };
 
+    let mut find_links = Vec::new();
+    for path in &options.find_links {
+        find_links.push(
+            path.canonicalize()
+                .with_context(|| format!("unable to find wheels in {}", path.display()))?,
+        );
+    }
+    if find_links.is_empty() {
+        find_links.extend(get_default_wheelhouse()?);
+    }
+    if options.offline && find_links.is_empty() {
+        bail!("offline installs require --find-links or a configured wheelhouse");
+    }
+
+    let shim_dir = get_shims_dir()?;
+    let tool_dir = get_tools_dir()?;
+
+    // older versions did not fully normalize the names of the folders, such
+    // installations are found too and replaced by one in the normalized
+    // folder when reinstalling.
     let target_venv_path = tool_dir.join(normalize_package_name(&requirement.name));
-    if target_venv_path.is_dir() && !force {
+    let installed_venv_path = match get_installed_tool(&requirement.name)? {
+        Some(tool) => tool.venv_path().to_path_buf(),
+        None => target_venv_path.clone(),
+    };
+
+    // an installation that did not finish does not count as installed
+    if installed_venv_path.is_dir()
+        && !installed_venv_path.join(TOOL_INCOMPLETE_MARKER).is_file()
+        && !options.force
+    {
+        if options.dry_run {
+            println!("{} is already installed", requirement.name);
+            return Err(QuietExit(2).into());
+        }
         bail!("package already installed");
     }
-    let target_venv_bin_path = target_venv_path.join("bin");
 
-    uninstall_helper(&target_venv_path, &shim_dir)?;
+    // when reinstalling, retain the extras, pip arguments and script selection
+    // from the previous installation unless new ones were provided.
+    let mut pip_args = options.pip_args.clone();
+    let mut include_deps = options.include_deps.clone();
+    let mut only_scripts = options.only_scripts.clone();
+    let mut script_aliases = options.script_aliases.clone();
+    let mut index_url = options.index_url.clone();
+    let mut extra_index_urls = options.extra_index_urls.clone();
+    let mut previously_replaced_shims = Vec::new();
+    let mut previous_python = None;
+    let mut pre = options.pre;
+    let no_cache = options.no_cache;
+    if let Some(receipt) = ToolReceipt::load(&installed_venv_path).ok().flatten() {
+        previously_replaced_shims = receipt.replaced_shims.clone();
+        previous_python = receipt.python.clone();
+        pre |= receipt.pre;
+        if requirement.extras.is_none() {
+            let extras = receipt.extras();
+            if !extras.is_empty() {
+                requirement.extras = Some(extras);
+            }
+        }
+        if pip_args.is_empty() {
+            pip_args = receipt.pip_args;
+        }
+        if include_deps == IncludeDeps::None {
+            include_deps = receipt.include_deps;
+        }
+        if only_scripts.is_empty() && script_aliases.is_empty() {
+            only_scripts = receipt.only_scripts;
+            script_aliases = receipt.script_aliases;
+        }
+        if constraints.is_none() {
+            constraints = receipt.constraints;
+        }
+        // hash checking is never dropped silently
+        if hashed_requirements.is_none() {
+            hashed_requirements = receipt.hashed_requirements;
+        }
+        if index_url.is_none() && extra_index_urls.is_empty() {
+            index_url = receipt.index_url;
+            extra_index_urls = receipt.extra_index_urls;
+        }
+    }
+
+    // fall back to the indexes from the config
+    if index_url.is_none() && extra_index_urls.is_empty() {
+        (index_url, extra_index_urls) = get_default_indexes()?;
+    }
+
+    // reinstalls stick with the interpreter of the previous installation
+    // unless another one was requested.
+    let py_ver = match py_ver {
+        Some(py_ver) => py_ver.clone(),
+        None => previous_python
+            .as_deref()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_TOOL_PYTHON),
+    };
+
+    let mut receipt = ToolReceipt {
+        requirement: format_requirement(&requirement).to_string(),
+        editable: options.editable,
+        pip_args,
+        include_deps,
+        only_scripts,
+        script_aliases,
+        constraints,
+        hashed_requirements,
+        index_url,
+        extra_index_urls,
+        find_links,
+        offline: options.offline,
+        pre,
+        no_cache,
+        installed_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|x| x.as_secs()),
+        ..ToolReceipt::default()
+    };
+
+    if options.dry_run {
+        let (version, download) = if options.offline {
+            (find_local_toolchain(&py_ver)?, false)
+        } else {
+            resolve_fetch(&py_ver)?
+        };
+        println!(
+            "python: {} ({})",
+            version,
+            if download {
+                "would be downloaded"
+            } else {
+                "already downloaded"
+            }
+        );
+        println!(
+            "virtualenv: {}{}",
+            target_venv_path.display(),
+            if installed_venv_path.is_dir() {
+                " (would be replaced)"
+            } else {
+                ""
+            }
+        );
+        let self_venv = get_app_dir()?.join("self");
+        let cmd =
+            pip_install_command(&self_venv, &target_venv_path, &receipt, false, None, output)?;
+        println!("pip: {}", format_command(&cmd));
+        return Ok(());
+    }
+
+    let self_venv = ensure_self_venv(output)?;
+    for dir in [&shim_dir, &tool_dir] {
+        fs::create_dir_all(dir).map_err(|err| {
+            let msg = format!(
+                "unable to create {}{}",
+                dir.display(),
+                permission_hint(&err)
+            );
+            Error::new(err).context(msg)
+        })?;
+    }
 
     // make sure we have a compatible python version
-    let py_ver = fetch(py_ver, output)?;
+    let mut progress = Progress::new(output);
+    progress.phase("Fetching python");
+    let py_ver = {
+        // toolchains are shared between tools that install in parallel
+        let _guard = FETCH_LOCK.lock().unwrap_or_else(|x| x.into_inner());
+        if options.offline {
+            find_local_toolchain(&py_ver)?
+        } else {
+            progress.suspend(|| fetch(&py_ver, output))?
+        }
+    };
+
+    receipt.python = Some(py_ver.to_string());
+
+    let marker = begin_tool_install(&installed_venv_path, &target_venv_path, &shim_dir)?;
+    receipt.replaced_shims = match populate_tool_venv(
+        &self_venv,
+        &py_ver,
+        &target_venv_path,
+        &receipt,
+        options.force_shims,
+        &mut progress,
+        output,
+    ) {
+        Ok(replaced_shims) => replaced_shims,
+        Err(err) => {
+            // do not leave a half installed tool behind
+            uninstall_helper(&target_venv_path, &shim_dir).ok();
+            return Err(err);
+        }
+    };
+
+    // shims that were taken over by an earlier installation of this tool
+    // are still ours to give back.
+    for replaced in previously_replaced_shims {
+        let shim = shim_dir.join(&replaced.shim);
+        if read_shim_target(&shim).is_some_and(|x| x.strip_prefix(&target_venv_path).is_ok()) {
+            receipt.replaced_shims.push(replaced);
+        }
+    }
+    receipt.shims = find_linked_shims(&target_venv_path, &shim_dir)?;
+    receipt.save(&target_venv_path)?;
+    fs::remove_file(&marker).context("unable to finalize tool installation")?;
+
+    Ok(())
+}
+
+/// Removes the previous installation of a tool and creates its new folder.
+///
+/// The returned marker is only removed once the installation finished.  If
+/// rye is interrupted, the next installation can clean up after it.
+fn begin_tool_install(
+    installed_venv_path: &Path,
+    target_venv_path: &Path,
+    shim_dir: &Path,
+) -> Result<PathBuf, Error> {
+    if installed_venv_path != target_venv_path {
+        uninstall_helper(installed_venv_path, shim_dir)?;
+    }
+    uninstall_helper(target_venv_path, shim_dir)?;
+    let marker = target_venv_path.join(TOOL_INCOMPLETE_MARKER);
+    fs::create_dir_all(target_venv_path)
+        .and_then(|_| fs::write(&marker, b""))
+        .with_context(|| format!("unable to create {}", target_venv_path.display()))?;
+    Ok(marker)
+}
+
+/// Creates the virtualenv of a tool, installs it and links its scripts.
+fn populate_tool_venv(
+    self_venv: &Path,
+    py_ver: &PythonVersion,
+    venv: &Path,
+    receipt: &ToolReceipt,
+    force_shims: bool,
+    progress: &mut Progress,
+    output: CommandOutput,
+) -> Result<Vec<ReplacedShim>, Error> {
+    let requirement: Requirement = receipt.requirement.parse()?;
+    progress.phase("Creating virtualenv");
+    create_virtualenv(
+        progress.subprocess_output(),
+        self_venv,
+        py_ver,
+        venv,
+        &normalize_package_name(&requirement.name),
+    )?;
+    progress.phase("Installing packages");
+    install_packages(
+        self_venv,
+        venv,
+        receipt,
+        false,
+        progress.subprocess_output(),
+    )?;
+    progress.phase("Linking scripts");
+    let scripts = find_scripts(venv, receipt)?;
+    if scripts.is_empty() {
+        bail!("no scripts found in {}", requirement.name);
+    }
+    for name in receipt
+        .only_scripts
+        .iter()
+        .chain(receipt.script_aliases.keys())
+    {
+        if !scripts.iter().any(|x| script_matches(x, name)) {
+            bail!("package does not provide a script named {}", name);
+        }
+    }
+    progress.finish();
+    link_shims(
+        &scripts,
+        venv,
+        &receipt.script_aliases,
+        &get_shims_dir()?,
+        &get_tools_dir()?,
+        force_shims,
+        output,
+    )
+}
+
+/// Installs the requirement of a receipt, through the wheel cache if possible.
+fn install_packages(
+    self_venv: &Path,
+    venv: &Path,
+    receipt: &ToolReceipt,
+    upgrade: bool,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    if !receipt.no_cache && install_from_wheel_cache(self_venv, venv, receipt, upgrade, output)? {
+        return Ok(());
+    }
+    pip_install(self_venv, venv, receipt, upgrade, output)
+}
+
+/// Installs the requirement of a receipt from the shared wheel cache.
+///
+/// pip resolves the requirement and reports what it would install.  Wheels
+/// missing from the cache are downloaded and unpacked into it, then all of
+/// them are linked into the virtualenv.  Returns `false` if the installation
+/// cannot go through the cache (for instance because a package has no
+/// wheel), in which case pip has to install the requirement.
+fn install_from_wheel_cache(
+    self_venv: &Path,
+    venv: &Path,
+    receipt: &ToolReceipt,
+    upgrade: bool,
+    output: CommandOutput,
+) -> Result<bool, Error> {
+    if cfg!(not(unix)) || receipt.editable {
+        return Ok(false);
+    }
+    let auth = IndexAuth::new(&receipt_index_urls(receipt), output)?;
+    let report_file = NamedTempFile::new()?;
+    let mut cmd = pip_install_command(
+        self_venv,
+        venv,
+        receipt,
+        upgrade,
+        Some(report_file.path()),
+        output,
+    )?;
+    if output == CommandOutput::Verbose {
+        eprintln!("running {}", format_command(&cmd));
+    }
+    auth.apply(&mut cmd);
+    if !cmd.status()?.success() {
+        bail!("tool installation failed");
+    }
+    let report: InstallReport = serde_json::from_slice(&fs::read(report_file.path())?)
+        .context("unable to parse pip installation report")?;
+
+    // only wheels with a known hash can be shared
+    let mut wheels = Vec::new();
+    for item in &report.install {
+        match item.download_info.as_ref().and_then(|x| x.wheel()) {
+            Some((filename, hash)) => wheels.push((item, filename, hash)),
+            None => return Ok(false),
+        }
+    }
+
+    let cache_dir = get_wheel_cache_dir()?;
+    let download_dir = tempfile::tempdir()?;
+    let mut requirements = String::new();
+    for (item, _, hash) in &wheels {
+        if !cache_dir.join(hash).is_dir() {
+            writeln!(
+                requirements,
+                "{} @ {} --hash=sha256:{}",
+                item.metadata.name,
+                item.download_info.as_ref().unwrap().url,
+                hash
+            )?;
+        }
+    }
+    if !requirements.is_empty() {
+        let requirements_file = download_dir.path().join("requirements.txt");
+        fs::write(&requirements_file, requirements)?;
+        let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip"));
+        cmd.arg("--python")
+            .arg(venv.join(VENV_BIN).join("python"))
+            .arg("download")
+            .arg("--no-deps")
+            .arg("--dest")
+            .arg(download_dir.path())
+            .arg("--requirement")
+            .arg(&requirements_file)
+            .env("PYTHONWARNINGS", "ignore");
+        use_pip_cache(&mut cmd)?;
+        match output {
+            CommandOutput::Verbose => {
+                cmd.arg("--verbose");
+            }
+            CommandOutput::Quiet => {
+                cmd.arg("-q");
+            }
+            CommandOutput::Normal => {}
+        }
+        if output == CommandOutput::Verbose {
+            eprintln!("running {}", format_command(&cmd));
+        }
+        auth.apply(&mut cmd);
+        if !cmd.status()?.success() {
+            bail!("unable to download packages");
+        }
+    }
+
+    let python = self_venv.join(VENV_BIN).join("python");
+    let mut unpacked = Vec::new();
+    for (item, filename, hash) in wheels {
+        let wheel_dir = unpack_wheel(&python, &download_dir.path().join(filename), hash)?;
+        if !can_link_wheel(&wheel_dir)? {
+            return Ok(false);
+        }
+        unpacked.push((item, wheel_dir));
+    }
+
+    let venv_python = venv.join(VENV_BIN).join("python");
+    let scheme = get_install_scheme(&venv_python)?;
+    if upgrade {
+        uninstall_dists(self_venv, venv, &scheme, &report, output)?;
+    }
+    for (item, wheel_dir) in unpacked {
+        let direct_url = if item.is_direct {
+            Some(serde_json::to_string(&item.download_info)?)
+        } else {
+            None
+        };
+        link_wheel(
+            &wheel_dir,
+            &scheme,
+            &venv_python,
+            item.requested,
+            direct_url.as_deref(),
+        )
+        .with_context(|| format!("unable to install {}", item.metadata.name))?;
+    }
+    Ok(true)
+}
+
+/// Asks the interpreter of a virtualenv where installed files go.
+fn get_install_scheme(python: &Path) -> Result<InstallScheme, Error> {
+    let out = Command::new(python)
+        .arg("-c")
+        .arg("import json, sysconfig; print(json.dumps(sysconfig.get_paths()))")
+        .stdout(Stdio::piped())
+        .output()
+        .context("unable to run python")?;
+    if !out.status.success() {
+        bail!("unable to determine where {} installs to", python.display());
+    }
+    serde_json::from_slice(&out.stdout).context("unable to parse install scheme")
+}
+
+/// Uninstalls the distributions of a report that are already installed.
+fn uninstall_dists(
+    self_venv: &Path,
+    venv: &Path,
+    scheme: &InstallScheme,
+    report: &InstallReport,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    let mut installed = Vec::new();
+    for dir in [&scheme.purelib, &scheme.platlib] {
+        if let Ok(iter) = fs::read_dir(dir) {
+            for entry in iter {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                if let Some(stem) = name.strip_suffix(".dist-info") {
+                    installed.push(normalize_package_name(stem.split('-').next().unwrap()));
+                }
+            }
+        }
+    }
+    let names = report
+        .install
+        .iter()
+        .map(|x| &x.metadata.name)
+        .filter(|x| installed.contains(&normalize_package_name(x)))
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return Ok(());
+    }
+    let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip"));
+    cmd.arg("--python")
+        .arg(venv.join(VENV_BIN).join("python"))
+        .arg("uninstall")
+        .arg("--yes")
+        .args(names)
+        .env("PYTHONWARNINGS", "ignore");
+    if output != CommandOutput::Verbose {
+        cmd.arg("-q");
+    }
+    if !cmd.status()?.success() {
+        bail!("unable to uninstall previous versions");
+    }
+    Ok(())
+}
+
+/// The result of a tool upgrade.
+#[derive(Debug, Clone)]
+pub struct UpgradeOutcome {
+    /// The normalized name of the tool.
+    pub name: String,
+    /// The version installed before the upgrade.
+    pub old_version: Option<String>,
+    /// The version installed after the upgrade (or that would be installed).
+    pub new_version: Option<String>,
+}
+
+impl UpgradeOutcome {
+    /// Did the version change?
+    pub fn changed(&self) -> bool {
+        self.old_version != self.new_version
+    }
+}
+
+/// Upgrades an installed tool in place.
+///
+/// If a requirement is passed, its specifier replaces the one the tool was
+/// originally installed with.  In dry-run mode nothing is changed and only
+/// the version that would be installed is determined.
+pub fn upgrade(
+    package: &str,
+    requirement: Option<Requirement>,
+    python: Option<&PythonVersionRequest>,
+    pre: bool,
+    dry_run: bool,
+    output: CommandOutput,
+) -> Result<UpgradeOutcome, Error> {
+    let shim_dir = get_shims_dir()?;
+    let tool_dir = get_tools_dir()?;
+    let tool = match get_installed_tool(package)? {
+        Some(tool) => tool,
+        None => bail!(
+            "{} is not installed{}. Use `rye install` to install it.",
+            package,
+            did_you_mean(package)?
+        ),
+    };
+    if tool.is_incomplete() && python.is_none() {
+        bail!(
+            "installation of {} did not finish. Use `rye install` to install it again.",
+            tool.name()
+        );
+    }
+    if tool.is_broken() && python.is_none() {
+        bail!(
+            "virtualenv of {} is broken. Use `rye install --force` to reinstall it.",
+            tool.name()
+        );
+    }
+
+    // tools installed by older versions of rye do not have a receipt, in
+    // which case the name is all we have to go by.
+    let mut receipt = tool.receipt().unwrap_or_else(|| ToolReceipt {
+        requirement: tool.name().to_string(),
+        ..ToolReceipt::default()
+    });
+    if let Some(ref path) = receipt.hashed_requirements {
+        if requirement.is_some() || pre {
+            bail!(
+                "{} was installed with hash checking. Update the hashes in {} and run \
+                 `rye tools upgrade {}` without a version instead.",
+                tool.name(),
+                path.display(),
+                tool.name()
+            );
+        }
+        // the requirements file decides the version, upgrading re-syncs to it
+        parse_hashed_requirements(path)?;
+    }
+    if let Some(mut requirement) = requirement {
+        if requirement.extras.is_none() {
+            let extras = receipt.extras();
+            if !extras.is_empty() {
+                requirement.extras = Some(extras);
+            }
+        }
+        receipt.requirement = format_requirement(&requirement).to_string();
+    }
+    receipt.pre |= pre;
+
+    // check the index first, this avoids invoking pip for tools that are
+    // already up to date.  Direct references cannot be checked that way.
+    let old_version = tool.version();
+    let latest_version = if receipt.editable || receipt.hashed_requirements.is_some() {
+        None
+    } else {
+        find_latest_version(&receipt, output)?
+    };
+    let mut outcome = UpgradeOutcome {
+        name: tool.name().to_string(),
+        old_version,
+        new_version: latest_version,
+    };
+    if dry_run {
+        return Ok(outcome);
+    }
+
+    // switching the interpreter requires rebuilding the virtualenv
+    if let Some(python) = python {
+        install(
+            receipt.requirement.parse()?,
+            Some(python),
+            &reinstall_options(&receipt),
+            output,
+        )?;
+        outcome.new_version = tool.version();
+        return Ok(outcome);
+    }
+
+    if outcome.new_version.is_some() && !outcome.changed() {
+        return Ok(outcome);
+    }
+
+    let self_venv = ensure_self_venv(output)?;
+    install_packages(&self_venv, tool.venv_path(), &receipt, true, output)?;
+    outcome.new_version = tool.version();
+
+    // remove shims of scripts that disappeared and link new ones
+    let scripts = find_scripts(tool.venv_path(), &receipt)?;
+    for shim in tool.list_shims(&shim_dir)? {
+        if !read_shim_target(&shim).is_some_and(|target| scripts.contains(&target)) {
+            fs::remove_file(&shim)?;
+            if output != CommandOutput::Quiet {
+                if let Some(name) = shim.file_name() {
+                    eprintln!("removed script {}", style(name.to_string_lossy()).cyan());
+                }
+            }
+        }
+    }
+    let replaced_shims = link_shims(
+        &scripts,
+        tool.venv_path(),
+        &receipt.script_aliases,
+        &shim_dir,
+        &tool_dir,
+        false,
+        output,
+    )?;
+    receipt.replaced_shims.extend(replaced_shims);
+    receipt.shims = find_linked_shims(tool.venv_path(), &shim_dir)?;
+    receipt.save(tool.venv_path())?;
+
+    Ok(outcome)
+}
+
+/// Finds the latest version on the index matching the requirement of a receipt.
+///
+/// For direct references `None` is returned.
+fn find_latest_version(
+    receipt: &ToolReceipt,
+    output: CommandOutput,
+) -> Result<Option<String>, Error> {
+    let requirement: Requirement = receipt.requirement.parse()?;
+    // the index lookup does not consider pre-releases
+    if receipt.offline || receipt.pre {
+        return Ok(None);
+    }
+    if let Some(VersionOrUrl::Url(_)) = requirement.version_or_url {
+        return Ok(None);
+    }
+    let mut unearth_path = ensure_self_venv(output)?;
+    unearth_path.push(VENV_BIN);
+    unearth_path.push("unearth");
+
+    let mut cmd = Command::new(&unearth_path);
+    if receipt.index_url.is_some() || !receipt.extra_index_urls.is_empty() {
+        // unearth has no concept of extra indexes, all of them are equal
+        let index_url = receipt.index_url.as_deref().unwrap_or(DEFAULT_INDEX_URL);
+        for url in Some(index_url)
+            .into_iter()
+            .chain(receipt.extra_index_urls.iter().map(|x| x.as_str()))
+        {
+            cmd.arg("--index-url").arg(get_public_index_url(url));
+        }
+    }
+    let auth = IndexAuth::new(&receipt_index_urls(receipt), output)?;
+    auth.apply(&mut cmd);
+    let unearth = cmd
+        .arg("--")
+        .arg(format_requirement(&requirement).to_string())
+        .stdout(Stdio::piped())
+        .output()
+        .context("unable to run unearth")?;
+    if !unearth.status.success() {
+        bail!("did not find package {}", format_requirement(&requirement));
+    }
+    let m: IndexMatch = serde_json::from_slice(&unearth.stdout)?;
+    Ok(Some(m.version))
+}
+
+/// Finds an already downloaded toolchain matching a version request.
+fn find_local_toolchain(py_ver: &PythonVersionRequest) -> Result<PythonVersion, Error> {
+    let mut toolchains = list_known_toolchains()?;
+    toolchains.retain(|x| matches_version(py_ver, x) && get_py_bin(x).is_ok_and(|x| x.is_file()));
+    toolchains.sort();
+    toolchains.pop().ok_or_else(|| {
+        anyhow!(
+            "no local python matching {} found, fetch it first with `rye fetch` (offline mode)",
+            py_ver
+        )
+    })
+}
+
+/// Installs the requirement of a receipt into a tool virtualenv.
+fn pip_install(
+    self_venv: &Path,
+    venv: &Path,
+    receipt: &ToolReceipt,
+    upgrade: bool,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    let mut cmd = pip_install_command(self_venv, venv, receipt, upgrade, None, output)?;
+    if output == CommandOutput::Verbose {
+        eprintln!("running {}", format_command(&cmd));
+    }
+    let auth = IndexAuth::new(&receipt_index_urls(receipt), output)?;
+    auth.apply(&mut cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        bail!("tool installation failed");
+    }
+    Ok(())
+}
 
-    create_virtualenv(output, &self_venv, &py_ver, &target_venv_path)?;
+/// Returns the index URLs pip uses for a receipt.
+fn receipt_index_urls(receipt: &ToolReceipt) -> Vec<String> {
+    if receipt.offline {
+        Vec::new()
+    } else if receipt.index_url.is_none() && receipt.extra_index_urls.is_empty() {
+        get_configured_index_urls()
+    } else {
+        receipt
+            .index_url
+            .iter()
+            .chain(receipt.extra_index_urls.iter())
+            .cloned()
+            .collect()
+    }
+}
 
-    let mut cmd = Command::new(&self_venv.join("bin/pip"));
+/// Builds the pip command that installs the requirement of a receipt.
+///
+/// With a report file, pip only writes what it would install into it.
+fn pip_install_command(
+    self_venv: &Path,
+    venv: &Path,
+    receipt: &ToolReceipt,
+    upgrade: bool,
+    report: Option<&Path>,
+    output: CommandOutput,
+) -> Result<Command, Error> {
+    let requirement: Requirement = receipt.requirement.parse()?;
+    let mut cmd = Command::new(self_venv.join(VENV_BIN).join("pip"));
     cmd.arg("--python")
-        .arg(&target_venv_bin_path.join("python"))
+        .arg(venv.join(VENV_BIN).join("python"))
         .arg("install")
         .env("PYTHONWARNINGS", "ignore");
+    use_pip_cache(&mut cmd)?;
     if output == CommandOutput::Verbose {
         cmd.arg("--verbose");
     } else {
-        if output == CommandOutput::Quiet {
+        if output == CommandOutput::Quiet || report.is_some() {
             cmd.arg("-q");
         }
         cmd.env("PYTHONWARNINGS", "ignore");
     }
-    cmd.arg("--").arg(&requirement.to_string());
-
-    let status = cmd.status()?;
-    if !status.success() {
-        bail!("tool installation failed");
+    if let Some(report) = report {
+        cmd.arg("--dry-run").arg("--report").arg(report);
+    }
+    if upgrade {
+        cmd.arg("--upgrade");
+    }
+    if receipt.pre {
+        cmd.arg("--pre");
+    }
+    if receipt.no_cache {
+        cmd.arg("--no-cache-dir");
+    }
+    cmd.args(&receipt.pip_args);
+    if let Some(ref constraints) = receipt.constraints {
+        cmd.arg("--constraint").arg(constraints);
+    }
+    for path in &receipt.find_links {
+        cmd.arg("--find-links").arg(path);
+    }
+    if receipt.offline {
+        cmd.arg("--no-index");
+    } else {
+        if let Some(ref index_url) = receipt.index_url {
+            cmd.arg("--index-url").arg(get_public_index_url(index_url));
+        }
+        for extra_index_url in &receipt.extra_index_urls {
+            cmd.arg("--extra-index-url")
+                .arg(get_public_index_url(extra_index_url));
+        }
+    }
+    if let Some(ref path) = receipt.hashed_requirements {
+        cmd.arg("--require-hashes").arg("--requirement").arg(path);
+    } else if receipt.editable {
+        let path = match requirement.version_or_url {
+            Some(VersionOrUrl::Url(ref url)) => url
+                .to_file_path()
+                .map_err(|_| anyhow!("editable installs require a local path"))?,
+            _ => bail!("editable installs require a local path"),
+        };
+        let mut path = path.into_os_string();
+        if let Some(ref extras) = requirement.extras {
+            path.push(format!("[{}]", extras.join(",")));
+        }
+        cmd.arg("-e").arg(path);
+    } else {
+        cmd.arg("--").arg(requirement.to_string());
     }
 
-    let out = Command::new(&target_venv_bin_path.join("python"))
+    Ok(cmd)
+}
+
+/// Finds the scripts in the bin folder of a tool that should get shims.
+fn find_scripts(venv: &Path, receipt: &ToolReceipt) -> Result<Vec<PathBuf>, Error> {
+    let requirement: Requirement = receipt.requirement.parse()?;
+    let venv_bin_path = venv.join(VENV_BIN);
+    let mut find_script_cmd = Command::new(venv_bin_path.join("python"));
+    find_script_cmd
         .arg("-c")
         .arg(FIND_SCRIPT_SCRIPT)
         .arg(&requirement.name)
+        .arg(requirement.extras.as_deref().unwrap_or_default().join(","));
+    match receipt.include_deps {
+        IncludeDeps::None => {}
+        IncludeDeps::Only(ref deps) => {
+            find_script_cmd.args(deps);
+        }
+        IncludeDeps::All => {
+            find_script_cmd.arg("--all");
+        }
+    }
+    let out = find_script_cmd
         .stdout(Stdio::piped())
         .output()
         .context("unable to dump package manifest from installed package")?;
-    let files = std::str::from_utf8(&out.stdout)
-        .context("non utf-8 package manifest")?
-        .lines()
-        .map(Path::new)
-        .collect::<Vec<_>>();
+    if !out.status.success() {
+        bail!("unable to dump package manifest from installed package");
+    }
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        for file in files {
-            if let Ok(rest) = file.strip_prefix(&target_venv_bin_path) {
-                let shim_target = shim_dir.join(rest);
-                symlink(file, shim_target)
-                    .with_context(|| format!("unable to symlink tool to {}", file.display()))?;
-                if output != CommandOutput::Quiet {
-                    eprintln!("installed script {}", style(rest.display()).cyan());
-                }
+    let mut rv = Vec::new();
+    let mut missing_record = false;
+    for line in String::from_utf8_lossy(&out.stdout).lines() {
+        if line.starts_with('!') {
+            missing_record = true;
+            continue;
+        }
+        let file = PathBuf::from(line);
+        // the same file can be listed more than once when it's
+        // contributed by multiple distributions.
+        if file.strip_prefix(&venv_bin_path).is_ok() && !rv.contains(&file) {
+            rv.push(file);
+        }
+    }
+    if missing_record {
+        for file in find_venv_scripts(venv)? {
+            if !rv.contains(&file) {
+                rv.push(file);
             }
         }
     }
+    if !receipt.only_scripts.is_empty() {
+        rv.retain(|x| {
+            receipt
+                .only_scripts
+                .iter()
+                .any(|name| script_matches(x, name))
+        });
+    }
+    Ok(rv)
+}
 
-    #[cfg(target_os = "windows")]
+/// Finds the scripts in the bin folder of a virtualenv by their shebang.
+///
+/// This is the fallback for distributions that do not list their files.  As
+/// the virtualenv is created without seed packages, every script in there
+/// that runs on the virtualenv's python was installed for the tool.
+fn find_venv_scripts(venv: &Path) -> Result<Vec<PathBuf>, Error> {
+    let venv_bin_path = venv.join(VENV_BIN);
+    let shebang = format!("#!{}", venv_bin_path.display());
+    let mut rv = Vec::new();
+    for entry in fs::read_dir(&venv_bin_path)
+        .with_context(|| format!("unable to read {}", venv_bin_path.display()))?
     {
-        for file in files {
-            if let Ok(rest) = file.strip_prefix(&target_venv_bin_path) {
-                let shim_target = shim_dir.join(rest);
-                symlink_file(file, shim_target)
-                    .with_context(|| format!("unable to symlink tool to {}", file.display()))?;
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let mut head = [0; 512];
+        let n = fs::File::open(&path)
+            .and_then(|mut f| std::io::Read::read(&mut f, &mut head))
+            .unwrap_or(0);
+        if head[..n].starts_with(shebang.as_bytes()) {
+            rv.push(path);
+        }
+    }
+    rv.sort();
+    Ok(rv)
+}
+
+/// Links scripts into the shim folder.
+///
+/// Scripts are linked under their alias if they have one.  Scripts that
+/// are already linked are skipped.  All conflicts are detected
+/// before any shim is created.  Conflicting shims of other tools are only
+/// replaced if `force_shims` is set, in which case the replaced shims are
+/// returned so that they can be restored later.
+fn link_shims(
+    scripts: &[PathBuf],
+    venv: &Path,
+    aliases: &BTreeMap<String, String>,
+    shim_dir: &Path,
+    tool_dir: &Path,
+    force_shims: bool,
+    output: CommandOutput,
+) -> Result<Vec<ReplacedShim>, Error> {
+    // tools that install in parallel can race on the same script names
+    let _guard = SHIM_LOCK.lock().unwrap_or_else(|x| x.into_inner());
+    let venv_bin_path = venv.join(VENV_BIN);
+    let mut to_link = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for file in scripts {
+        let rest = match file.strip_prefix(&venv_bin_path) {
+            Ok(rest) => rest,
+            Err(_) => continue,
+        };
+        let alias = aliases
+            .iter()
+            .find(|(name, _)| script_matches(file, name))
+            .map(|x| x.1);
+        let shim = get_shim_path(shim_dir, alias.map_or(rest, Path::new));
+        if shim.is_symlink() || shim.exists() {
+            let target = read_shim_target(&shim);
+            if target.as_ref() == Some(file) {
+                continue;
+            }
+            conflicts.push((shim.clone(), target));
+        }
+        to_link.push((file, rest, alias, shim));
+    }
+
+    // files in the shim folder that are not shims (like the python shims)
+    // are never replaced.
+    if !conflicts.is_empty() && (!force_shims || conflicts.iter().any(|x| x.1.is_none())) {
+        let mut msg = String::from("conflicting shims:");
+        for (shim, target) in &conflicts {
+            let name = shim.file_name().unwrap_or_default().to_string_lossy();
+            match target {
+                Some(target) => match get_owning_tool(target, tool_dir) {
+                    Some(owner) => write!(msg, "\n  {} (from {})", name, owner)?,
+                    None => write!(msg, "\n  {} (points to {})", name, target.display())?,
+                },
+                None => write!(msg, "\n  {} (not a shim, cannot be replaced)", name)?,
+            }
+        }
+        if conflicts.iter().all(|x| x.1.is_some()) {
+            msg.push_str("\nUse --force-shims to take them over.");
+        }
+        bail!(msg);
+    }
+
+    let mut replaced = Vec::new();
+    for (shim, target) in conflicts {
+        fs::remove_file(&shim)
+            .with_context(|| format!("unable to replace shim {}", shim.display()))?;
+        if let Some(target) = target {
+            if let Some(tool) = get_owning_tool(&target, tool_dir) {
                 if output != CommandOutput::Quiet {
-                    eprintln!("installed script {}", style(rest.display()).cyan());
+                    eprintln!(
+                        "taking over script {} from {}",
+                        style(shim.file_name().unwrap_or_default().to_string_lossy()).cyan(),
+                        tool
+                    );
                 }
+                replaced.push(ReplacedShim {
+                    shim: shim
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    tool,
+                    target,
+                });
             }
         }
     }
 
-    Ok(())
+    for (file, rest, alias, shim) in to_link {
+        create_shim(file, &shim)?;
+        if output != CommandOutput::Quiet {
+            match alias {
+                Some(alias) => eprintln!(
+                    "installed script {} as {}",
+                    style(rest.display()).cyan(),
+                    style(alias).cyan()
+                ),
+                None => eprintln!("installed script {}", style(rest.display()).cyan()),
+            }
+        }
+    }
+
+    Ok(replaced)
+}
+
+/// Returns the file names of all shims pointing into a virtualenv.
+fn find_linked_shims(venv: &Path, shim_dir: &Path) -> Result<Vec<String>, Error> {
+    let mut rv = Vec::new();
+    for entry in fs::read_dir(shim_dir)? {
+        let path = entry?.path();
+        if read_shim_target(&path).is_some_and(|x| x.strip_prefix(venv).is_ok()) {
+            if let Some(name) = path.file_name() {
+                rv.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    rv.sort();
+    Ok(rv)
+}
+
+/// Returns the name of the tool that owns a script.
+fn get_owning_tool(target: &Path, tool_dir: &Path) -> Option<String> {
+    let rest = target.strip_prefix(tool_dir).ok()?;
+    rest.components()
+        .next()
+        .map(|x| x.as_os_str().to_string_lossy().into_owned())
 }
 
-pub fn uninstall(package: &str, output: CommandOutput) -> Result<(), Error> {
-    let app_dir = get_app_dir()?;
-    let shim_dir = app_dir.join("shims");
-    let tool_dir = app_dir.join("tools");
-    let target_venv_path = tool_dir.join(normalize_package_name(package));
-    if !target_venv_path.is_dir() {
-        eprintln!("{} is not installed", style(package).cyan());
+pub fn uninstall(package: &str, dry_run: bool, output: CommandOutput) -> Result<(), Error> {
+    let shim_dir = get_shims_dir()?;
+    let tool = match get_installed_tool(package)? {
+        Some(tool) => tool,
+        None => {
+            eprintln!(
+                "{} is not installed{}",
+                style(package).cyan(),
+                did_you_mean(package)?
+            );
+            if dry_run {
+                return Err(QuietExit(2).into());
+            }
+            return Ok(());
+        }
+    };
+
+    if dry_run {
+        println!("virtualenv: {}", tool.venv_path().display());
+        for shim in tool.list_shims(&shim_dir)? {
+            if let Some(target) = read_shim_target(&shim) {
+                println!("shim: {} -> {}", shim.display(), target.display());
+            }
+        }
         return Ok(());
     }
 
-    uninstall_helper(&target_venv_path, &shim_dir)
-        .with_context(|| format!("unable to uninstall {}", target_venv_path.display()))?;
+    let receipt = tool.receipt();
+    uninstall_helper(tool.venv_path(), &shim_dir)
+        .with_context(|| format!("unable to uninstall {}", tool.venv_path().display()))?;
     if output != CommandOutput::Quiet {
         eprintln!("Uninstalled {}", style(package).cyan());
     }
+
+    if let Some(receipt) = receipt {
+        restore_replaced_shims(&receipt, &shim_dir, output)?;
+    }
     Ok(())
 }
 
-fn uninstall_helper(target_venv_path: &Path, shim_dir: &Path) -> Result<(), Error> {
-    fs::remove_dir_all(target_venv_path).ok();
+/// Rebuilds the virtualenv of a tool and reinstalls it.
+///
+/// The tool is reinstalled from its receipt on the python version it was
+/// built with.  If that exact version can no longer be fetched, the latest
+/// one of the same minor version is used instead.
+pub fn reinstall(tool: &InstalledTool, output: CommandOutput) -> Result<(), Error> {
+    let receipt = tool.receipt().unwrap_or_else(|| ToolReceipt {
+        requirement: tool.name().to_string(),
+        ..ToolReceipt::default()
+    });
+    let py_ver = match tool.python_version() {
+        Some(version) => {
+            let mut py_ver = PythonVersionRequest::from(version);
+            let available = if receipt.offline {
+                find_local_toolchain(&py_ver).is_ok()
+            } else {
+                fetch(&py_ver, output).is_ok()
+            };
+            if !available {
+                py_ver.patch = None;
+                py_ver.suffix = None;
+            }
+            Some(py_ver)
+        }
+        None => None,
+    };
 
-    for script in fs::read_dir(shim_dir)? {
-        let script = script?;
-        if !script.path().is_symlink() {
+    install(
+        receipt.requirement.parse()?,
+        py_ver.as_ref(),
+        &reinstall_options(&receipt),
+        output,
+    )
+}
+
+/// Returns the options to rebuild a tool the way it was installed.
+///
+/// Everything else is retained from the receipt by [`install`] itself.
+fn reinstall_options(receipt: &ToolReceipt) -> InstallOptions {
+    InstallOptions {
+        force: true,
+        editable: receipt.editable,
+        find_links: receipt.find_links.clone(),
+        offline: receipt.offline,
+        pre: receipt.pre,
+        no_cache: receipt.no_cache,
+        ..InstallOptions::default()
+    }
+}
+
+/// Uninstalls all tools and removes dangling shims.
+///
+/// Failures to uninstall individual tools do not stop the uninstallation of
+/// the others, they are reported at the end instead.
+pub fn uninstall_all(output: CommandOutput) -> Result<(), Error> {
+    let shim_dir = get_shims_dir()?;
+    let mut failed = Vec::new();
+
+    for tool in list_installed_tools()? {
+        match uninstall_helper(tool.venv_path(), &shim_dir) {
+            Ok(()) => {
+                if output != CommandOutput::Quiet {
+                    eprintln!("Uninstalled {}", style(tool.name()).cyan());
+                }
+            }
+            Err(err) => failed.push((tool.name().to_string(), err)),
+        }
+    }
+
+    for path in find_dangling_shims()? {
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                if output == CommandOutput::Verbose {
+                    eprintln!("Removed dangling shim {}", path.display());
+                }
+            }
+            Err(err) => failed.push((path.display().to_string(), err.into())),
+        }
+    }
+
+    for (name, err) in &failed {
+        eprintln!("{} {}: {:#}", style("failed:").red(), name, err);
+    }
+    if !failed.is_empty() {
+        bail!("failed to uninstall {} tool(s)", failed.len());
+    }
+
+    Ok(())
+}
+
+/// Gives shims that were taken over by a tool back to their original owners.
+fn restore_replaced_shims(
+    receipt: &ToolReceipt,
+    shim_dir: &Path,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    for replaced in &receipt.replaced_shims {
+        let shim = shim_dir.join(&replaced.shim);
+        if !replaced.target.is_file() || shim.is_symlink() || shim.exists() {
             continue;
         }
-        if let Ok(target) = fs::read_link(&script.path()) {
+        if confirm(
+            &format!(
+                "Restore script {} of {}?",
+                style(&replaced.shim).cyan(),
+                replaced.tool
+            ),
+            true,
+        )? {
+            create_shim(&replaced.target, &shim)?;
+            if output != CommandOutput::Quiet {
+                eprintln!(
+                    "restored script {} of {}",
+                    style(&replaced.shim).cyan(),
+                    replaced.tool
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn uninstall_helper(target_venv_path: &Path, shim_dir: &Path) -> Result<(), Error> {
+    let receipt = ToolReceipt::load(target_venv_path).ok().flatten();
+
+    match fs::remove_dir_all(target_venv_path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            let msg = format!(
+                "unable to remove {}{}",
+                target_venv_path.display(),
+                permission_hint(&err)
+            );
+            return Err(Error::new(err).context(msg));
+        }
+        _ => {}
+    }
+
+    // the receipt knows which shims were created, otherwise fall back to
+    // looking for shims pointing into the virtualenv.
+    let shims = match receipt {
+        Some(receipt) if !receipt.shims.is_empty() => receipt
+            .shims
+            .iter()
+            .map(|x| shim_dir.join(x))
+            .collect::<Vec<_>>(),
+        _ => match fs::read_dir(shim_dir) {
+            Ok(iter) => iter
+                .map(|x| x.map(|x| x.path()))
+                .collect::<Result<_, _>>()?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err).context("unable to enumerate shims"),
+        },
+    };
+    for shim in shims {
+        if let Some(target) = read_shim_target(&shim) {
             if target.strip_prefix(target_venv_path).is_ok() {
-                fs::remove_file(&script.path())?;
+                fs::remove_file(&shim).map_err(|err| {
+                    let msg = format!(
+                        "unable to remove shim {}{}",
+                        shim.display(),
+                        permission_hint(&err)
+                    );
+                    Error::new(err).context(msg)
+                })?;
             }
         }
     }
 
     Ok(())
 }
+
+/// Returns a hint for io errors caused by missing permissions.
+fn permission_hint(err: &std::io::Error) -> &'static str {
+    if err.kind() == std::io::ErrorKind::PermissionDenied {
+        " (permission denied, check the ownership of the rye folder)"
+    } else {
+        ""
+    }
+}
+
+#[test]
+fn test_parse_tool_requirement() {
+    let req = parse_tool_requirement("black[d]>=23", CommandOutput::Quiet).unwrap();
+    assert_eq!(req.name, "black");
+    assert_eq!(req.extras, Some(vec!["d".to_string()]));
+    let req = parse_tool_requirement(
+        "git+https://github.com/psf/black@main#egg=black",
+        CommandOutput::Quiet,
+    )
+    .unwrap();
+    assert_eq!(req.name, "black");
+    assert_eq!(
+        req.to_string(),
+        "black @ git+https://github.com/psf/black@main#egg=black"
+    );
+}
+
+#[test]
+fn test_expand_version_shorthand() {
+    assert_eq!(
+        expand_version_shorthand("black@23.12.1"),
+        (Cow::Borrowed("black==23.12.1"), false)
+    );
+    assert_eq!(
+        expand_version_shorthand("httpie[brotli]@3.2.2"),
+        (Cow::Borrowed("httpie[brotli]==3.2.2"), false)
+    );
+    assert_eq!(
+        expand_version_shorthand("black@latest"),
+        (Cow::Borrowed("black"), true)
+    );
+    for s in [
+        "black",
+        "black>=23",
+        "black @ https://example.com/black.whl",
+        "git+https://git@github.com/psf/black",
+        "black@git+https://github.com/psf/black",
+    ] {
+        assert_eq!(expand_version_shorthand(s), (Cow::Borrowed(s), false));
+    }
+}
+
+#[test]
+fn test_parse_hashed_requirements() {
+    let mut file = NamedTempFile::new().unwrap();
+    std::io::Write::write_all(
+        &mut file,
+        b"# generated\n--index-url https://pypi.org/simple\nblack==23.1.0 \\\n    --hash=sha256:aaa \\\n    --hash=sha256:bbb\n    # via -r tools.in\nclick==8.1.3 --hash=sha256:ccc\n",
+    )
+    .unwrap();
+    let reqs = parse_hashed_requirements(file.path()).unwrap();
+    assert_eq!(
+        reqs.iter()
+            .map(|x| format_requirement(x).to_string())
+            .collect::<Vec<_>>(),
+        ["black==23.1.0", "click==8.1.3"]
+    );
+
+    std::io::Write::write_all(&mut file, b"ruff==0.1.0\n").unwrap();
+    let err = parse_hashed_requirements(file.path()).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("line 8: 'ruff==0.1.0' has no hash"));
+}
+
+#[test]
+fn test_begin_tool_install_replaces_old_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let shim_dir = dir.path().join("shims");
+    let installed = dir.path().join("tools").join("Foo_Bar");
+    let target = dir.path().join("tools").join("foo-bar");
+    fs::create_dir_all(installed.join("bin")).unwrap();
+    fs::create_dir_all(&shim_dir).unwrap();
+
+    let marker = begin_tool_install(&installed, &target, &shim_dir).unwrap();
+    assert!(!installed.exists());
+    assert_eq!(marker, target.join(TOOL_INCOMPLETE_MARKER));
+    assert!(marker.is_file());
+}
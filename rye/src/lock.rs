@@ -1,21 +1,38 @@
-use std::collections::HashMap;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::{fmt, fs};
 
 use anyhow::{anyhow, bail, Context, Error};
+use console::style;
 use once_cell::sync::Lazy;
+use pep440_rs::Version;
+use pep508_rs::Requirement;
 use regex::Regex;
 use tempfile::NamedTempFile;
 use url::Url;
 
-use crate::bootstrap::ensure_self_venv;
-use crate::pyproject::{normalize_package_name, DependencyKind, PyProject, Workspace};
-use crate::utils::CommandOutput;
+use crate::auth::{get_index_urls, IndexAuth};
+use crate::backend::{Backend, BackendKind};
+use crate::bootstrap::is_offline;
+use crate::config::get_generate_hashes;
+use crate::index::{has_version, list_index_versions, parse_dist_filename, PYPI_SIMPLE_URL};
+use crate::pyproject::{
+    get_source_options, normalize_package_name, DependencyKind, PyProject, SourceKind, SourceRef,
+    Workspace,
+};
+use crate::utils::{format_command, CommandOutput};
 
 static FILE_EDITABLE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^-e (file://.*?)\s*$").unwrap());
+static EXCLUDE_NEWER_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])(T([01]\d|2[0-3]):[0-5]\d:[0-5]\d(\.\d+)?(Z|[+-]\d{2}:\d{2}))?$",
+    )
+    .unwrap()
+});
 static REQUIREMENTS_HEADER: &str = "\
 # generated by rye\n\
 # use `rye lock` or `rye sync` to update this lockfile\
@@ -49,27 +66,516 @@ pub struct LockOptions {
     pub update: Vec<String>,
     /// Pick pre-release versions.
     pub pre: bool,
+    /// Keep lockfiles whose inputs did not change since they were generated.
+    pub keep_unchanged: bool,
+    /// Only check if the lockfiles are up to date instead of generating
+    /// the ones that are not.
+    pub check_only: bool,
+    /// Only report the changes without writing the lockfiles.
+    pub dry_run: bool,
+    /// The optional dependency groups to include.  If not set the groups
+    /// the lockfile was generated with are used.
+    pub features: Option<Vec<String>>,
+    /// Include all optional dependency groups.
+    pub all_features: bool,
+    /// Do not include hashes in the lockfiles.
+    pub no_hashes: bool,
+    /// Constraints files to resolve with in addition to the configured ones.
+    pub constraints: Vec<PathBuf>,
+    /// Use platform specific lockfiles for this platform.
+    pub platform: Option<String>,
+    /// The backend to use instead of the configured one.
+    pub backend: Option<BackendKind>,
+    /// The package sources of the project, see `tool.rye.sources`.
+    pub sources: Vec<SourceRef>,
+    /// Ignore distributions uploaded after this date.  If not set the date
+    /// the lockfile was generated with is used.
+    pub exclude_newer: Option<String>,
+    /// Stop ignoring distributions uploaded after a date.
+    pub clear_exclude_newer: bool,
 }
 
-fn get_pip_compile(output: CommandOutput) -> Result<PathBuf, Error> {
-    let mut pip_compile = ensure_self_venv(output)?;
-    pip_compile.push("bin");
-    pip_compile.push("pip-compile");
-    Ok(pip_compile)
+impl LockOptions {
+    /// Applies the pre-release setting of the project.
+    ///
+    /// When pre-releases are no longer allowed, the pre-releases pinned in
+    /// the lockfile are updated so that they go back to stable versions.
+    fn with_prereleases(&self, allow: bool, lockfile: &Path) -> Result<LockOptions, Error> {
+        let mut rv = self.clone();
+        rv.pre |= allow;
+        if !rv.pre && lockfile_allows_prereleases(lockfile) {
+            rv.update.extend(
+                read_locked_pins(lockfile)?
+                    .into_iter()
+                    .filter(|(_, version)| {
+                        Version::from_str(version).is_ok_and(|x| x.any_prerelease())
+                    })
+                    .map(|(name, _)| name),
+            );
+        }
+        Ok(rv)
+    }
+
+    /// Applies the `exclude-newer` date the lockfile was generated with
+    /// unless another one is given or it is cleared.
+    fn with_exclude_newer(mut self, lockfile: &Path) -> LockOptions {
+        if self.clear_exclude_newer {
+            self.exclude_newer = None;
+        } else if self.exclude_newer.is_none() {
+            self.exclude_newer = read_lockfile_exclude_newer(lockfile);
+        }
+        self
+    }
+
+    /// Checks if hashes should be generated.
+    pub fn generate_hashes(&self) -> Result<bool, Error> {
+        Ok(!self.no_hashes && get_generate_hashes()?)
+    }
+
+    /// Checks if an existing lockfile can be kept as it is.
+    fn can_keep(&self, lockfile: &Path, fingerprint: &str) -> bool {
+        self.keep_unchanged
+            && !self.update_all
+            && self.update.is_empty()
+            && fs::read_to_string(lockfile).is_ok_and(|x| {
+                x.lines()
+                    .any(|line| line.strip_prefix(INPUTS_PREFIX) == Some(fingerprint))
+            })
+    }
+}
+
+/// The prefix of the lockfile line that records the fingerprint of its inputs.
+static INPUTS_PREFIX: &str = "# inputs: ";
+/// The prefix of the lockfile line that records the included features.
+static FEATURES_PREFIX: &str = "# features: ";
+/// The lockfile line that records that pre-releases were allowed.
+static PRERELEASES_LINE: &str = "# pre-releases: allowed";
+/// The prefix of the lockfile line that records the `exclude-newer` date.
+static EXCLUDE_NEWER_PREFIX: &str = "# exclude-newer: ";
+/// The prefix of the lockfile lines that record the constraints files.
+static CONSTRAINTS_PREFIX: &str = "# constraints: ";
+/// Marks the pins only required by optional dependency groups with the
+/// groups that require them.
+static FEATURES_MARKER: &str = "# from features: ";
+/// Marks pins only required by optional dependency groups in older lockfiles.
+static LEGACY_FEATURE_ONLY_MARKER: &str = "# feature-only";
+/// Marks the pins of packages from a local find-links directory.
+static FIND_LINKS_MARKER: &str = "# from find-links: ";
+/// Marks the pins with the package index they were locked from.
+static SOURCE_MARKER: &str = "# from source: ";
+/// The name of the implicit PyPI source.
+static PYPI_SOURCE_NAME: &str = "pypi";
+
+/// Selects the features to lock out of the available ones.
+fn select_features(
+    available: &BTreeSet<String>,
+    lock_options: &LockOptions,
+    lockfile: &Path,
+) -> Result<Vec<String>, Error> {
+    if lock_options.all_features {
+        return Ok(available.iter().cloned().collect());
+    }
+    let Some(ref features) = lock_options.features else {
+        return Ok(read_lockfile_features(lockfile)
+            .into_iter()
+            .filter(|x| available.contains(x))
+            .collect());
+    };
+    let mut rv = BTreeSet::new();
+    for feature in features.iter().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        if !available.contains(feature) {
+            bail!(
+                "unknown feature '{}' (available: {})",
+                feature,
+                available.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+        rv.insert(feature.to_string());
+    }
+    Ok(rv.into_iter().collect())
+}
+
+/// Checks if a lockfile pins its packages with hashes.
+pub fn has_hashes(lockfile: &Path) -> bool {
+    fs::read_to_string(lockfile)
+        .is_ok_and(|x| x.lines().any(|line| line.trim().starts_with("--hash=")))
+}
+
+/// Checks if a lockfile was generated with pre-releases allowed.
+fn lockfile_allows_prereleases(lockfile: &Path) -> bool {
+    fs::read_to_string(lockfile).is_ok_and(|x| x.lines().any(|line| line == PRERELEASES_LINE))
+}
+
+/// Reads the features a lockfile was generated with.
+pub fn read_lockfile_features(lockfile: &Path) -> Vec<String> {
+    fs::read_to_string(lockfile)
+        .ok()
+        .and_then(|contents| {
+            contents.lines().find_map(|line| {
+                line.strip_prefix(FEATURES_PREFIX)
+                    .map(|x| x.split(',').map(|x| x.trim().to_string()).collect())
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// Reads the `exclude-newer` date a lockfile was generated with.
+fn read_lockfile_exclude_newer(lockfile: &Path) -> Option<String> {
+    fs::read_to_string(lockfile).ok().and_then(|contents| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(EXCLUDE_NEWER_PREFIX))
+            .map(|x| x.trim().to_string())
+    })
+}
+
+/// Validates an `exclude-newer` date.
+///
+/// This is either a date like `2024-01-01` or an RFC 3339 timestamp.
+pub fn parse_exclude_newer(value: &str) -> Result<String, Error> {
+    if !EXCLUDE_NEWER_RE.is_match(value) {
+        bail!(
+            "invalid date '{}', expected a date like 2024-01-01 or a timestamp like \
+             2024-01-01T12:00:00Z",
+            value
+        );
+    }
+    Ok(value.to_string())
+}
+
+/// A FNV-1a hasher for the fingerprints written into lockfiles.
+///
+/// Unlike the hashers of the standard library this is stable across rye
+/// versions and platforms.
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn new() -> Fingerprint {
+        Fingerprint(0xcbf29ce484222325)
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().chain(b"\0") {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Fingerprints the inputs of a lockfile.
+fn fingerprint_inputs(
+    lock_mode: LockMode,
+    lock_options: &LockOptions,
+    features: &[String],
+    inputs: &[&Path],
+    workspace_path: &Path,
+) -> Result<String, Error> {
+    let mut hash = Fingerprint::new();
+    hash.feed(lock_mode.to_string().as_bytes());
+    hash.feed(if lock_options.pre { b"pre" } else { b"" });
+    hash.feed(if lock_options.generate_hashes()? {
+        b"hashes"
+    } else {
+        b""
+    });
+    hash.feed(features.join(",").as_bytes());
+    hash.feed(
+        lock_options
+            .exclude_newer
+            .as_deref()
+            .unwrap_or("")
+            .as_bytes(),
+    );
+    for option in get_source_options(&lock_options.sources) {
+        hash.feed(option.as_bytes());
+    }
+    // new distributions in a find-links directory can change the resolution
+    for (_, dir) in get_find_links_dirs(lock_options, workspace_path)? {
+        for (name, version) in list_find_links_dir(&dir)? {
+            hash.feed(format!("{}=={}", name, version).as_bytes());
+        }
+    }
+    for input in inputs {
+        hash.feed(&fs::read(input)?);
+    }
+    Ok(hash.finish())
+}
+
+/// Collects the constraints files to resolve with.
+///
+/// Besides the ones passed in the lock options these are configured with
+/// `tool.rye.constraints` relative to the pyproject.toml.
+fn collect_constraints(
+    lock_options: &LockOptions,
+    pyproject: Option<&PyProject>,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut rv = Vec::new();
+    let configured = pyproject.into_iter().flat_map(|x| {
+        x.constraints()
+            .into_iter()
+            .map(|path| x.root_path().join(path))
+    });
+    for path in lock_options.constraints.iter().cloned().chain(configured) {
+        if !path.is_file() {
+            bail!("constraints file {} does not exist", path.display());
+        }
+        let path = path.canonicalize()?;
+        if !rv.contains(&path) {
+            rv.push(path);
+        }
+    }
+    Ok(rv)
+}
+
+/// Returns the lines of the lockfile header that record how it was generated.
+fn header_lines(
+    fingerprint: &str,
+    lock_options: &LockOptions,
+    constraints: &[PathBuf],
+    workspace_path: &Path,
+) -> Result<Vec<String>, Error> {
+    let mut rv = vec![format!("{}{}", INPUTS_PREFIX, fingerprint)];
+    if lock_options.pre {
+        rv.push(PRERELEASES_LINE.to_string());
+    }
+    if let Some(ref date) = lock_options.exclude_newer {
+        rv.push(format!("{}{}", EXCLUDE_NEWER_PREFIX, date));
+    }
+    for path in constraints {
+        let mut hash = Fingerprint::new();
+        hash.feed(&fs::read(path)?);
+        rv.push(format!(
+            "{}{} ({})",
+            CONSTRAINTS_PREFIX,
+            pathdiff::diff_paths(path, workspace_path)
+                .as_deref()
+                .unwrap_or(path)
+                .display(),
+            hash.finish()
+        ));
+    }
+    // pip-sync and uv install from the sources the lockfile was resolved with
+    rv.extend(get_source_options(&lock_options.sources));
+    Ok(rv)
+}
+
+/// Checks if a lockfile was generated from the current state of a project.
+///
+/// This compares the fingerprint of the inputs with the one recorded in the
+/// lockfile without resolving anything.
+pub fn is_lockfile_up_to_date(
+    pyproject: &PyProject,
+    lock_mode: LockMode,
+    lockfile: &Path,
+) -> Result<bool, Error> {
+    let lock_options = LockOptions {
+        keep_unchanged: true,
+        check_only: true,
+        ..Default::default()
+    };
+    match pyproject.workspace() {
+        Some(workspace) => update_workspace_lockfile(
+            workspace,
+            lock_mode,
+            lockfile,
+            CommandOutput::Quiet,
+            &lock_options,
+        ),
+        None => update_single_project_lockfile(
+            pyproject,
+            lock_mode,
+            lockfile,
+            CommandOutput::Quiet,
+            &lock_options,
+        ),
+    }
+}
+
+/// Returns the production and dev lockfiles in a workspace.
+///
+/// Platform specific lockfiles are named after the platform and are used
+/// instead of the regular ones if they exist for the current platform.  As
+/// markers are evaluated when resolving, they can only be generated on the
+/// platform itself.
+pub fn get_lockfiles(
+    workspace_path: &Path,
+    platform: Option<&str>,
+) -> Result<(PathBuf, PathBuf), Error> {
+    let platform = match platform {
+        Some(platform) if platform != std::env::consts::OS => {
+            bail!(
+                "lockfiles for {} can only be generated on {} (this is {})",
+                platform,
+                platform,
+                std::env::consts::OS
+            );
+        }
+        Some(platform) => Some(platform),
+        None => Some(std::env::consts::OS).filter(|x| {
+            workspace_path
+                .join(format!("requirements.{}.lock", x))
+                .is_file()
+        }),
+    };
+    Ok(match platform {
+        Some(platform) => (
+            workspace_path.join(format!("requirements.{}.lock", platform)),
+            workspace_path.join(format!("requirements-dev.{}.lock", platform)),
+        ),
+        None => (
+            workspace_path.join("requirements.lock"),
+            workspace_path.join("requirements-dev.lock"),
+        ),
+    })
+}
+
+/// Makes sure that the packages requested to be updated are locked.
+///
+/// Updating relaxes the pins of already locked packages, so a package that
+/// is in none of the lockfiles is an error rather than silently added.
+pub fn ensure_updatable(lock_options: &LockOptions, lockfiles: &[&Path]) -> Result<(), Error> {
+    if lock_options.update.is_empty() {
+        return Ok(());
+    }
+    let mut locked = HashSet::new();
+    for lockfile in lockfiles {
+        if let Ok(contents) = fs::read_to_string(lockfile) {
+            locked.extend(contents.lines().filter_map(|x| locked_pin(x).map(|x| x.0)));
+        }
+    }
+    for pkg in &lock_options.update {
+        if !locked.contains(&normalize_package_name(pkg)) {
+            bail!("cannot update '{}' because it is not in the lockfile", pkg);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the normalized name and version of the package pinned by a
+/// lockfile line.
+fn locked_pin(line: &str) -> Option<(String, String)> {
+    let line = line.split(" #").next().unwrap_or("").trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(line.len());
+    let version = line[end..]
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim_end_matches('\\')
+        .trim();
+    Some((
+        normalize_package_name(&line[..end]),
+        version.strip_prefix("==").unwrap_or(version).to_string(),
+    ))
+}
+
+/// Reads the pins of a lockfile.
+pub fn read_locked_pins(lockfile: &Path) -> Result<BTreeMap<String, String>, Error> {
+    if !lockfile.is_file() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(fs::read_to_string(lockfile)?
+        .lines()
+        .filter_map(locked_pin)
+        .collect())
+}
+
+/// Formats the differences between two sets of pins as a list.
+fn format_pin_changes(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut rv = Vec::new();
+    for (name, version) in old {
+        match new.get(name) {
+            Some(new_version) if new_version != version => {
+                rv.push(format!("- {}: {} → {}", name, version, new_version));
+            }
+            Some(_) => {}
+            None => rv.push(format!("- {}: {} (removed)", name, version)),
+        }
+    }
+    for (name, version) in new {
+        if !old.contains_key(name) {
+            rv.push(format!("- {}: {} (added)", name, version));
+        }
+    }
+    rv
+}
+
+/// Prints the pins that differ between two versions of a lockfile.
+pub fn report_pin_changes(lock_mode: LockMode, old: &Path, new: &Path) -> Result<(), Error> {
+    let changes = format_pin_changes(&read_locked_pins(old)?, &read_locked_pins(new)?);
+    if !changes.is_empty() {
+        println!("Changes to {} lockfile:", lock_mode);
+        for change in changes {
+            println!("{}", change);
+        }
+    }
+    Ok(())
+}
+
+/// Generates a lockfile and reports the pins that changed.
+///
+/// On dry runs the lockfile is generated into a scratch copy and only the
+/// changes are reported.
+fn generate_and_report<F>(
+    lock_mode: LockMode,
+    lockfile: &Path,
+    output: CommandOutput,
+    lock_options: &LockOptions,
+    generate: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&Path) -> Result<(), Error>,
+{
+    let old_pins = read_locked_pins(lockfile)?;
+    let scratch = tempfile::tempdir()?;
+    let target = if lock_options.dry_run {
+        let target = scratch.path().join("requirements.lock");
+        if lockfile.is_file() {
+            fs::copy(lockfile, &target)?;
+        }
+        target
+    } else {
+        lockfile.to_path_buf()
+    };
+    generate(&target)?;
+
+    if output == CommandOutput::Quiet && !lock_options.dry_run {
+        return Ok(());
+    }
+    let changes = format_pin_changes(&old_pins, &read_locked_pins(&target)?);
+    if !changes.is_empty() {
+        println!("Changes to {} lockfile:", lock_mode);
+        for change in changes {
+            println!("{}", change);
+        }
+    } else if lock_options.dry_run {
+        eprintln!("No changes to {} lockfile", lock_mode);
+    }
+    Ok(())
 }
 
 /// Creates lockfiles for all projects in the workspace.
+///
+/// Returns whether the lockfile is up to date, which is only not the case
+/// if `check_only` kept it from being generated.
 pub fn update_workspace_lockfile(
     workspace: &Arc<Workspace>,
     lock_mode: LockMode,
     lockfile: &Path,
     output: CommandOutput,
     lock_options: &LockOptions,
-) -> Result<(), Error> {
-    if output != CommandOutput::Quiet {
-        eprintln!("Generating {} lockfile: {}", lock_mode, lockfile.display());
-    }
-
+) -> Result<bool, Error> {
     let mut req_file = NamedTempFile::new()?;
     let mut local_req_file = NamedTempFile::new()?;
 
@@ -85,12 +591,36 @@ pub fn update_workspace_lockfile(
         projects.push(pyproject);
     }
 
+    let allow_prereleases = projects
+        .iter()
+        .any(|x| x.root_path() == workspace.path() && x.allow_prereleases());
+    let lock_options = &LockOptions {
+        sources: match projects.first() {
+            Some(pyproject) => pyproject.sources()?,
+            None => Vec::new(),
+        },
+        ..lock_options
+            .with_prereleases(allow_prereleases, lockfile)?
+            .with_exclude_newer(lockfile)
+    };
+    let constraints = collect_constraints(
+        lock_options,
+        projects.iter().find(|x| x.root_path() == workspace.path()),
+    )?;
+    let available = projects
+        .iter()
+        .flat_map(|x| x.optional_dependency_groups())
+        .collect();
+    let features = select_features(&available, lock_options, lockfile)?;
+    let mut feature_req_file = NamedTempFile::new()?;
+    let mut conditional = ConditionalDependencies::new()?;
     for pyproject in projects {
         dump_dependencies(
             &pyproject,
             &local_projects,
             req_file.as_file_mut(),
             DependencyKind::Normal,
+            &mut conditional,
         )?;
         if lock_mode == LockMode::Dev {
             dump_dependencies(
@@ -98,27 +628,200 @@ pub fn update_workspace_lockfile(
                 &local_projects,
                 req_file.as_file_mut(),
                 DependencyKind::Dev,
+                &mut conditional,
+            )?;
+        }
+        for feature in &features {
+            dump_dependencies(
+                &pyproject,
+                &local_projects,
+                feature_req_file.as_file_mut(),
+                DependencyKind::Optional(feature.as_str().into()),
+                &mut conditional,
             )?;
         }
     }
 
-    generate_lockfile(
-        output,
-        &workspace.path(),
-        req_file.path(),
-        lockfile,
+    let mut requirements = vec![req_file.path()];
+    requirements.extend(conditional.paths());
+    requirements.push(feature_req_file.path());
+    let mut inputs = requirements.clone();
+    inputs.push(local_req_file.path());
+    inputs.extend(constraints.iter().map(|x| x.as_path()));
+    let fingerprint = fingerprint_inputs(
+        lock_mode,
         lock_options,
-        &[],
-    )?;
-    generate_lockfile(
-        output,
+        &features,
+        &inputs,
         &workspace.path(),
-        local_req_file.path(),
-        lockfile,
-        lock_options,
-        &["--pip-args=--no-deps"],
     )?;
+    let header = header_lines(&fingerprint, lock_options, &constraints, &workspace.path())?;
+    write_constraints(req_file.as_file(), &constraints)?;
+    if lock_options.can_keep(lockfile, &fingerprint) {
+        return Ok(true);
+    }
+    if lock_options.check_only {
+        return Ok(false);
+    }
+    if output != CommandOutput::Quiet {
+        eprintln!("Generating {} lockfile: {}", lock_mode, lockfile.display());
+    }
+
+    generate_and_report(lock_mode, lockfile, output, lock_options, |lockfile| {
+        generate_lockfile(
+            output,
+            &workspace.path(),
+            &requirements,
+            lockfile,
+            lock_options,
+            false,
+            &header,
+        )?;
+        generate_lockfile(
+            output,
+            &workspace.path(),
+            &[local_req_file.path()],
+            lockfile,
+            lock_options,
+            true,
+            &header,
+        )?;
+        apply_markers(
+            output,
+            &workspace.path(),
+            &[req_file.path(), feature_req_file.path()],
+            lockfile,
+            lock_options,
+            &conditional,
+        )?;
+        mark_feature_pins(
+            output,
+            &workspace.path(),
+            &[req_file.path(), conditional.file.path()],
+            &conditional.feature_paths(),
+            lockfile,
+            lock_options,
+        )?;
+        mark_find_links_pins(&workspace.path(), lockfile, lock_options)?;
+        mark_source_pins(lockfile, lock_options, output)
+    })?;
+    Ok(true)
+}
+
+/// The dependencies with environment markers.
+///
+/// These are resolved without their markers, see [`apply_markers`].  The
+/// ones of features are kept apart and the dependencies of every feature
+/// are also collected on their own, see [`mark_feature_pins`].
+struct ConditionalDependencies {
+    file: NamedTempFile,
+    feature_file: NamedTempFile,
+    features: BTreeMap<String, NamedTempFile>,
+    markers: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl ConditionalDependencies {
+    fn new() -> Result<ConditionalDependencies, Error> {
+        Ok(ConditionalDependencies {
+            file: NamedTempFile::new()?,
+            feature_file: NamedTempFile::new()?,
+            features: BTreeMap::new(),
+            markers: BTreeMap::new(),
+        })
+    }
+
+    /// Records the marker of a dependency and returns the dependency
+    /// without it.
+    fn strip_marker(&mut self, dep: &Requirement) -> Option<Requirement> {
+        let marker = dep.marker.as_ref()?;
+        self.markers
+            .entry(normalize_package_name(&dep.name))
+            .or_default()
+            .insert(marker.to_string());
+        Some(Requirement {
+            marker: None,
+            ..dep.clone()
+        })
+    }
+
+    /// Writes a dependency, setting it aside if it has a marker.
+    fn write(&mut self, out: &mut fs::File, dep: &Requirement) -> Result<(), Error> {
+        match self.strip_marker(dep) {
+            Some(unconditional) => writeln!(self.file, "{}", unconditional)?,
+            None => writeln!(out, "{}", dep)?,
+        }
+        Ok(())
+    }
 
+    /// Writes a dependency of a feature, setting it aside if it has a marker.
+    fn write_feature(
+        &mut self,
+        feature: &str,
+        out: &mut fs::File,
+        dep: &Requirement,
+    ) -> Result<(), Error> {
+        let unconditional = match self.strip_marker(dep) {
+            Some(unconditional) => {
+                writeln!(self.feature_file, "{}", unconditional)?;
+                unconditional
+            }
+            None => {
+                writeln!(out, "{}", dep)?;
+                dep.clone()
+            }
+        };
+        self.write_feature_line(feature, &unconditional.to_string())
+    }
+
+    /// Adds a line to the requirements of a single feature.
+    fn write_feature_line(&mut self, feature: &str, line: &str) -> Result<(), Error> {
+        let file = match self.features.entry(feature.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(NamedTempFile::new()?),
+        };
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+
+    /// Returns the files with the conditional dependencies.
+    fn paths(&self) -> [&Path; 2] {
+        [self.file.path(), self.feature_file.path()]
+    }
+
+    /// Returns the requirements of every feature.
+    fn feature_paths(&self) -> Vec<(&str, &Path)> {
+        self.features
+            .iter()
+            .map(|(feature, file)| (feature.as_str(), file.path()))
+            .collect()
+    }
+
+    /// Returns the marker for the pin of a package.
+    fn marker_for(&self, name: &str) -> String {
+        let markers = match self.markers.get(name) {
+            Some(markers) => markers.iter().collect::<Vec<_>>(),
+            None => self.markers.values().flatten().collect(),
+        };
+        if markers.len() == 1 {
+            markers[0].clone()
+        } else {
+            markers
+                .iter()
+                .map(|x| format!("({})", x))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        }
+    }
+}
+
+/// Writes the constraints files into a requirements file.
+///
+/// This happens after fingerprinting as the absolute paths would otherwise
+/// make the fingerprint depend on the location of the project.
+fn write_constraints(mut out: &fs::File, constraints: &[PathBuf]) -> Result<(), Error> {
+    for path in constraints {
+        writeln!(out, "-c {}", shlex::quote(&path.to_string_lossy()))?;
+    }
     Ok(())
 }
 
@@ -127,7 +830,12 @@ fn dump_dependencies(
     local_projects: &HashMap<String, String>,
     out: &mut fs::File,
     dep_kind: DependencyKind,
+    conditional: &mut ConditionalDependencies,
 ) -> Result<(), Error> {
+    let feature = match dep_kind {
+        DependencyKind::Optional(ref feature) => Some(feature.to_string()),
+        _ => None,
+    };
     for dep in pyproject.iter_dependencies(dep_kind) {
         if let Some(path) = local_projects.get(&normalize_package_name(&dep.name)) {
             // if there are extras and we have a local dependency, we just write it
@@ -135,61 +843,629 @@ fn dump_dependencies(
             // XXX: this drops the marker, but pip-compile already has other
             // problems with markers too: https://github.com/jazzband/pip-tools/issues/826
             if let Some(ref extras) = dep.extras {
-                writeln!(out, "-e {}[{}]", path, extras.join(","))?;
+                let line = format!("-e {}[{}]", path, extras.join(","));
+                writeln!(out, "{}", line)?;
+                if let Some(ref feature) = feature {
+                    conditional.write_feature_line(feature, &line)?;
+                }
             }
+        } else if let Some(ref feature) = feature {
+            conditional.write_feature(feature, out, &dep)?;
         } else {
-            writeln!(out, "{}", dep)?;
+            conditional.write(out, &dep)?;
         }
     }
     Ok(())
 }
 
 /// Updates the lockfile of the current project.
+///
+/// Returns whether the lockfile is up to date like
+/// [`update_workspace_lockfile`].
 pub fn update_single_project_lockfile(
     pyproject: &PyProject,
     lock_mode: LockMode,
     lockfile: &Path,
     output: CommandOutput,
     lock_options: &LockOptions,
-) -> Result<(), Error> {
-    if output != CommandOutput::Quiet {
-        eprintln!("Generating {} lockfile: {}", lock_mode, lockfile.display());
-    }
-
+) -> Result<bool, Error> {
     let mut req_file = NamedTempFile::new()?;
     writeln!(
         req_file,
         "-e {}",
         make_relative_url(&pyproject.root_path(), &pyproject.workspace_path())?
     )?;
+    let mut conditional = ConditionalDependencies::new()?;
     for dep in pyproject.iter_dependencies(DependencyKind::Normal) {
-        writeln!(req_file, "{}", dep)?;
+        conditional.write(req_file.as_file_mut(), &dep)?;
     }
     if lock_mode == LockMode::Dev {
         for dep in pyproject.iter_dependencies(DependencyKind::Dev) {
-            writeln!(req_file, "{}", dep)?;
+            conditional.write(req_file.as_file_mut(), &dep)?;
+        }
+    }
+    let lock_options = &LockOptions {
+        sources: pyproject.sources()?,
+        ..lock_options
+            .with_prereleases(pyproject.allow_prereleases(), lockfile)?
+            .with_exclude_newer(lockfile)
+    };
+    let constraints = collect_constraints(lock_options, Some(pyproject))?;
+    let available = pyproject.optional_dependency_groups().into_iter().collect();
+    let features = select_features(&available, lock_options, lockfile)?;
+    let mut feature_req_file = NamedTempFile::new()?;
+    for feature in &features {
+        for dep in pyproject.iter_dependencies(DependencyKind::Optional(feature.as_str().into())) {
+            conditional.write_feature(feature, feature_req_file.as_file_mut(), &dep)?;
         }
     }
 
-    generate_lockfile(
-        output,
+    let mut requirements = vec![req_file.path()];
+    requirements.extend(conditional.paths());
+    requirements.push(feature_req_file.path());
+    let mut inputs = requirements.clone();
+    inputs.extend(constraints.iter().map(|x| x.as_path()));
+    let fingerprint = fingerprint_inputs(
+        lock_mode,
+        lock_options,
+        &features,
+        &inputs,
+        &pyproject.workspace_path(),
+    )?;
+    let header = header_lines(
+        &fingerprint,
+        lock_options,
+        &constraints,
         &pyproject.workspace_path(),
-        req_file.path(),
+    )?;
+    write_constraints(req_file.as_file(), &constraints)?;
+    if lock_options.can_keep(lockfile, &fingerprint) {
+        return Ok(true);
+    }
+    if lock_options.check_only {
+        return Ok(false);
+    }
+    if output != CommandOutput::Quiet {
+        eprintln!("Generating {} lockfile: {}", lock_mode, lockfile.display());
+    }
+
+    generate_and_report(lock_mode, lockfile, output, lock_options, |lockfile| {
+        generate_lockfile(
+            output,
+            &pyproject.workspace_path(),
+            &requirements,
+            lockfile,
+            lock_options,
+            false,
+            &header,
+        )?;
+        apply_markers(
+            output,
+            &pyproject.workspace_path(),
+            &[req_file.path(), feature_req_file.path()],
+            lockfile,
+            lock_options,
+            &conditional,
+        )?;
+        mark_feature_pins(
+            output,
+            &pyproject.workspace_path(),
+            &[req_file.path(), conditional.file.path()],
+            &conditional.feature_paths(),
+            lockfile,
+            lock_options,
+        )?;
+        mark_find_links_pins(&pyproject.workspace_path(), lockfile, lock_options)?;
+        mark_source_pins(lockfile, lock_options, output)
+    })?;
+    Ok(true)
+}
+
+/// Marks the pins that are only required by the selected features.
+///
+/// The dependencies are resolved again without the features and with every
+/// single feature.  Each pin missing from the resolution without features
+/// is marked with the features that require it, so that syncing can leave
+/// out the features that are not selected.
+fn mark_feature_pins(
+    output: CommandOutput,
+    workspace_path: &Path,
+    base_requirements: &[&Path],
+    features: &[(&str, &Path)],
+    lockfile: &Path,
+    lock_options: &LockOptions,
+) -> Result<(), Error> {
+    if features.is_empty() {
+        return Ok(());
+    }
+    let base_pins = resolve_base_pins(
+        output,
+        workspace_path,
+        base_requirements,
+        lockfile,
+        lock_options,
+    )?;
+    let mut origins = BTreeMap::<String, Vec<&str>>::new();
+    for &(feature, requirements) in features {
+        let mut feature_requirements = base_requirements.to_vec();
+        feature_requirements.push(requirements);
+        let feature_pins = resolve_base_pins(
+            output,
+            workspace_path,
+            &feature_requirements,
+            lockfile,
+            lock_options,
+        )?;
+        for name in feature_pins.into_keys() {
+            if !base_pins.contains_key(&name) {
+                origins.entry(name).or_default().push(feature);
+            }
+        }
+    }
+    let all_features = features.iter().map(|x| x.0).collect::<Vec<_>>();
+
+    // the marker goes on the last line of an entry as a comment would
+    // otherwise swallow the hashes on the continuation lines.
+    let mut rv = String::new();
+    let mut mark = None;
+    for line in fs::read_to_string(lockfile)?.lines() {
+        rv.push_str(line);
+        if line.starts_with(INPUTS_PREFIX) {
+            rv.push('\n');
+            rv.push_str(FEATURES_PREFIX);
+            rv.push_str(&all_features.join(","));
+        } else if let Some((name, _)) = locked_pin(line) {
+            mark = (!base_pins.contains_key(&name))
+                .then(|| origins.get(&name).unwrap_or(&all_features));
+        }
+        if !line.ends_with('\\') {
+            if let Some(features) = mark.take() {
+                rv.push_str("  ");
+                rv.push_str(FEATURES_MARKER);
+                rv.push_str(&features.join(","));
+            }
+        }
+        rv.push('\n');
+    }
+    fs::write(lockfile, rv)?;
+    Ok(())
+}
+
+/// Returns the features a lockfile line marks its pin as required by.
+///
+/// Pins required without any features are not marked.
+fn pin_features(line: &str) -> Option<Vec<&str>> {
+    if line.trim_end().ends_with(LEGACY_FEATURE_ONLY_MARKER) {
+        return Some(Vec::new());
+    }
+    let (_, features) = line.split_once(FEATURES_MARKER)?;
+    let features = features.split("  #").next().unwrap_or("");
+    Some(features.split(',').map(|x| x.trim()).collect())
+}
+
+/// Removes the pins of the features that are not selected from a lockfile.
+///
+/// Pins of older lockfiles that do not record their features are kept if
+/// any feature is selected.
+pub fn select_lockfile_features(contents: &str, features: &[String]) -> String {
+    let mut rv = String::new();
+    let mut entry = String::new();
+    for line in contents.lines() {
+        entry.push_str(line);
+        entry.push('\n');
+        if line.ends_with('\\') {
+            continue;
+        }
+        let keep = match pin_features(line) {
+            Some(origins) if origins.is_empty() => !features.is_empty(),
+            Some(origins) => origins.iter().any(|x| features.iter().any(|y| x == y)),
+            None => true,
+        };
+        if keep {
+            rv.push_str(&entry);
+        }
+        entry.clear();
+    }
+    rv.push_str(&entry);
+    rv
+}
+
+/// Turns a lockfile into a requirements file that pip installs without rye.
+///
+/// The local projects and the comments of rye are left out and the pins are
+/// sorted by name.  If features are given, only their pins are kept like
+/// with [`select_lockfile_features`].
+pub fn export_lockfile(contents: &str, features: Option<&[String]>, hashes: bool) -> String {
+    fn strip_comment(line: &str) -> &str {
+        line.split("  #").next().unwrap_or("").trim_end()
+    }
+
+    let contents = match features {
+        Some(features) => select_lockfile_features(contents, features),
+        None => contents.to_string(),
+    };
+    let mut options = Vec::new();
+    let mut pins = Vec::new();
+    let mut lines = contents.lines();
+    while let Some(first) = lines.next() {
+        let mut entry = vec![first];
+        while entry.last().is_some_and(|x| x.ends_with('\\')) {
+            match lines.next() {
+                Some(line) => entry.push(line),
+                None => break,
+            }
+        }
+        let first = first.trim();
+        if first.is_empty() || first.starts_with('#') || first.starts_with("-e ") {
+            continue;
+        }
+        if first.starts_with('-') {
+            options.push(first.to_string());
+            continue;
+        }
+        let Some((name, _)) = locked_pin(first) else {
+            continue;
+        };
+        let pin = if hashes {
+            entry
+                .iter()
+                .map(|x| strip_comment(x))
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            strip_comment(first)
+                .trim_end_matches('\\')
+                .trim_end()
+                .to_string()
+        };
+        pins.push((name, pin));
+    }
+    pins.sort();
+
+    let mut rv = String::new();
+    for line in options
+        .into_iter()
+        .chain(pins.into_iter().map(|(_, pin)| pin))
+    {
+        rv.push_str(&line);
+        rv.push('\n');
+    }
+    rv
+}
+
+/// Returns the local find-links directories of the sources.
+///
+/// Each directory is returned along with its path relative to the workspace.
+/// A missing directory is an error.
+fn get_find_links_dirs(
+    lock_options: &LockOptions,
+    workspace_path: &Path,
+) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut rv = Vec::new();
+    for source in &lock_options.sources {
+        if let Some(dir) = source.local_path(workspace_path) {
+            if !dir.is_dir() {
+                bail!(
+                    "find-links directory {} of package source '{}' does not exist",
+                    dir.display(),
+                    source.name
+                );
+            }
+            let rel_path = pathdiff::diff_paths(&dir, workspace_path).unwrap_or(dir.clone());
+            rv.push((rel_path.to_string_lossy().into_owned(), dir));
+        }
+    }
+    Ok(rv)
+}
+
+/// Lists the normalized names and versions of the distributions in a
+/// find-links directory.
+fn list_find_links_dir(dir: &Path) -> Result<BTreeSet<(String, String)>, Error> {
+    let mut rv = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let filename = entry?.file_name();
+        rv.extend(parse_dist_filename(&filename.to_string_lossy()));
+    }
+    Ok(rv)
+}
+
+/// Marks the pins that are provided by local find-links directories.
+///
+/// This lets a sync fail with a clear error when the directory is missing
+/// on another machine.
+fn mark_find_links_pins(
+    workspace_path: &Path,
+    lockfile: &Path,
+    lock_options: &LockOptions,
+) -> Result<(), Error> {
+    let mut provided = BTreeMap::new();
+    for (url, dir) in get_find_links_dirs(lock_options, workspace_path)? {
+        for dist in list_find_links_dir(&dir)? {
+            provided.entry(dist).or_insert_with(|| url.clone());
+        }
+    }
+    if provided.is_empty() {
+        return Ok(());
+    }
+
+    let mut rv = String::new();
+    let mut mark = None;
+    for line in fs::read_to_string(lockfile)?.lines() {
+        rv.push_str(line);
+        if let Some(pin) = locked_pin(line) {
+            mark = provided.get(&pin);
+        }
+        if !line.ends_with('\\') {
+            if let Some(url) = mark.take() {
+                rv.push_str("  ");
+                rv.push_str(FIND_LINKS_MARKER);
+                rv.push_str(url);
+            }
+        }
+        rv.push('\n');
+    }
+    fs::write(lockfile, rv)?;
+    Ok(())
+}
+
+/// Makes sure the find-links directories that packages were locked from
+/// exist.
+pub fn ensure_find_links(lockfile: &Path, workspace_path: &Path) -> Result<(), Error> {
+    let mut pin = None;
+    for line in fs::read_to_string(lockfile)?.lines() {
+        if let Some(line_pin) = locked_pin(line) {
+            pin = Some(line_pin);
+        }
+        let Some((_, dir)) = line.split_once(FIND_LINKS_MARKER) else {
+            continue;
+        };
+        if !workspace_path.join(dir.trim()).is_dir() {
+            let (name, version) = pin.take().unwrap_or_default();
+            bail!(
+                "{}=={} was locked from the find-links directory {} which does not exist",
+                name,
+                version,
+                dir.trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the names and URLs of the package indexes to resolve from.
+///
+/// Unless a source replaces it, PyPI comes first.
+fn get_index_sources(sources: &[SourceRef]) -> Vec<(String, String)> {
+    let mut rv = Vec::new();
+    if !sources.iter().any(|x| x.name == SourceRef::DEFAULT_NAME) {
+        rv.push((PYPI_SOURCE_NAME.to_string(), PYPI_SIMPLE_URL.to_string()));
+    }
+    for source in sources {
+        match source.kind {
+            SourceKind::Index if source.name == SourceRef::DEFAULT_NAME => {
+                rv.insert(0, (source.name.clone(), source.url.clone()))
+            }
+            SourceKind::Index => rv.push((source.name.clone(), source.url.clone())),
+            SourceKind::FindLinks => {}
+        }
+    }
+    rv
+}
+
+/// Finds the package indexes that offer a version of a package.
+fn find_offering_sources(
+    indexes: &[(String, String)],
+    name: &str,
+    version: &str,
+    output: CommandOutput,
+) -> Result<Vec<String>, Error> {
+    let mut rv = Vec::new();
+    for (source, url) in indexes {
+        if has_version(&list_index_versions(url, name, output)?, version) {
+            rv.push(source.clone());
+        }
+    }
+    Ok(rv)
+}
+
+/// Marks the pins with the package index they were locked from.
+///
+/// This is only done when there is more than one index, as pip merges the
+/// packages of all indexes.  The first index that offers the pinned version
+/// is recorded and packages offered by several indexes are warned about.
+fn mark_source_pins(
+    lockfile: &Path,
+    lock_options: &LockOptions,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    let indexes = get_index_sources(&lock_options.sources);
+    if indexes.len() < 2 || is_offline() {
+        return Ok(());
+    }
+
+    let mut rv = String::new();
+    let mut mark = None;
+    for line in fs::read_to_string(lockfile)?.lines() {
+        rv.push_str(line);
+        if let Some((name, version)) = locked_pin(line).filter(|(_, x)| !x.starts_with('@')) {
+            let offering = find_offering_sources(&indexes, &name, &version, output)?;
+            if offering.len() > 1 && output != CommandOutput::Quiet {
+                eprintln!(
+                    "{} {}=={} is offered by several sources ({}), locked from '{}'",
+                    style("warning:").yellow(),
+                    name,
+                    version,
+                    offering.join(", "),
+                    offering[0]
+                );
+            }
+            mark = offering.into_iter().next();
+        }
+        if !line.ends_with('\\') {
+            if let Some(source) = mark.take() {
+                if !line.contains(FIND_LINKS_MARKER) {
+                    rv.push_str("  ");
+                    rv.push_str(SOURCE_MARKER);
+                    rv.push_str(&source);
+                }
+            }
+        }
+        rv.push('\n');
+    }
+    fs::write(lockfile, rv)?;
+    Ok(())
+}
+
+/// Makes sure the package indexes that packages were locked from are still
+/// configured.
+///
+/// In strict mode this fails instead of warning, and also if a pinned
+/// version is offered by another index than the one it was locked from.
+/// Neither pip nor uv can install a package from a particular index, so
+/// such a package could be installed from the wrong one.
+pub fn ensure_sources(
+    lockfile: &Path,
+    sources: &[SourceRef],
+    strict: bool,
+    output: CommandOutput,
+) -> Result<(), Error> {
+    let indexes = get_index_sources(sources);
+    let mut pin = None;
+    for line in fs::read_to_string(lockfile)?.lines() {
+        if let Some(line_pin) = locked_pin(line) {
+            pin = Some(line_pin);
+        }
+        let Some((_, source)) = line.split_once(SOURCE_MARKER) else {
+            continue;
+        };
+        let source = source.split("  #").next().unwrap_or("").trim();
+        let (name, version) = pin.take().unwrap_or_default();
+        if !indexes.iter().any(|(x, _)| x == source) {
+            if strict {
+                bail!(
+                    "{}=={} was locked from package source '{}' which is not configured",
+                    name,
+                    version,
+                    source
+                );
+            }
+            if output != CommandOutput::Quiet {
+                eprintln!(
+                    "{} {}=={} was locked from package source '{}' which is not configured",
+                    style("warning:").yellow(),
+                    name,
+                    version,
+                    source
+                );
+            }
+            continue;
+        }
+        if strict && !is_offline() {
+            let offering = find_offering_sources(&indexes, &name, &version, output)?;
+            if !offering.iter().any(|x| x == source) {
+                bail!(
+                    "{}=={} is no longer offered by package source '{}'",
+                    name,
+                    version,
+                    source
+                );
+            }
+            if let Some(other) = offering.iter().find(|x| *x != source) {
+                bail!(
+                    "{}=={} was locked from package source '{}' but is also offered by '{}'",
+                    name,
+                    version,
+                    source,
+                    other
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Puts the environment markers of the conditional dependencies on their
+/// pins.
+///
+/// As pip-compile evaluates markers for the current machine, conditional
+/// dependencies are resolved without their markers.  To keep the lockfile
+/// installable everywhere, the pins that are only required by conditional
+/// dependencies are restricted again.  Packages only required transitively
+/// get the union of all the markers.
+fn apply_markers(
+    output: CommandOutput,
+    workspace_path: &Path,
+    base_requirements: &[&Path],
+    lockfile: &Path,
+    lock_options: &LockOptions,
+    conditional: &ConditionalDependencies,
+) -> Result<(), Error> {
+    if conditional.markers.is_empty() {
+        return Ok(());
+    }
+    let base_pins = resolve_base_pins(
+        output,
+        workspace_path,
+        base_requirements,
         lockfile,
         lock_options,
-        &[],
     )?;
 
+    let mut rv = String::new();
+    for line in fs::read_to_string(lockfile)?.lines() {
+        let marker = locked_pin(line)
+            .filter(|(name, _)| !base_pins.contains_key(name) && !line.contains(';'))
+            .map(|(name, _)| conditional.marker_for(&name));
+        match (marker, line.strip_suffix(" \\")) {
+            (Some(marker), Some(req)) => rv.push_str(&format!("{} ; {} \\", req, marker)),
+            (Some(marker), None) => rv.push_str(&format!("{} ; {}", line, marker)),
+            (None, _) => rv.push_str(line),
+        }
+        rv.push('\n');
+    }
+    fs::write(lockfile, rv)?;
     Ok(())
 }
 
+/// Resolves a subset of the requirements to find out which pins they need.
+///
+/// The resolution starts from the pins of the lockfile so that it picks the
+/// same versions.
+fn resolve_base_pins(
+    output: CommandOutput,
+    workspace_path: &Path,
+    requirements: &[&Path],
+    lockfile: &Path,
+    lock_options: &LockOptions,
+) -> Result<BTreeMap<String, String>, Error> {
+    let scratch = tempfile::tempdir()?;
+    let base_lockfile = scratch.path().join("requirements.lock");
+    fs::copy(lockfile, &base_lockfile)?;
+    let base_options = LockOptions {
+        update: Vec::new(),
+        update_all: false,
+        ..lock_options.clone()
+    };
+    generate_lockfile(
+        output,
+        workspace_path,
+        requirements,
+        &base_lockfile,
+        &base_options,
+        false,
+        &[],
+    )?;
+    read_locked_pins(&base_lockfile)
+}
+
 fn generate_lockfile(
     output: CommandOutput,
     workspace_path: &Path,
-    requirements_file_in: &Path,
+    requirements_files_in: &[&Path],
     lockfile: &Path,
     lock_options: &LockOptions,
-    extra_args: &[&str],
+    no_deps: bool,
+    header: &[String],
 ) -> Result<(), Error> {
     let scratch = tempfile::tempdir()?;
     let requirements_file = scratch.path().join("requirements.txt");
@@ -199,46 +1475,47 @@ fn generate_lockfile(
         fs::write(lockfile, b"")?;
     }
 
-    let pip_compile_path = get_pip_compile(output)?;
-    let mut cmd = Command::new(pip_compile_path);
-    cmd.arg("--resolver=backtracking")
-        .arg("--no-annotate")
-        .arg("--strip-extras")
-        .arg("--allow-unsafe")
-        .arg("--no-header")
-        .arg("-o")
-        .arg(&requirements_file)
-        .arg(requirements_file_in)
-        .env("PYTHONWARNINGS", "ignore");
+    let backend = Backend::get(lock_options.backend, output)?;
+    let mut cmd = backend.compile_command(
+        requirements_files_in,
+        &requirements_file,
+        lock_options,
+        no_deps,
+        output,
+    )?;
+    // relative find-links sources are relative to the workspace
+    cmd.current_dir(workspace_path);
     if output == CommandOutput::Verbose {
-        cmd.arg("--verbose");
-    } else {
-        cmd.arg("-q");
+        eprintln!("running {}", format_command(&cmd));
     }
-    for pkg in &lock_options.update {
-        cmd.arg("--upgrade-package");
-        cmd.arg(pkg);
-    }
-    if lock_options.update_all {
-        cmd.arg("--upgrade");
-    }
-    if lock_options.pre {
-        cmd.arg("--pre");
-    }
-    cmd.args(extra_args);
-    let status = cmd.status().context("unable to run pip-compile")?;
+    let auth = IndexAuth::new(&get_index_urls(&lock_options.sources), output)?;
+    auth.apply(&mut cmd);
+    let status = cmd
+        .status()
+        .with_context(|| format!("unable to run {}", backend.resolver_name()))?;
     if !status.success() {
+        if is_offline() {
+            bail!("failed to generate lockfile offline, packages must be in the wheelhouse");
+        }
         bail!("failed to generate lockfile");
     };
 
-    finalize_lockfile(&requirements_file, lockfile, workspace_path)?;
+    finalize_lockfile(&requirements_file, lockfile, workspace_path, header)?;
 
     Ok(())
 }
 
-fn finalize_lockfile(generated: &Path, out: &Path, workspace_root: &Path) -> Result<(), Error> {
+fn finalize_lockfile(
+    generated: &Path,
+    out: &Path,
+    workspace_root: &Path,
+    header: &[String],
+) -> Result<(), Error> {
     let mut rv = BufWriter::new(fs::File::create(out)?);
     writeln!(rv, "{}", REQUIREMENTS_HEADER)?;
+    for line in header {
+        writeln!(rv, "{}", line)?;
+    }
     for line in fs::read_to_string(generated)?.lines() {
         if let Some(m) = FILE_EDITABLE_RE.captures(line) {
             let url = Url::parse(&m[1]).context("invalid editable URL generated")?;
@@ -285,3 +1562,157 @@ fn test_make_relativec_url() {
         "file:."
     );
 }
+
+#[test]
+fn test_locked_pin() {
+    assert_eq!(
+        locked_pin("Flask_SQLAlchemy==3.0.3"),
+        Some(("flask-sqlalchemy".into(), "3.0.3".into()))
+    );
+    assert_eq!(
+        locked_pin("colorama==0.4.6 ; platform_system == 'Windows'"),
+        Some(("colorama".into(), "0.4.6".into()))
+    );
+    assert_eq!(locked_pin("# inputs: 0123456789abcdef"), None);
+    assert_eq!(locked_pin("-e file:."), None);
+}
+
+#[test]
+fn test_list_find_links_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    for filename in [
+        "Private_Pkg-1.2.0-py3-none-any.whl",
+        "other-tool-0.1.tar.gz",
+        "README.md",
+    ] {
+        fs::write(dir.path().join(filename), b"").unwrap();
+    }
+    let dists = list_find_links_dir(dir.path()).unwrap();
+    assert_eq!(
+        dists.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("other-tool".to_string(), "0.1".to_string()),
+            ("private-pkg".to_string(), "1.2.0".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_select_lockfile_features() {
+    let contents = "\
+# features: docs,server
+-e file:.
+requests==2.31.0
+mkdocs==1.5.3 \\
+    --hash=sha256:aaaa  # from features: docs
+uvicorn==0.23.2  # from features: server
+watchfiles==0.20.0 ; sys_platform != \"win32\"  # from features: docs,server
+";
+    assert_eq!(
+        select_lockfile_features(contents, &["server".into()]),
+        "\
+# features: docs,server
+-e file:.
+requests==2.31.0
+uvicorn==0.23.2  # from features: server
+watchfiles==0.20.0 ; sys_platform != \"win32\"  # from features: docs,server
+"
+    );
+    assert_eq!(
+        select_lockfile_features(contents, &[]),
+        "# features: docs,server\n-e file:.\nrequests==2.31.0\n"
+    );
+}
+
+#[test]
+fn test_parse_exclude_newer() {
+    assert!(parse_exclude_newer("2024-01-01").is_ok());
+    assert!(parse_exclude_newer("2024-01-01T12:30:00Z").is_ok());
+    assert!(parse_exclude_newer("2024-01-01T12:30:00.5+02:00").is_ok());
+    assert!(parse_exclude_newer("2024-13-01").is_err());
+    assert!(parse_exclude_newer("2024-01-01 12:30").is_err());
+    assert!(parse_exclude_newer("yesterday").is_err());
+}
+
+#[test]
+fn test_get_index_sources() {
+    let source = |name: &str, url: &str, kind| SourceRef {
+        name: name.into(),
+        url: url.into(),
+        kind,
+    };
+    let sources = [
+        source(
+            "internal",
+            "https://pypi.example.com/simple",
+            SourceKind::Index,
+        ),
+        source("wheels", "./wheels", SourceKind::FindLinks),
+    ];
+    assert_eq!(
+        get_index_sources(&sources),
+        [
+            ("pypi".to_string(), PYPI_SIMPLE_URL.to_string()),
+            (
+                "internal".to_string(),
+                "https://pypi.example.com/simple".to_string()
+            ),
+        ]
+    );
+    let sources = [
+        source(
+            "internal",
+            "https://pypi.example.com/simple",
+            SourceKind::Index,
+        ),
+        source(
+            "default",
+            "https://mirror.example.com/simple",
+            SourceKind::Index,
+        ),
+    ];
+    assert_eq!(
+        get_index_sources(&sources)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>(),
+        ["default", "internal"]
+    );
+}
+
+#[test]
+fn test_export_lockfile() {
+    let lockfile = "\
+# generated by rye
+# inputs: 0123456789abcdef
+# features: docs
+--index-url https://pypi.example.com/simple
+-e file:.
+requests==2.31.0 \\
+    --hash=sha256:aaaa  # from source: default
+mkdocs==1.5.3 \\
+    --hash=sha256:bbbb  # from features: docs
+colorama==0.4.6 ; sys_platform == 'win32' \\
+    --hash=sha256:cccc
+";
+    assert_eq!(
+        export_lockfile(lockfile, None, true),
+        "\
+--index-url https://pypi.example.com/simple
+colorama==0.4.6 ; sys_platform == 'win32' \\
+    --hash=sha256:cccc
+mkdocs==1.5.3 \\
+    --hash=sha256:bbbb
+requests==2.31.0 \\
+    --hash=sha256:aaaa
+"
+    );
+    assert_eq!(
+        export_lockfile(lockfile, Some(&[]), false),
+        "\
+--index-url https://pypi.example.com/simple
+colorama==0.4.6 ; sys_platform == 'win32'
+requests==2.31.0
+"
+    );
+}
@@ -1,13 +1,28 @@
+use std::process;
+
+mod auth;
+mod backend;
 mod bootstrap;
+mod cache;
 mod cli;
 mod config;
+mod index;
 mod installer;
 mod lock;
 mod pyproject;
 mod sources;
 mod sync;
+mod tools;
 mod utils;
 
-pub fn main() -> Result<(), anyhow::Error> {
-    cli::execute()
+use crate::utils::QuietExit;
+
+pub fn main() {
+    if let Err(err) = cli::execute() {
+        if let Some(QuietExit(code)) = err.downcast_ref() {
+            process::exit(*code);
+        }
+        eprintln!("Error: {:?}", err);
+        process::exit(1);
+    }
 }
@@ -4,24 +4,33 @@ use std::collections::HashSet;
 use std::env;
 use std::ffi::OsStr;
 use std::fs;
+use std::ops::Range;
 #[cfg(not(target_os = "windows"))]
 use std::os::unix::prelude::MetadataExt;
 #[cfg(target_os = "windows")]
 use std::os::windows::prelude::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Context, Error};
 use globset::Glob;
 use once_cell::sync::Lazy;
+use pep440_rs::{LocalSegment, Version, VersionSpecifiers};
 use pep508_rs::Requirement;
 use regex::Regex;
 use toml_edit::{Array, Document, Item, Table, TableLike, Value};
 
-use crate::utils::format_requirement;
+use crate::config::get_discovery_boundaries;
+use crate::utils::{format_requirement, VENV_BIN};
 
 static NORMALIZATION_SPLIT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[-_.]+").unwrap());
+static VERSION_ASSIGN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?m)^\s*(?:__version__|VERSION)\s*(?::\s*str\s*)?=\s*['"]([^'"]+)['"]"#).unwrap()
+});
+static NAME_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^([a-z0-9]|[a-z0-9][a-z0-9._-]*[a-z0-9])$").unwrap());
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DependencyKind<'a> {
@@ -34,15 +43,31 @@ pub enum DependencyKind<'a> {
 #[derive(Clone, Debug)]
 pub enum Script {
     /// A command alias
-    Cmd(Vec<String>),
+    Cmd(Vec<String>, ScriptOptions),
     /// External script reference
     External(PathBuf),
+    /// Other scripts that are run one after another
+    Chain(Vec<String>, ScriptOptions),
+    /// A python function (`module:function`) or module to call
+    Call(String, ScriptOptions),
+}
+
+impl Script {
+    /// Returns the options of the script if it has any.
+    pub fn options(&self) -> Option<&ScriptOptions> {
+        match self {
+            Script::Cmd(_, ref options)
+            | Script::Call(_, ref options)
+            | Script::Chain(_, ref options) => Some(options),
+            Script::External(_) => None,
+        }
+    }
 }
 
 impl fmt::Display for Script {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Script::Cmd(args) => {
+            Script::Cmd(args, _) => {
                 for (idx, arg) in args.iter().enumerate() {
                     if idx > 0 {
                         write!(f, " ")?;
@@ -52,14 +77,261 @@ impl fmt::Display for Script {
                 Ok(())
             }
             Script::External(ref script) => write!(f, "external: {}", script.display()),
+            Script::Chain(ref scripts, _) => write!(f, "chain: {}", scripts.join(", ")),
+            Script::Call(ref target, _) => write!(f, "call: {}", target),
+        }
+    }
+}
+
+/// The options of a script that is declared as table.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptOptions {
+    /// Environment variables to set.  Values can reference other variables
+    /// as `${VAR}`.
+    pub env: Vec<(String, String)>,
+    /// Environment variables to remove.
+    pub env_remove: Vec<String>,
+    /// Scripts to run before the script.
+    pub pre: Vec<String>,
+    /// Scripts to run after the script succeeded.
+    pub post: Vec<String>,
+    /// Also run the post scripts if the script failed.
+    pub post_always: bool,
+    /// A description of the script.
+    pub help: Option<String>,
+    /// The folder to run the script in.  This is the project for scripts
+    /// inherited from the workspace root, other scripts run in the current
+    /// folder.
+    pub cwd: Option<PathBuf>,
+    /// An env file to load, relative to the project root.
+    pub env_file: Option<PathBuf>,
+}
+
+impl ScriptOptions {
+    fn from_table(tbl: &dyn TableLike) -> ScriptOptions {
+        let strings = |key: &str| -> Vec<String> {
+            tbl.get(key)
+                .and_then(|x| x.as_array())
+                .map(|x| {
+                    x.iter()
+                        .filter_map(|x| x.as_str().map(|x| x.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        ScriptOptions {
+            env: tbl
+                .get("env")
+                .and_then(|x| x.as_table_like())
+                .map(|env| {
+                    env.iter()
+                        .map(|(key, value)| {
+                            let value = value
+                                .as_str()
+                                .map(|x| x.to_string())
+                                .unwrap_or_else(|| value.to_string().trim().to_string());
+                            (key.to_string(), value)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            env_remove: strings("env-remove"),
+            pre: strings("pre"),
+            post: strings("post"),
+            post_always: tbl
+                .get("post-always")
+                .and_then(|x| x.as_bool())
+                .unwrap_or(false),
+            help: tbl
+                .get("help")
+                .and_then(|x| x.as_str())
+                .map(|x| x.to_string()),
+            cwd: None,
+            env_file: tbl
+                .get("env-file")
+                .and_then(|x| x.as_str())
+                .map(PathBuf::from),
         }
     }
 }
 
+/// Parses a script command given as string or as array of arguments.
+fn parse_script_cmd(value: &Item) -> Result<Option<Vec<String>>, Error> {
+    if let Some(cmd) = value.as_str() {
+        split_script_cmd(cmd).map(Some)
+    } else {
+        Ok(value.as_array().map(|cmd| {
+            cmd.iter()
+                .map(|x| {
+                    x.as_str()
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| x.to_string())
+                })
+                .collect()
+        }))
+    }
+}
+
+/// Splits a script command given as string into its arguments.
+///
+/// Quotes and escapes work like in a POSIX shell, but the command is not
+/// run by a shell, so shell operators are rejected rather than passed on as
+/// arguments.
+fn split_script_cmd(cmd: &str) -> Result<Vec<String>, Error> {
+    let args = shlex::split(cmd)
+        .ok_or_else(|| anyhow!("unbalanced quotes or trailing escape in '{}'", cmd))?;
+    if let Some(operator) = find_shell_operator(cmd) {
+        bail!(
+            "'{}' is not supported as scripts are not run by a shell.\n\
+             Use `chain` or `pre` and `post` hooks to run several commands, or \
+             `cmd = [\"bash\", \"-c\", \"...\"]` to use a shell.",
+            operator
+        );
+    }
+    Ok(args)
+}
+
+/// Finds an unquoted shell operator in a script command.
+fn find_shell_operator(cmd: &str) -> Option<&'static str> {
+    // quoted and escaped characters are replaced so that they cannot form
+    // an operator.
+    let mut unquoted = Vec::new();
+    let mut token = String::new();
+    let mut quote = None;
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                chars.next();
+                token.push('\0');
+            }
+            (Some(_), _) => token.push('\0'),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() => unquoted.push(std::mem::take(&mut token)),
+            (None, c) => token.push(c),
+        }
+    }
+    unquoted.push(token);
+
+    unquoted.iter().find_map(|token| {
+        ["&&", "||", "|", ";"]
+            .into_iter()
+            .find(|op| token.contains(op))
+            .or_else(|| ["&", ">", ">>", "<"].into_iter().find(|op| token == op))
+    })
+}
+
+/// Where the version of a project is kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSource {
+    /// `project.version` in the pyproject.toml.
+    Static,
+    /// A file with the version, on its own or as `__version__`.
+    File(PathBuf),
+    /// The tags of the git repository.
+    Git,
+}
+
+/// The kind of a package source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// A simple repository API index.
+    Index,
+    /// A directory or page with links to distributions.
+    FindLinks,
+}
+
+/// A package source from `tool.rye.sources`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceRef {
+    pub name: String,
+    pub url: String,
+    pub kind: SourceKind,
+}
+
+impl SourceRef {
+    /// The name of the source that replaces PyPI.
+    pub const DEFAULT_NAME: &'static str = "default";
+
+    /// Returns the directory of a local find-links source.
+    ///
+    /// Relative paths are relative to the project root.
+    pub fn local_path(&self, root: &Path) -> Option<PathBuf> {
+        if self.kind != SourceKind::FindLinks {
+            return None;
+        }
+        match url::Url::parse(&self.url) {
+            Ok(url) if url.scheme() == "file" => url.to_file_path().ok(),
+            Ok(url) if matches!(url.scheme(), "http" | "https") => None,
+            _ => Some(root.join(&self.url)),
+        }
+    }
+
+    fn from_item(item: &dyn TableLike) -> Result<SourceRef, Error> {
+        let name = item
+            .get("name")
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| anyhow!("package source is missing a name"))?;
+        let url = item
+            .get("url")
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| anyhow!("package source '{}' is missing a url", name))?;
+        let kind = match item.get("type").and_then(|x| x.as_str()) {
+            None | Some("index") => SourceKind::Index,
+            Some("find-links") => SourceKind::FindLinks,
+            Some(other) => bail!("package source '{}' has unknown type '{}'", name, other),
+        };
+        if url::Url::parse(url).is_ok_and(|x| !x.username().is_empty() || x.password().is_some()) {
+            bail!(
+                "package source '{}' must not contain credentials, store them with \
+                 `rye config --set-credential` instead",
+                name
+            );
+        }
+        Ok(SourceRef {
+            name: name.to_string(),
+            url: url.to_string(),
+            kind,
+        })
+    }
+}
+
+/// Returns the pip options that select a list of sources.
+///
+/// The index named `default` replaces PyPI (or the index pip is configured
+/// with), the other indexes are added after it in the order they are
+/// declared.  If the default source is a find-links source, no index is used
+/// at all.
+pub fn get_source_options(sources: &[SourceRef]) -> Vec<String> {
+    let mut rv = Vec::new();
+    for source in sources {
+        match source.kind {
+            SourceKind::Index if source.name == SourceRef::DEFAULT_NAME => {
+                rv.insert(0, format!("--index-url {}", source.url))
+            }
+            SourceKind::Index => rv.push(format!("--extra-index-url {}", source.url)),
+            SourceKind::FindLinks => {
+                if source.name == SourceRef::DEFAULT_NAME {
+                    rv.insert(0, "--no-index".into());
+                }
+                rv.push(format!("--find-links {}", source.url));
+            }
+        }
+    }
+    rv
+}
+
 #[derive(Debug)]
 pub struct Workspace {
     root: PathBuf,
     members: Vec<String>,
+    /// The scripts declared in the workspace root which members inherit.
+    scripts: Item,
+    /// The package sources declared in the workspace root.
+    sources: Item,
+    /// Whether the projects are installed editable, from the workspace root.
+    editable: Option<bool>,
 }
 
 impl Workspace {
@@ -67,6 +339,9 @@ impl Workspace {
     pub fn from_workspace_section_and_path(workspace: &dyn TableLike, path: &Path) -> Workspace {
         Workspace {
             root: path.to_path_buf(),
+            scripts: Item::None,
+            sources: Item::None,
+            editable: None,
             members: workspace
                 .get("members")
                 .and_then(|x| x.as_array())
@@ -82,6 +357,7 @@ impl Workspace {
     /// Discovers a pyproject toml
     #[allow(unused)]
     pub fn discover_from_path(path: &Path) -> Option<Workspace> {
+        let boundaries = get_discovery_boundaries();
         let mut here = path;
 
         loop {
@@ -96,6 +372,9 @@ impl Workspace {
                 }
             }
 
+            if is_discovery_boundary(here, &boundaries) {
+                break;
+            }
             here = match here.parent() {
                 Some(parent) => parent,
                 None => break,
@@ -111,56 +390,51 @@ impl Workspace {
     }
 
     /// Checks if a project is a member of the declared workspace.
+    ///
+    /// The root itself is always a member, the projects below it only if
+    /// they match one of the `members` globs.  Without globs all projects
+    /// below the root are members.
     pub fn is_member(&self, path: &Path) -> bool {
-        let canonicalized = self.root.join(path);
-        if let Ok(relative) = path.strip_prefix(canonicalized) {
-            if relative == Path::new("") || self.members.is_empty() {
-                true
-            } else {
-                let path = relative.to_string_lossy();
-                for pattern in &self.members {
-                    if let Ok(glob) = Glob::new(pattern) {
-                        if glob.compile_matcher().is_match(&*path) {
-                            return true;
-                        }
-                    }
-                }
-                false
-            }
-        } else {
-            false
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative == Path::new("") || self.members.is_empty() {
+            return true;
         }
+        let path = relative.to_string_lossy().replace('\\', "/");
+        self.members
+            .iter()
+            .filter_map(|pattern| Glob::new(pattern.trim_end_matches('/')).ok())
+            .any(|glob| glob.compile_matcher().is_match(&path))
     }
 
     /// Iterates through all projects in the workspace.
     pub fn iter_projects<'a>(
         self: &'a Arc<Self>,
     ) -> impl Iterator<Item = Result<PyProject, Error>> + 'a {
+        // hidden folders such as virtualenvs never hold members
         walkdir::WalkDir::new(&self.root)
             .into_iter()
+            .filter_entry(|entry| {
+                entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+            })
             .filter_map(move |entry| match entry {
                 Ok(entry) => {
                     if entry.file_type().is_file()
                         && entry.file_name() == OsStr::new("pyproject.toml")
+                        && self.is_member(entry.path().parent().unwrap())
                     {
-                        let project =
-                            match PyProject::load_with_workspace(entry.path(), self.clone()) {
-                                Ok(Some(project)) => project,
-                                Ok(None) => return None,
-                                Err(err) => return Some(Err(err)),
-                            };
-                        if self.is_member(entry.path().parent().unwrap()) {
-                            return Some(Ok(project));
-                        }
+                        PyProject::load_with_workspace(entry.path(), self.clone()).transpose()
+                    } else {
+                        None
                     }
-                    None
                 }
                 Err(err) => Some(Err(err.into())),
             })
     }
 
     /// Looks up a single project.
-    pub fn get_project<'a>(self: &'a Arc<Self>, p: &str) -> Result<Option<PyProject>, Error> {
+    pub fn get_project(self: &Arc<Self>, p: &str) -> Result<Option<PyProject>, Error> {
         let normalized_name = normalize_package_name(p);
         for project in self.iter_projects() {
             let project = project?;
@@ -176,7 +450,26 @@ impl Workspace {
             .and_then(|x| x.get("rye"))
             .and_then(|x| x.get("workspace"))
             .and_then(|x| x.as_table_like())
-            .map(|workspace| Workspace::from_workspace_section_and_path(workspace, path))
+            .map(|workspace| Workspace {
+                scripts: doc
+                    .get("tool")
+                    .and_then(|x| x.get("rye"))
+                    .and_then(|x| x.get("scripts"))
+                    .cloned()
+                    .unwrap_or_default(),
+                sources: doc
+                    .get("tool")
+                    .and_then(|x| x.get("rye"))
+                    .and_then(|x| x.get("sources"))
+                    .cloned()
+                    .unwrap_or_default(),
+                editable: doc
+                    .get("tool")
+                    .and_then(|x| x.get("rye"))
+                    .and_then(|x| x.get("editable"))
+                    .and_then(|x| x.as_bool()),
+                ..Workspace::from_workspace_section_and_path(workspace, path)
+            })
     }
 
     /// Returns the virtualenv path of the workspace.
@@ -196,11 +489,39 @@ pub struct PyProject {
 impl PyProject {
     /// Discovers and loads a pyproject toml.
     pub fn discover() -> Result<PyProject, Error> {
-        let pyproject_toml = match find_project_root() {
-            Some(root) => root.join("pyproject.toml"),
-            None => bail!("did not find pyproject.toml"),
+        Self::discover_from_dir(&env::current_dir()?)
+    }
+
+    /// Discovers the pyproject toml that applies to a folder.
+    pub fn discover_from(path: &Path) -> Result<PyProject, Error> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("unable to find {}", path.display()))?;
+        Self::discover_from_dir(&path)
+    }
+
+    fn discover_from_dir(path: &Path) -> Result<PyProject, Error> {
+        let boundaries = get_discovery_boundaries();
+        let last = match search_project_root(path, &boundaries) {
+            Ok(root) => return Self::load(&root.join("pyproject.toml")),
+            Err(last) => last,
         };
-        Self::load(&pyproject_toml)
+        if last == path {
+            bail!("did not find pyproject.toml in {}", path.display());
+        }
+        if is_discovery_boundary(&last, &boundaries) {
+            bail!(
+                "did not find pyproject.toml in {} or its parents up to the repository \
+                 root {} (set RYE_NO_DISCOVERY_BOUNDARY=1 to search beyond it)",
+                path.display(),
+                last.display()
+            );
+        }
+        bail!(
+            "did not find pyproject.toml in {} or its parents up to {}",
+            path.display(),
+            last.display()
+        );
     }
 
     /// Loads a pyproject toml.
@@ -214,21 +535,11 @@ impl PyProject {
                     &filename.display()
                 )
             })?;
-        let mut workspace = Workspace::try_load_from_toml(&doc, root).map(Arc::new);
-
-        if workspace.is_none() {
-            workspace = Workspace::discover_from_path(root).map(Arc::new);
-        }
-
-        if let Some(ref workspace) = workspace {
-            if !workspace.is_member(root) {
-                bail!(
-                    "project {} is not part of pyproject workspace {}",
-                    filename.display(),
-                    workspace.path().display()
-                );
-            }
-        }
+        // projects below a workspace root that are not members of it are
+        // standalone projects.
+        let workspace = Workspace::try_load_from_toml(&doc, root)
+            .or_else(|| Workspace::discover_from_path(root).filter(|x| x.is_member(root)))
+            .map(Arc::new);
 
         Ok(PyProject {
             root: root.to_owned(),
@@ -245,6 +556,9 @@ impl PyProject {
         workspace: Arc<Workspace>,
     ) -> Result<Option<PyProject>, Error> {
         let root = filename.parent().unwrap_or(Path::new("."));
+        if !workspace.is_member(root) {
+            return Ok(None);
+        }
         let doc = fs::read_to_string(filename)?
             .parse::<Document>()
             .with_context(|| {
@@ -255,10 +569,6 @@ impl PyProject {
                 )
             })?;
 
-        if !workspace.is_member(root) {
-            return Ok(None);
-        }
-
         Ok(Some(PyProject {
             root: root.to_owned(),
             workspace: Some(workspace),
@@ -301,7 +611,7 @@ impl PyProject {
 
     /// Returns the virtualenv bin path of the virtualenv.
     pub fn venv_bin_path(&self) -> Cow<'_, Path> {
-        Cow::Owned(self.venv_path().join("bin"))
+        Cow::Owned(self.venv_path().join(VENV_BIN))
     }
 
     /// Returns the project name.
@@ -317,34 +627,187 @@ impl PyProject {
         self.name().map(normalize_package_name)
     }
 
-    /// Looks up a script
-    pub fn get_script_cmd(&self, key: &str) -> Option<Script> {
-        let external = self.venv_bin_path().join(key);
-        if external.metadata().map_or(false, |x| x.mode() & 0o001 != 0) {
-            return Some(Script::External(external));
+    /// Returns where the version of the project is kept.
+    ///
+    /// Dynamic versions are read from `tool.rye.version-file` or the version
+    /// file configured for hatch or pdm.  Projects using hatch-vcs, the scm
+    /// source of pdm or setuptools-scm take the version from git tags.  Other
+    /// dynamic versions fail with a pointer to where they are configured.
+    pub fn version_source(&self) -> Result<VersionSource, Error> {
+        let dynamic = self
+            .doc
+            .get("project")
+            .and_then(|x| x.get("dynamic"))
+            .and_then(|x| x.as_array())
+            .is_some_and(|x| x.iter().any(|x| x.as_str() == Some("version")));
+        if !dynamic {
+            return Ok(VersionSource::Static);
+        }
+
+        let tool = self.doc.get("tool");
+        let tool_str = |keys: &[&str]| {
+            keys.iter()
+                .try_fold(tool?, |item, key| item.get(key))
+                .and_then(|x| x.as_str())
+        };
+        let pdm_source = tool_str(&["pdm", "version", "source"]);
+        let version_file = tool_str(&["rye", "version-file"])
+            .or_else(|| tool_str(&["hatch", "version", "path"]))
+            .or_else(|| match pdm_source {
+                Some("file") => tool_str(&["pdm", "version", "path"]),
+                _ => None,
+            });
+        if let Some(path) = version_file {
+            return Ok(VersionSource::File(self.root.join(path)));
+        }
+        if tool_str(&["hatch", "version", "source"]) == Some("vcs")
+            || pdm_source == Some("scm")
+            || tool.and_then(|x| x.get("setuptools_scm")).is_some()
+        {
+            return Ok(VersionSource::Git);
         }
 
-        let value = self
+        let backend = self
+            .doc
+            .get("build-system")
+            .and_then(|x| x.get("build-backend"))
+            .and_then(|x| x.as_str())
+            .unwrap_or("");
+        let config = match backend.split('.').next() {
+            Some("hatchling") => "`tool.hatch.version` in pyproject.toml",
+            Some("setuptools") => "`tool.setuptools.dynamic.version` in pyproject.toml",
+            Some("flit_core") => "`__version__` of the module",
+            Some("pdm") => "`tool.pdm.version` in pyproject.toml",
+            _ => "the configuration of the build backend",
+        };
+        bail!(
+            "the version of the project is dynamic, it is managed by {}\n\
+             Point `tool.rye.version-file` to the file that holds it to let rye read it.",
+            config
+        );
+    }
+
+    /// Returns the version of the project.
+    ///
+    /// For dynamic versions this is the version that the version file or the
+    /// git tags give.
+    pub fn version(&self) -> Result<Version, Error> {
+        match self.version_source()? {
+            VersionSource::Static => {
+                let version = self
+                    .doc
+                    .get("project")
+                    .and_then(|x| x.get("version"))
+                    .and_then(|x| x.as_str())
+                    .ok_or_else(|| anyhow!("project.version is missing in pyproject.toml"))?;
+                Version::from_str(version)
+                    .map_err(|err| anyhow!("invalid project.version: {}", err))
+            }
+            VersionSource::File(path) => read_version_file(&path),
+            VersionSource::Git => get_git_version(&self.root),
+        }
+    }
+
+    /// Sets the version of the project, keeping the formatting around it.
+    ///
+    /// This is only for static versions, see [`write_version_file`] for
+    /// version files.
+    pub fn set_version(&mut self, version: &Version) {
+        let item = &mut self.doc["project"]["version"];
+        let mut value = Value::from(version.to_string());
+        if let Some(old) = item.as_value() {
+            *value.decor_mut() = old.decor().clone();
+        }
+        *item = Item::Value(value);
+    }
+
+    /// Looks up the declaration of a script.
+    ///
+    /// Members of a workspace inherit the scripts of the workspace root but
+    /// their own declarations win.  Returns the declaration and if it was
+    /// inherited.
+    fn get_script_item(&self, key: &str) -> Option<(&Item, bool)> {
+        if let Some(value) = self
             .doc
             .get("tool")
             .and_then(|x| x.get("rye"))
             .and_then(|x| x.get("scripts"))
-            .and_then(|x| x.get(key))?;
-        if let Some(cmd) = value.as_str() {
-            shlex::split(cmd).map(Script::Cmd)
-        } else {
-            value.as_array().map(|cmd| {
-                Script::Cmd(
-                    cmd.iter()
-                        .map(|x| {
-                            x.as_str()
-                                .map(|x| x.to_string())
-                                .unwrap_or_else(|| x.to_string())
-                        })
-                        .collect(),
-                )
-            })
+            .and_then(|x| x.get(key))
+        {
+            return Some((value, false));
+        }
+        let workspace = self.workspace.as_ref()?;
+        if workspace.root == self.root {
+            return None;
         }
+        workspace.scripts.get(key).map(|value| (value, true))
+    }
+
+    /// Checks if a script is declared in the pyproject.toml or inherited
+    /// from the workspace root.
+    pub fn is_declared_script(&self, key: &str) -> bool {
+        key != "_" && self.get_script_item(key).is_some()
+    }
+
+    /// Checks if a script is inherited from the workspace root.
+    pub fn is_inherited_script(&self, key: &str) -> bool {
+        self.get_script_item(key).is_some_and(|x| x.1)
+    }
+
+    /// Looks up a script
+    ///
+    /// Declared scripts shadow the scripts installed into the virtualenv.
+    /// Scripts that are declared but cannot be run fail with an error.
+    pub fn get_script_cmd(&self, key: &str) -> Result<Option<Script>, Error> {
+        let (value, inherited) = match self.get_script_item(key) {
+            Some(item) if key != "_" => item,
+            _ => {
+                let external = self.venv_bin_path().join(key);
+                return Ok(external
+                    .metadata()
+                    .is_ok_and(|x| x.mode() & 0o001 != 0)
+                    .then_some(Script::External(external)));
+            }
+        };
+        let mut options = value
+            .as_table_like()
+            .map(ScriptOptions::from_table)
+            .unwrap_or_default();
+        if inherited {
+            options.cwd = Some(self.root.clone());
+        }
+        let cmd = if let Some(tbl) = value.as_table_like() {
+            if let Some(chain) = tbl.get("chain").and_then(|x| x.as_array()) {
+                return Ok(Some(Script::Chain(
+                    chain
+                        .iter()
+                        .filter_map(|x| x.as_str().map(|x| x.to_string()))
+                        .collect(),
+                    options,
+                )));
+            }
+            if let Some(target) = tbl.get("call").and_then(|x| x.as_str()) {
+                return Ok(Some(Script::Call(target.to_string(), options)));
+            }
+            match tbl.get("cmd") {
+                Some(cmd) => cmd,
+                None => return Ok(None),
+            }
+        } else {
+            value
+        };
+        let cmd = parse_script_cmd(cmd).with_context(|| format!("invalid script '{}'", key))?;
+        Ok(cmd.map(|cmd| Script::Cmd(cmd, options)))
+    }
+
+    /// Returns the options that apply to all scripts.
+    ///
+    /// These are declared in the special `_` entry of the scripts.
+    pub fn script_defaults(&self) -> ScriptOptions {
+        self.get_script_item("_")
+            .and_then(|x| x.0.as_table_like())
+            .map(ScriptOptions::from_table)
+            .unwrap_or_default()
     }
 
     /// Returns a list of known scripts.
@@ -356,16 +819,28 @@ impl PyProject {
             .and_then(|x| x.get("scripts"))
             .and_then(|x| x.as_table_like())
         {
-            Some(tbl) => tbl.iter().map(|x| x.0.to_string()).collect(),
+            Some(tbl) => tbl
+                .iter()
+                .map(|x| x.0.to_string())
+                .filter(|x| x != "_")
+                .collect(),
             None => HashSet::new(),
         };
-        for entry in fs::read_dir(&self.venv_bin_path())
+        if let Some(tbl) = self
+            .workspace
+            .as_ref()
+            .filter(|x| x.root != self.root)
+            .and_then(|x| x.scripts.as_table_like())
+        {
+            rv.extend(tbl.iter().map(|x| x.0.to_string()).filter(|x| x != "_"));
+        }
+        for entry in fs::read_dir(self.venv_bin_path())
             .ok()
             .into_iter()
             .flatten()
             .flatten()
         {
-            if entry.metadata().map_or(false, |x| (x.mode() & 0o001) != 0) {
+            if entry.metadata().is_ok_and(|x| (x.mode() & 0o001) != 0) {
                 rv.insert(
                     entry
                         .path()
@@ -379,30 +854,68 @@ impl PyProject {
         rv
     }
 
+    /// Returns the scripts to run after the virtualenv was synced.
+    ///
+    /// These are declared in `tool.rye.hooks.post-sync`.
+    pub fn post_sync_hooks(&self) -> Vec<String> {
+        self.doc
+            .get("tool")
+            .and_then(|x| x.get("rye"))
+            .and_then(|x| x.get("hooks"))
+            .and_then(|x| x.get("post-sync"))
+            .and_then(|x| x.as_array())
+            .map(|hooks| {
+                hooks
+                    .iter()
+                    .filter_map(|x| x.as_str().map(|x| x.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     /// Adds a dependency.
-    pub fn add_dependency(&mut self, req: &Requirement, kind: DependencyKind) -> Result<(), Error> {
+    ///
+    /// An existing entry for the same package is replaced and returned.
+    pub fn add_dependency(
+        &mut self,
+        req: &Requirement,
+        kind: DependencyKind,
+    ) -> Result<Option<String>, Error> {
         let dependencies = match kind {
             DependencyKind::Normal => &mut self.doc["project"]["dependencies"],
-            DependencyKind::Dev => &mut self.doc["tool"]["rye"]["dev-dependencies"],
+            DependencyKind::Dev => {
+                // add this as a proper `[tool.rye]` table if it's missing
+                if self.doc.get("tool").is_none() {
+                    let mut table = Table::new();
+                    table.set_implicit(true);
+                    self.doc["tool"] = Item::Table(table);
+                }
+                let tool = &mut self.doc["tool"];
+                if tool.get("rye").is_none() {
+                    tool["rye"] = Item::Table(Table::new());
+                }
+                &mut tool["rye"]["dev-dependencies"]
+            }
             DependencyKind::Optional(ref section) => {
                 // add this as a proper non-inline table if it's missing
                 let table = &mut self.doc["project"]["optional-dependencies"];
                 if table.is_none() {
                     *table = Item::Table(Table::new());
                 }
-                &mut table[section as &str]
+                let section =
+                    find_optional_group(table, section).unwrap_or_else(|| section.to_string());
+                &mut table[&section]
             }
         };
         if dependencies.is_none() {
             *dependencies = Item::Value(Value::Array(Array::new()));
         }
-        set_dependency(
+        Ok(set_dependency(
             dependencies
                 .as_array_mut()
                 .ok_or_else(|| anyhow!("dependencies in pyproject.toml are malformed"))?,
             req,
-        );
-        Ok(())
+        ))
     }
 
     /// Removes a dependency
@@ -411,23 +924,76 @@ impl PyProject {
         req: &Requirement,
         kind: DependencyKind,
     ) -> Result<Option<Requirement>, Error> {
+        // missing tables must not be created by looking into them
         let dependencies = match kind {
-            DependencyKind::Normal => &mut self.doc["project"]["dependencies"],
-            DependencyKind::Dev => &mut self.doc["tool"]["rye"]["dev-dependencies"],
-            DependencyKind::Optional(ref section) => {
-                &mut self.doc["project"]["optional-dependencies"][section as &str]
-            }
+            DependencyKind::Normal => self
+                .doc
+                .get_mut("project")
+                .and_then(|x| x.get_mut("dependencies")),
+            DependencyKind::Dev => self
+                .doc
+                .get_mut("tool")
+                .and_then(|x| x.get_mut("rye"))
+                .and_then(|x| x.get_mut("dev-dependencies")),
+            DependencyKind::Optional(ref section) => self
+                .doc
+                .get_mut("project")
+                .and_then(|x| x.get_mut("optional-dependencies"))
+                .and_then(|table| {
+                    let section = find_optional_group(table, section)?;
+                    table.get_mut(&section)
+                }),
         };
-        if !dependencies.is_none() {
-            Ok(remove_dependency(
+        match dependencies.filter(|x| !x.is_none()) {
+            Some(dependencies) => Ok(remove_dependency(
                 dependencies
                     .as_array_mut()
                     .ok_or_else(|| anyhow!("dependencies in pyproject.toml are malformed"))?,
                 req,
-            ))
-        } else {
-            Ok(None)
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the name an optional dependency group is declared with.
+    pub fn optional_group(&self, section: &str) -> Option<String> {
+        self.doc
+            .get("project")
+            .and_then(|x| x.get("optional-dependencies"))
+            .and_then(|table| find_optional_group(table, section))
+    }
+
+    /// Removes an optional dependency group if it has no dependencies left.
+    ///
+    /// The `optional-dependencies` table goes away with its last group.
+    /// Returns `true` if the group was removed.
+    pub fn prune_optional_group(&mut self, section: &str) -> bool {
+        let Some(table) = self
+            .doc
+            .get_mut("project")
+            .and_then(|x| x.get_mut("optional-dependencies"))
+        else {
+            return false;
+        };
+        let Some(section) = find_optional_group(table, section) else {
+            return false;
+        };
+        if !table
+            .get(&section)
+            .and_then(|x| x.as_array())
+            .is_some_and(|x| x.is_empty())
+        {
+            return false;
+        }
+        if let Some(table) = table.as_table_like_mut() {
+            table.remove(&section);
+            if table.is_empty() {
+                self.doc["project"]
+                    .as_table_like_mut()
+                    .map(|x| x.remove("optional-dependencies"));
+            }
         }
+        true
     }
 
     /// Iterates over all dependencies.
@@ -455,6 +1021,139 @@ impl PyProject {
             .filter_map(|x| Requirement::from_str(x).ok())
     }
 
+    /// Checks if the resolver may pick pre-release versions.
+    pub fn allow_prereleases(&self) -> bool {
+        self.doc
+            .get("tool")
+            .and_then(|x| x.get("rye"))
+            .and_then(|x| x.get("allow-prereleases"))
+            .and_then(|x| x.as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Returns the Python versions the project supports.
+    pub fn requires_python(&self) -> Result<Option<VersionSpecifiers>, Error> {
+        self.doc
+            .get("project")
+            .and_then(|x| x.get("requires-python"))
+            .and_then(|x| x.as_str())
+            .map(|x| {
+                VersionSpecifiers::from_str(x)
+                    .map_err(|err| anyhow!("invalid requires-python '{}': {}", x, err))
+            })
+            .transpose()
+    }
+
+    /// Sets the `requires-python` of the project, keeping the formatting
+    /// around it.
+    pub fn set_requires_python(&mut self, requires_python: &str) {
+        let item = &mut self.doc["project"]["requires-python"];
+        let mut value = Value::from(requires_python);
+        if let Some(old) = item.as_value() {
+            *value.decor_mut() = old.decor().clone();
+        }
+        *item = Item::Value(value);
+    }
+
+    /// Checks if the projects are installed editable into the virtualenv.
+    ///
+    /// Members of a workspace use the setting of the workspace.
+    pub fn editable(&self) -> bool {
+        match self.workspace {
+            Some(ref workspace) => workspace.editable,
+            None => self
+                .doc
+                .get("tool")
+                .and_then(|x| x.get("rye"))
+                .and_then(|x| x.get("editable"))
+                .and_then(|x| x.as_bool()),
+        }
+        .unwrap_or(true)
+    }
+
+    /// Returns the configured constraints files relative to the project root.
+    pub fn constraints(&self) -> Vec<PathBuf> {
+        let Some(constraints) = self
+            .doc
+            .get("tool")
+            .and_then(|x| x.get("rye"))
+            .and_then(|x| x.get("constraints"))
+        else {
+            return Vec::new();
+        };
+        match constraints.as_array() {
+            Some(paths) => paths
+                .iter()
+                .filter_map(|x| x.as_str())
+                .map(PathBuf::from)
+                .collect(),
+            None => constraints
+                .as_str()
+                .map(PathBuf::from)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Returns the parts of the pyproject.toml that affect what is installed.
+    pub fn dependency_sections(&self) -> Vec<String> {
+        let project = self.doc.get("project");
+        let rye = self.doc.get("tool").and_then(|x| x.get("rye"));
+        [
+            project.and_then(|x| x.get("name")),
+            project.and_then(|x| x.get("requires-python")),
+            project.and_then(|x| x.get("dependencies")),
+            project.and_then(|x| x.get("optional-dependencies")),
+            rye.and_then(|x| x.get("dev-dependencies")),
+            rye.and_then(|x| x.get("allow-prereleases")),
+            rye.and_then(|x| x.get("constraints")),
+            rye.and_then(|x| x.get("sources")),
+            rye.and_then(|x| x.get("editable")),
+            rye.and_then(|x| x.get("hooks")),
+        ]
+        .into_iter()
+        .map(|x| x.map(|x| x.to_string()).unwrap_or_default())
+        .collect()
+    }
+
+    /// Returns the package sources to resolve and install from.
+    ///
+    /// Members of a workspace use the sources of the workspace.
+    pub fn sources(&self) -> Result<Vec<SourceRef>, Error> {
+        let item = match self.workspace {
+            Some(ref workspace) => &workspace.sources,
+            None => self
+                .doc
+                .get("tool")
+                .and_then(|x| x.get("rye"))
+                .and_then(|x| x.get("sources"))
+                .unwrap_or(&Item::None),
+        };
+        let mut rv = Vec::new();
+        if let Some(sources) = item.as_array_of_tables() {
+            for source in sources {
+                let source = SourceRef::from_item(source)?;
+                if rv.iter().any(|x: &SourceRef| x.name == source.name) {
+                    bail!("package source '{}' is declared twice", source.name);
+                }
+                rv.push(source);
+            }
+        } else if !item.is_none() {
+            bail!("tool.rye.sources must be an array of tables");
+        }
+        Ok(rv)
+    }
+
+    /// Returns the names of the optional dependency groups.
+    pub fn optional_dependency_groups(&self) -> Vec<String> {
+        self.doc
+            .get("project")
+            .and_then(|x| x.get("optional-dependencies"))
+            .and_then(|x| x.as_table_like())
+            .map(|x| x.iter().map(|(name, _)| name.to_string()).collect())
+            .unwrap_or_default()
+    }
+
     /// Save back changes
     pub fn save(&self) -> Result<(), Error> {
         fs::write(self.toml_path(), self.doc.to_string()).with_context(|| {
@@ -464,74 +1163,515 @@ impl PyProject {
     }
 }
 
+/// Checks if a name is valid for a package.
+pub fn is_valid_package_name(name: &str) -> bool {
+    NAME_RE.is_match(name)
+}
+
+/// Validates the name of an optional dependency group.
+///
+/// Groups become extras of the package, so their names follow the same
+/// rules as package names.
+pub fn parse_extra_name(value: &str) -> Result<String, Error> {
+    if !is_valid_package_name(value) {
+        bail!(
+            "invalid group name '{}', only letters, digits, '-', '_' and '.' are \
+             allowed and it has to start and end with a letter or digit",
+            value
+        );
+    }
+    Ok(value.to_string())
+}
+
+/// Normalizes a package name according to PEP 503.
+///
+/// Runs of `-`, `_` and `.` become a single `-` and the name is lowercased.
 pub fn normalize_package_name(x: &str) -> String {
     NORMALIZATION_SPLIT_RE
-        .split(x)
-        .fold(String::new(), |mut acc, item| {
-            if !acc.is_empty() {
-                acc.push('-');
-            }
-            acc.push_str(&item.to_ascii_lowercase());
-            acc
-        })
+        .replace_all(x, "-")
+        .to_ascii_lowercase()
 }
 
-fn set_dependency(deps: &mut Array, req: &Requirement) {
-    let mut to_replace = None;
-    for (idx, dep) in deps.iter().enumerate() {
-        if let Some(dep) = dep.as_str() {
-            if let Ok(dep_req) = Requirement::from_str(dep) {
-                if dep_req.name.eq_ignore_ascii_case(&req.name) {
-                    to_replace = Some(idx);
-                    break;
-                }
-            }
-        }
-    }
+/// Finds the key of an optional dependency group.
+///
+/// Group names are compared in their normalized form like extras.
+fn find_optional_group(table: &Item, section: &str) -> Option<String> {
+    let section = normalize_package_name(section);
+    table
+        .as_table_like()?
+        .iter()
+        .map(|x| x.0)
+        .find(|x| normalize_package_name(x) == section)
+        .map(|x| x.to_string())
+}
+
+/// Finds the entry of a package in a dependency array by its canonical name.
+fn find_dependency(deps: &Array, name: &str) -> Option<usize> {
+    let name = normalize_package_name(name);
+    deps.iter().position(|dep| {
+        dep.as_str()
+            .and_then(|x| Requirement::from_str(x).ok())
+            .is_some_and(|x| normalize_package_name(&x.name) == name)
+    })
+}
 
+/// Returns the canonical name of the package of a dependency entry.
+fn dependency_sort_key(dep: &Value) -> Option<String> {
+    let dep = dep.as_str()?;
+    Some(match Requirement::from_str(dep) {
+        Ok(req) => normalize_package_name(&req.name),
+        Err(_) => dep.to_ascii_lowercase(),
+    })
+}
+
+/// Adds or replaces a dependency and returns the entry it replaced.
+///
+/// New entries keep an array sorted if it was sorted by package name.
+fn set_dependency(deps: &mut Array, req: &Requirement) -> Option<String> {
     let formatted = format_requirement(req).to_string();
-    if let Some(idx) = to_replace {
+    if let Some(idx) = find_dependency(deps, &req.name) {
+        let old = deps
+            .get(idx)
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_string());
         deps.replace(idx, formatted);
-    } else {
-        deps.push(formatted);
+        return old;
     }
+
+    let keys: Vec<_> = deps.iter().map(dependency_sort_key).collect();
+    let sorted = keys.windows(2).all(|x| x[0] <= x[1]);
+    let name = Some(normalize_package_name(&req.name));
+    let idx = keys
+        .iter()
+        .position(|x| *x > name)
+        .filter(|_| sorted)
+        .unwrap_or(keys.len());
+    // the new entry is indented like its neighbour, leaving comments alone.
+    // The first entry of an array on one line is the only one without space.
+    let neighbour = deps.get(idx).or_else(|| deps.get(idx.wrapping_sub(1)));
+    let prefix = neighbour
+        .and_then(|x| x.decor().prefix())
+        .and_then(|x| x.as_str())
+        .map(|x| match x.rfind('\n') {
+            Some(pos) => x[pos..].to_string(),
+            None if idx == 0 => x.to_string(),
+            None => " ".into(),
+        });
+    deps.insert(idx, formatted);
+    if let Some(prefix) = prefix {
+        deps.get_mut(idx).unwrap().decor_mut().set_prefix(prefix);
+    }
+    if let Some(moved) = deps.get_mut(idx + 1) {
+        if moved
+            .decor()
+            .prefix()
+            .and_then(|x| x.as_str())
+            .is_some_and(|x| !x.contains('\n'))
+        {
+            moved.decor_mut().set_prefix(" ");
+        }
+    }
+    None
 }
 
+/// Removes a dependency and returns it.
+///
+/// Comments in front of the entry are kept for the entry that follows it.
 fn remove_dependency(deps: &mut Array, req: &Requirement) -> Option<Requirement> {
-    let mut to_remove = None;
-    for (idx, dep) in deps.iter().enumerate() {
-        if let Some(dep) = dep.as_str() {
-            if let Ok(dep_req) = Requirement::from_str(dep) {
-                if dep_req.name.eq_ignore_ascii_case(&req.name) {
-                    to_remove = Some(idx);
-                    break;
-                }
+    let idx = find_dependency(deps, &req.name)?;
+    let removed = deps.remove(idx);
+    let prefix = removed
+        .decor()
+        .prefix()
+        .and_then(|x| x.as_str())
+        .unwrap_or_default();
+    let comments = prefix.rfind('\n').map(|pos| &prefix[..pos]);
+    match deps.get_mut(idx) {
+        Some(next) => {
+            let next_prefix = next
+                .decor()
+                .prefix()
+                .and_then(|x| x.as_str())
+                .unwrap_or_default()
+                .to_string();
+            match comments {
+                Some(comments) => next
+                    .decor_mut()
+                    .set_prefix(comments.to_string() + &next_prefix),
+                // on a single line the next entry takes the spacing
+                None if !next_prefix.contains('\n') => next.decor_mut().set_prefix(prefix),
+                None => {}
+            }
+        }
+        None => {
+            if let Some(comments) = comments {
+                let trailing = deps.trailing().as_str().unwrap_or_default().to_string();
+                deps.set_trailing(comments.to_string() + &trailing);
             }
         }
     }
+    removed.as_str().and_then(|x| Requirement::from_str(x).ok())
+}
 
-    if let Some(idx) = to_remove {
-        deps.remove(idx)
-            .as_str()
-            .and_then(|x| Requirement::from_str(x).ok())
-    } else {
-        None
+/// Locates the version in the contents of a version file.
+///
+/// This is the value of a `__version__` (or `VERSION`) assignment like in a
+/// Python module or otherwise the entire file.
+fn find_version_span(contents: &str) -> Option<Range<usize>> {
+    if let Some(m) = VERSION_ASSIGN_RE.captures(contents).and_then(|x| x.get(1)) {
+        return Some(m.range());
+    }
+    let trimmed = contents.trim();
+    if trimmed.is_empty() || trimmed.contains(char::is_whitespace) {
+        return None;
+    }
+    let start = contents.find(trimmed)?;
+    Some(start..start + trimmed.len())
+}
+
+/// Reads the version from a version file.
+fn read_version_file(path: &Path) -> Result<Version, Error> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let span = find_version_span(&contents)
+        .ok_or_else(|| anyhow!("could not find a version in {}", path.display()))?;
+    Version::from_str(&contents[span])
+        .map_err(|err| anyhow!("invalid version in {}: {}", path.display(), err))
+}
+
+/// Replaces the version in a version file.
+pub fn write_version_file(path: &Path, version: &Version) -> Result<(), Error> {
+    let mut contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let span = find_version_span(&contents)
+        .ok_or_else(|| anyhow!("could not find a version in {}", path.display()))?;
+    contents.replace_range(span, &version.to_string());
+    fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Derives the version from the git tags of the repository at a path.
+fn get_git_version(path: &Path) -> Result<Version, Error> {
+    let rv = Command::new("git")
+        .arg("describe")
+        .arg("--tags")
+        .arg("--long")
+        .arg("--dirty")
+        .arg("--match")
+        .arg("*[0-9]*")
+        .current_dir(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .context("failed to run git")?;
+    if !rv.status.success() {
+        bail!(
+            "could not determine the version from git: {}",
+            String::from_utf8_lossy(&rv.stderr).trim()
+        );
+    }
+    parse_git_describe(String::from_utf8_lossy(&rv.stdout).trim())
+}
+
+/// Turns the output of `git describe --tags --long --dirty` into a version.
+///
+/// A tagged commit has the version of the tag, a leading `v` is ignored.
+/// Later commits and uncommitted changes give a development release of the
+/// next version like setuptools-scm does, so three commits after `v1.2.3`
+/// are `1.2.4.dev3+g1a2b3c4`.
+fn parse_git_describe(describe: &str) -> Result<Version, Error> {
+    let (describe, dirty) = match describe.strip_suffix("-dirty") {
+        Some(rest) => (rest, true),
+        None => (describe, false),
+    };
+    let mut parts = describe.rsplitn(3, '-');
+    let (hash, distance, tag) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(hash), Some(distance), Some(tag)) => (hash, distance, tag),
+        _ => bail!("unexpected output from git describe: {}", describe),
+    };
+    let distance: usize = distance
+        .parse()
+        .with_context(|| format!("unexpected output from git describe: {}", describe))?;
+    let mut version = Version::from_str(tag.strip_prefix('v').unwrap_or(tag))
+        .map_err(|err| anyhow!("tag {} is not a valid version: {}", tag, err))?;
+    if distance == 0 && !dirty {
+        return Ok(version);
+    }
+
+    match version.pre {
+        Some((kind, number)) => version.pre = Some((kind, number + 1)),
+        None => {
+            if let Some(last) = version.release.last_mut() {
+                *last += 1;
+            }
+        }
     }
+    version.post = None;
+    version.dev = Some(distance);
+    version.local = Some(vec![LocalSegment::String(hash.to_string())]);
+    Ok(version)
 }
 
-pub fn find_project_root() -> Option<PathBuf> {
-    let mut here = env::current_dir().ok()?;
+/// Checks if the search for projects stops at a folder.
+fn is_discovery_boundary(path: &Path, boundaries: &[String]) -> bool {
+    boundaries.iter().any(|x| path.join(x).exists())
+}
+
+/// Searches a folder and its parents for a `pyproject.toml`.
+///
+/// The search ends with the root of a repository so that unrelated projects
+/// further up are not picked up.  If nothing is found, the last folder that
+/// was searched is returned as error.
+fn search_project_root(path: &Path, boundaries: &[String]) -> Result<PathBuf, PathBuf> {
+    let mut here = path.to_path_buf();
 
     loop {
         let project_file = here.join("pyproject.toml");
         if project_file.is_file() {
-            return Some(here.to_path_buf());
+            return Ok(here);
         }
 
-        if !here.pop() {
-            break;
+        if is_discovery_boundary(&here, boundaries) || !here.pop() {
+            return Err(here);
         }
     }
+}
 
-    None
+#[test]
+fn test_split_script_cmd() {
+    assert_eq!(
+        split_script_cmd("flask run --debug").unwrap(),
+        ["flask", "run", "--debug"]
+    );
+    assert_eq!(
+        split_script_cmd(r#"pytest -k "a and not b" it\'s '{args}'"#).unwrap(),
+        ["pytest", "-k", "a and not b", "it's", "{args}"]
+    );
+    assert_eq!(
+        split_script_cmd("python -c 'print(1 | 2); print(3)' '&&' \\|").unwrap(),
+        ["python", "-c", "print(1 | 2); print(3)", "&&", "|"]
+    );
+    assert!(split_script_cmd("echo 'unbalanced").is_err());
+    assert_eq!(find_shell_operator("black . && ruff ."), Some("&&"));
+    assert_eq!(find_shell_operator("cat log|grep error"), Some("|"));
+    assert_eq!(find_shell_operator("echo a; echo b"), Some(";"));
+    assert_eq!(find_shell_operator("serve > out.log"), Some(">"));
+    assert_eq!(find_shell_operator("pip install foo>=1"), None);
+}
+
+#[test]
+fn test_sources() {
+    let doc: Document = r#"
+[[tool.rye.sources]]
+name = "internal"
+url = "https://pypi.example.com/simple/"
+
+[[tool.rye.sources]]
+name = "wheels"
+url = "https://example.com/wheels/"
+type = "find-links"
+
+[[tool.rye.sources]]
+name = "default"
+url = "https://mirror.example.com/simple/"
+"#
+    .parse()
+    .unwrap();
+    let pyproject = PyProject {
+        root: PathBuf::from("/project"),
+        workspace: None,
+        doc,
+    };
+    let sources = pyproject.sources().unwrap();
+    assert_eq!(sources[1].kind, SourceKind::FindLinks);
+    assert_eq!(
+        get_source_options(&sources),
+        vec![
+            "--index-url https://mirror.example.com/simple/",
+            "--extra-index-url https://pypi.example.com/simple/",
+            "--find-links https://example.com/wheels/",
+        ]
+    );
+}
+
+#[test]
+fn test_set_dependency() {
+    let mut doc: Document = r#"
+[project]
+dependencies = [
+    "Flask>=2.0",
+    "requests~=2.31",
+]
+"#
+    .parse()
+    .unwrap();
+    let deps = doc["project"]["dependencies"].as_array_mut().unwrap();
+    let old = set_dependency(deps, &Requirement::from_str("flask==3.0").unwrap());
+    assert_eq!(old.as_deref(), Some("Flask>=2.0"));
+    assert_eq!(
+        set_dependency(deps, &Requirement::from_str("jinja2").unwrap()),
+        None
+    );
+    set_dependency(deps, &Requirement::from_str("zope.interface").unwrap());
+    set_dependency(deps, &Requirement::from_str("attrs").unwrap());
+    let names: Vec<_> = deps.iter().filter_map(|x| x.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "attrs",
+            "flask==3.0",
+            "jinja2",
+            "requests~=2.31",
+            "zope.interface"
+        ]
+    );
+
+    let mut doc: Document = "dependencies = [\"b\", \"a\"]".parse().unwrap();
+    let deps = doc["dependencies"].as_array_mut().unwrap();
+    set_dependency(deps, &Requirement::from_str("Foo_Bar").unwrap());
+    let names: Vec<_> = deps.iter().filter_map(|x| x.as_str()).collect();
+    assert_eq!(names, vec!["b", "a", "Foo_Bar"]);
+}
+
+#[test]
+fn test_remove_dependency() {
+    let mut doc: Document = r#"
+[project]
+dependencies = [
+    # web
+    "Flask>=2.0",
+    "requests~=2.31",
+]
+optional = ["a", "b"]
+"#
+    .parse()
+    .unwrap();
+    let deps = doc["project"]["dependencies"].as_array_mut().unwrap();
+    let removed = remove_dependency(deps, &Requirement::from_str("flask").unwrap());
+    assert_eq!(removed.unwrap().name, "Flask");
+    assert!(remove_dependency(deps, &Requirement::from_str("flask").unwrap()).is_none());
+    let deps = doc["project"]["optional"].as_array_mut().unwrap();
+    remove_dependency(deps, &Requirement::from_str("a").unwrap());
+    assert_eq!(
+        doc.to_string(),
+        r#"
+[project]
+dependencies = [
+    # web
+    "requests~=2.31",
+]
+optional = ["b"]
+"#
+    );
+}
+
+#[test]
+fn test_workspace_is_member() {
+    let workspace = Workspace {
+        root: PathBuf::from("/repo"),
+        members: vec!["packages/*".into(), "tools/cli/".into()],
+        scripts: Item::None,
+        sources: Item::None,
+        editable: None,
+    };
+    assert!(workspace.is_member(Path::new("/repo")));
+    assert!(workspace.is_member(Path::new("/repo/packages/a")));
+    assert!(workspace.is_member(Path::new("/repo/tools/cli")));
+    assert!(!workspace.is_member(Path::new("/repo/examples/demo")));
+    assert!(!workspace.is_member(Path::new("/other/packages/a")));
+}
+
+#[test]
+fn test_workspace_skips_non_members() {
+    let dir = tempfile::tempdir().unwrap();
+    let member = dir.path().join("packages").join("a");
+    let other = dir.path().join("examples").join("demo");
+    fs::create_dir_all(&member).unwrap();
+    fs::create_dir_all(&other).unwrap();
+    fs::write(
+        dir.path().join("pyproject.toml"),
+        "[project]\nname = \"root\"\n",
+    )
+    .unwrap();
+    fs::write(member.join("pyproject.toml"), "[project]\nname = \"a\"\n").unwrap();
+    fs::write(other.join("pyproject.toml"), "not [valid toml").unwrap();
+
+    let workspace = Arc::new(Workspace {
+        root: dir.path().to_path_buf(),
+        members: vec!["packages/*".into()],
+        scripts: Item::None,
+        sources: Item::None,
+        editable: None,
+    });
+    let mut names = workspace
+        .iter_projects()
+        .map(|x| x.unwrap().name().unwrap().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["a", "root"]);
+}
+
+#[test]
+fn test_search_project_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    let nested = repo.join("docs").join("api");
+    fs::create_dir_all(&nested).unwrap();
+    fs::create_dir(repo.join(".git")).unwrap();
+    fs::write(dir.path().join("pyproject.toml"), "").unwrap();
+
+    let boundaries = vec![".git".to_string()];
+    assert_eq!(search_project_root(&nested, &boundaries), Err(repo.clone()));
+    assert_eq!(
+        search_project_root(&nested, &[]),
+        Ok(dir.path().to_path_buf())
+    );
+    fs::write(repo.join("pyproject.toml"), "").unwrap();
+    assert_eq!(search_project_root(&nested, &boundaries), Ok(repo));
+}
+
+#[test]
+fn test_normalize_package_name() {
+    assert_eq!(normalize_package_name("Flask"), "flask");
+    assert_eq!(normalize_package_name("zope.interface"), "zope-interface");
+    assert_eq!(normalize_package_name("zope-interface"), "zope-interface");
+    assert_eq!(normalize_package_name("Zope_Interface"), "zope-interface");
+    assert_eq!(normalize_package_name("foo__bar"), "foo-bar");
+    assert_eq!(normalize_package_name("foo.-_bar"), "foo-bar");
+    assert_eq!(normalize_package_name("FOO---BAR...baz"), "foo-bar-baz");
+    assert_eq!(normalize_package_name("_private"), "-private");
+    assert_eq!(normalize_package_name("trailing."), "trailing-");
+}
+
+#[test]
+fn test_version_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("__about__.py");
+    fs::write(
+        &path,
+        "# comment\n__version__: str = \"1.2.3\"\nfoo = '4.5.6'\n",
+    )
+    .unwrap();
+    assert_eq!(read_version_file(&path).unwrap().to_string(), "1.2.3");
+    write_version_file(&path, &Version::from_str("1.3.0").unwrap()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "# comment\n__version__: str = \"1.3.0\"\nfoo = '4.5.6'\n"
+    );
+
+    let path = dir.path().join("VERSION");
+    fs::write(&path, "2.0.0rc1\n").unwrap();
+    assert_eq!(read_version_file(&path).unwrap().to_string(), "2.0.0rc1");
+    write_version_file(&path, &Version::from_str("2.0.0").unwrap()).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "2.0.0\n");
+}
+
+#[test]
+fn test_parse_git_describe() {
+    let parse = |describe| parse_git_describe(describe).unwrap().to_string();
+    assert_eq!(parse("v1.2.3-0-g1a2b3c4"), "1.2.3");
+    assert_eq!(parse("1.2.3-3-g1a2b3c4"), "1.2.4.dev3+g1a2b3c4");
+    assert_eq!(parse("v1.2.3-0-g1a2b3c4-dirty"), "1.2.4.dev0+g1a2b3c4");
+    assert_eq!(parse("v2.0.0rc1-2-g1a2b3c4"), "2.0.0rc2.dev2+g1a2b3c4");
+    assert_eq!(parse("v1.0.post1-1-g1a2b3c4"), "1.1.dev1+g1a2b3c4");
+    assert!(parse_git_describe("release-x-1-g1a2b3c4").is_err());
 }
@@ -4,6 +4,7 @@ use std::fmt;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Error};
+use pep440_rs::{Version, VersionSpecifiers};
 use serde::{de, Deserialize, Serialize};
 
 mod indygreg_python {
@@ -40,6 +41,15 @@ impl PythonVersion {
         .expect("unsupported platform")
         .0
     }
+
+    /// Checks if this version satisfies a `requires-python` specification.
+    pub fn satisfies(&self, requires_python: &VersionSpecifiers) -> bool {
+        requires_python.contains(&Version::from_release(vec![
+            self.major as usize,
+            self.minor as usize,
+            self.patch as usize,
+        ]))
+    }
 }
 
 impl Serialize for PythonVersion {
@@ -169,13 +179,17 @@ impl fmt::Display for PythonVersionRequest {
             write!(f, ".{}", minor)?;
             if let Some(ref patch) = self.patch {
                 write!(f, ".{}", patch)?;
+                if let Some(ref suffix) = self.suffix {
+                    write!(f, ".{}", suffix)?;
+                }
             }
         }
         Ok(())
     }
 }
 
-fn matches_version(req: &PythonVersionRequest, v: &PythonVersion) -> bool {
+/// Checks if a python version satisfies a version request.
+pub fn matches_version(req: &PythonVersionRequest, v: &PythonVersion) -> bool {
     if req.kind.as_deref().unwrap_or(DEFAULT_KIND) != v.kind {
         return false;
     }
@@ -242,3 +256,20 @@ fn test_get_download_url() {
     let url = get_download_url(&"3".parse().unwrap(), "macos", "aarch64");
     assert_eq!(url, Some((PythonVersion { kind: "cpython".into(), major: 3, minor: 11, patch: 1, suffix: None }, "https://github.com/indygreg/python-build-standalone/releases/download/20230116/cpython-3.11.1%2B20230116-aarch64-apple-darwin-pgo-full.tar.zst")));
 }
+
+#[test]
+fn test_satisfies() {
+    let requires_python = ">=3.10, <3.12".parse().unwrap();
+    let version = |x: &str| x.parse::<PythonVersion>().unwrap();
+    assert!(version("cpython@3.11.4").satisfies(&requires_python));
+    assert!(!version("cpython@3.9.17").satisfies(&requires_python));
+    assert!(!version("pypy@3.12.0").satisfies(&requires_python));
+}
+
+#[test]
+fn test_version_request_roundtrip() {
+    for version in ["3", "3.11", "cpython@3.11.1", "cpython@3.11.0.11"] {
+        let req: PythonVersionRequest = version.parse().unwrap();
+        assert_eq!(req.to_string(), version);
+    }
+}
@@ -1,24 +1,38 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::env::consts::{ARCH, OS};
+use std::fs;
+use std::io::Write;
 #[cfg(not(target_os = "windows"))]
 use std::os::unix::fs::symlink;
 #[cfg(target_os = "windows")]
 use std::os::windows::fs::symlink_file;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{env, fs};
+use std::str::FromStr;
 
-use anyhow::{bail, Context, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use console::style;
+use pep440_rs::{Version, VersionSpecifiers};
 use serde::{Deserialize, Serialize};
-use tempfile::TempDir;
+use tempfile::{NamedTempFile, TempDir};
+use url::Url;
 
-use crate::bootstrap::{ensure_self_venv, fetch, get_pip_module};
-use crate::config::{get_py_bin, load_python_version};
+use crate::auth::{get_index_urls, IndexAuth};
+use crate::backend::Backend;
+use crate::bootstrap::{ensure_self_venv, fetch, get_pip_module, is_offline};
+use crate::config::{
+    find_python_version_file, get_autosync, get_py_bin, list_known_toolchains,
+    load_python_version_file,
+};
 use crate::lock::{
-    update_single_project_lockfile, update_workspace_lockfile, LockMode, LockOptions,
+    ensure_find_links, ensure_sources, ensure_updatable, get_lockfiles, has_hashes,
+    read_locked_pins, read_lockfile_features, report_pin_changes, select_lockfile_features,
+    update_single_project_lockfile, update_workspace_lockfile, Fingerprint, LockMode, LockOptions,
 };
-use crate::pyproject::PyProject;
-use crate::sources::PythonVersion;
-use crate::utils::CommandOutput;
+use crate::pyproject::{normalize_package_name, PyProject};
+use crate::sources::{iter_downloadable, PythonVersion};
+use crate::utils::{format_command, CommandOutput, QuietExit, VENV_BIN};
 
 /// Controls the sync mode
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
@@ -47,6 +61,21 @@ pub struct SyncOptions {
     pub force: bool,
     /// Controls locking.
     pub lock_options: LockOptions,
+    /// The pyproject.toml of the project to sync instead of discovering it.
+    pub pyproject: Option<PathBuf>,
+    /// Install from the existing lockfiles without updating them.
+    pub no_lock: bool,
+    /// Only install the dependencies but not the projects themselves.
+    pub no_install_project: bool,
+    /// Install the projects regularly instead of editable.
+    pub no_editable: bool,
+    /// Sets the prompt of an existing virtualenv to the project name.
+    pub update_prompt: bool,
+    /// Refuses to install packages that could come from another package
+    /// source than the one they were locked from.
+    pub strict_sources: bool,
+    /// Skips the post-sync hooks.
+    pub no_hooks: bool,
 }
 
 impl SyncOptions {
@@ -63,37 +92,235 @@ impl SyncOptions {
 #[derive(Serialize, Deserialize, Debug)]
 struct VenvMarker {
     python: PythonVersion,
+    /// What the dependencies were last synced from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    synced: Option<SyncStamp>,
+    /// Whether the projects are installed editable, if they are installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    editable: Option<bool>,
+    /// The features installed from the lockfile unless it is all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    features: Option<Vec<String>>,
+}
+
+/// Records the inputs of the last successful sync.
+#[derive(Serialize, Deserialize, Debug)]
+struct SyncStamp {
+    inputs: String,
+    dev: bool,
+}
+
+fn read_venv_marker(venv: &Path) -> Result<VenvMarker, Error> {
+    let contents =
+        fs::read(venv.join("rye-venv.json")).context("could not read venv marker file")?;
+    serde_json::from_slice(&contents).context("malformed venv marker file")
+}
+
+/// Replaces the marker file so that it is never seen half written.
+fn write_venv_marker(venv: &Path, marker: &VenvMarker) -> Result<(), Error> {
+    let mut tmp = NamedTempFile::new_in(venv)?;
+    tmp.write_all(serde_json::to_string_pretty(marker)?.as_bytes())?;
+    tmp.persist(venv.join("rye-venv.json"))?;
+    Ok(())
+}
+
+/// Returns the Python version a rye managed virtualenv was created with.
+pub fn get_venv_python_version(venv: &Path) -> Result<PythonVersion, Error> {
+    Ok(read_venv_marker(venv)?.python)
+}
+
+/// Returns whether the projects are installed editable into a virtualenv.
+///
+/// This is `None` if the projects are not installed or it is not known.
+pub fn get_venv_editable(venv: &Path) -> Option<bool> {
+    read_venv_marker(venv).ok().and_then(|x| x.editable)
+}
+
+/// Fingerprints what the dependencies of a virtualenv are synced from.
+///
+/// These are the dependency related sections and constraints files of all
+/// projects, the lockfile that is installed, the Python version and the
+/// version of rye.
+fn fingerprint_sync_inputs(
+    pyproject: &PyProject,
+    py_ver: &PythonVersion,
+    lockfile: &Path,
+    dev_lockfile: &Path,
+    dev: bool,
+) -> Result<String, Error> {
+    fn sync_sections(project: &PyProject) -> (PathBuf, Vec<String>) {
+        let root = project.root_path().into_owned();
+        let mut sections = project.dependency_sections();
+        sections.extend(
+            project
+                .constraints()
+                .iter()
+                .map(|path| fs::read_to_string(root.join(path)).unwrap_or_default()),
+        );
+        (root, sections)
+    }
+
+    let mut projects = Vec::new();
+    match pyproject.workspace() {
+        Some(workspace) => {
+            for project in workspace.iter_projects() {
+                projects.push(sync_sections(&project?));
+            }
+        }
+        None => projects.push(sync_sections(pyproject)),
+    }
+    projects.sort();
+
+    let mut hash = Fingerprint::new();
+    hash.feed(env!("CARGO_PKG_VERSION").as_bytes());
+    hash.feed(py_ver.to_string().as_bytes());
+    for (root, sections) in &projects {
+        hash.feed(root.to_string_lossy().as_bytes());
+        for section in sections {
+            hash.feed(section.as_bytes());
+        }
+    }
+    let lockfile = if dev && dev_lockfile.is_file() {
+        dev_lockfile
+    } else {
+        lockfile
+    };
+    hash.feed(&fs::read(lockfile).unwrap_or_default());
+    Ok(hash.finish())
+}
+
+/// Checks if automatic syncing is turned off with `RYE_NO_AUTO_SYNC`.
+pub fn is_autosync_disabled_by_env() -> bool {
+    env::var_os("RYE_NO_AUTO_SYNC").is_some_and(|x| !x.is_empty() && x != "0")
+}
+
+/// Makes sure the virtualenv of a project is ready to be used.
+///
+/// The Python installation is always synced.  If the dependencies or
+/// lockfiles changed since the last sync, the dependencies are synced too
+/// unless `behavior.autosync` is disabled in the config.
+pub fn autosync(pyproject: &PyProject, output: CommandOutput) -> Result<(), Error> {
+    sync(SyncOptions {
+        output,
+        pyproject: Some(pyproject.toml_path().into_owned()),
+        ..SyncOptions::python_only()
+    })?;
+    if is_autosync_disabled_by_env() || !get_autosync()? {
+        return Ok(());
+    }
+
+    let marker = read_venv_marker(&pyproject.venv_path())?;
+    let synced = marker.synced;
+    let dev = synced.as_ref().is_none_or(|x| x.dev);
+    let (lockfile, dev_lockfile) = get_lockfiles(&pyproject.workspace_path(), None)?;
+    let inputs = fingerprint_sync_inputs(pyproject, &marker.python, &lockfile, &dev_lockfile, dev)?;
+    if synced.is_some_and(|x| x.inputs == inputs) {
+        return Ok(());
+    }
+
+    if output != CommandOutput::Quiet {
+        eprintln!("Dependencies changed, syncing virtualenv");
+    }
+    sync(SyncOptions {
+        output: match output {
+            CommandOutput::Normal => CommandOutput::Quiet,
+            other => other,
+        },
+        dev,
+        mode: SyncMode::Regular,
+        pyproject: Some(pyproject.toml_path().into_owned()),
+        ..Default::default()
+    })
 }
 
 /// Synchronizes a project's virtualenv.
 pub fn sync(cmd: SyncOptions) -> Result<(), Error> {
-    let pyproject = PyProject::discover()?;
-    let lockfile = pyproject.workspace_path().join("requirements.lock");
-    let dev_lockfile = pyproject.workspace_path().join("requirements-dev.lock");
+    let pyproject = match cmd.pyproject {
+        Some(ref path) => PyProject::load(path)?,
+        None => PyProject::discover()?,
+    };
+    let (lockfile, dev_lockfile) = get_lockfiles(
+        &pyproject.workspace_path(),
+        cmd.lock_options.platform.as_deref(),
+    )?;
     let venv = pyproject.venv_path();
-    let py_ver = load_python_version().unwrap_or_else(PythonVersion::latest_cpython);
+    let requires_python = collect_requires_python(&pyproject)?;
+    let pin_file = find_python_version_file(&pyproject.root_path());
+    let py_ver = match pin_file {
+        Some(ref pin_file) => load_python_version_file(pin_file)?,
+        None => pick_python_version(&requires_python)?,
+    };
+    check_requires_python(&py_ver, &requires_python, pin_file.as_deref())?;
     let marker_file = venv.join("rye-venv.json");
     let output = cmd.output;
+    let editable = !cmd.no_editable && pyproject.editable();
+
+    // nothing changed since the last sync
+    if is_synced(
+        &cmd,
+        &pyproject,
+        &py_ver,
+        &lockfile,
+        &dev_lockfile,
+        editable,
+    )? {
+        if output != CommandOutput::Quiet && cmd.mode != SyncMode::PythonOnly {
+            eprintln!("Virtualenv is up to date");
+        }
+        return Ok(());
+    }
 
     // ensure we are bootstrapped
     let self_venv = ensure_self_venv(output).context("could not sync because bootstrap failed")?;
 
+    // dry runs only report what would change
+    if cmd.lock_options.dry_run {
+        if cmd.mode == SyncMode::LockOnly {
+            return update_lockfiles(&cmd, &pyproject, &lockfile, &dev_lockfile);
+        }
+        let pending = plan_sync(
+            &cmd,
+            &pyproject,
+            &self_venv,
+            &py_ver,
+            &lockfile,
+            &dev_lockfile,
+            editable,
+        )?;
+        if pending {
+            return Err(QuietExit(1).into());
+        }
+        return Ok(());
+    }
+
     let mut recreate = cmd.mode == SyncMode::Full;
     if venv.is_dir() {
         if marker_file.is_file() {
-            let contents = fs::read(&marker_file).context("could not read venv marker file")?;
-            let marker: VenvMarker =
-                serde_json::from_slice(&contents).context("malformed venv marker file")?;
-            if marker.python != py_ver {
-                if cmd.output != CommandOutput::Quiet {
-                    eprintln!(
-                        "Python version mismatch (found {}, expect {}), recreating.",
-                        marker.python, py_ver
-                    );
+            // a forced sync recreates the virtualenv anyways, so a broken
+            // marker is not an error then.
+            match get_venv_python_version(&venv) {
+                Ok(venv_py_ver) if venv_py_ver != py_ver => {
+                    if cmd.output != CommandOutput::Quiet {
+                        eprintln!(
+                            "Python version mismatch (found {}, expect {}), recreating.",
+                            venv_py_ver, py_ver
+                        );
+                    }
+                    recreate = true;
                 }
-                recreate = true;
+                Ok(_) => {}
+                Err(_) if recreate => {}
+                Err(err) => return Err(err),
             }
         } else if cmd.force {
+            // only delete what looks like a virtualenv so that a path that
+            // points somewhere else by accident is left alone.
+            if !venv.join("pyvenv.cfg").is_file() && fs::read_dir(&venv)?.next().is_some() {
+                bail!(
+                    "{} does not look like a virtualenv, refusing to delete it",
+                    venv.display()
+                );
+            }
             if cmd.output != CommandOutput::Quiet {
                 eprintln!("Forcing re-creation of non rye managed virtualenv");
             }
@@ -108,8 +335,9 @@ pub fn sync(cmd: SyncOptions) -> Result<(), Error> {
         fetch(&py_ver.into(), output).context("failed fetching toolchain ahead of sync")?;
 
     // kill the virtualenv if it's there and we need to get rid of it.
-    if recreate {
-        fs::remove_dir_all(&venv).ok();
+    if recreate && venv.is_dir() {
+        fs::remove_dir_all(&venv)
+            .with_context(|| format!("failed to remove virtualenv {}", venv.display()))?;
     }
 
     if venv.is_dir() {
@@ -119,6 +347,21 @@ pub fn sync(cmd: SyncOptions) -> Result<(), Error> {
         {
             eprintln!("Reusing already existing virtualenv");
         }
+        // virtualenv keeps the installed packages when run again, it only
+        // replaces the activation scripts and the configuration.
+        if cmd.update_prompt {
+            if output != CommandOutput::Quiet {
+                eprintln!("Updating virtualenv prompt");
+            }
+            create_virtualenv(
+                output,
+                &self_venv,
+                &py_ver,
+                &venv,
+                &get_venv_prompt(&pyproject),
+            )
+            .context("failed updating virtualenv prompt")?;
+        }
     } else {
         if output != CommandOutput::Quiet {
             eprintln!(
@@ -127,15 +370,46 @@ pub fn sync(cmd: SyncOptions) -> Result<(), Error> {
             );
             eprintln!("Python version: {}", style(&py_ver).cyan());
         }
-        create_virtualenv(output, &self_venv, &py_ver, &venv)
-            .context("failed creating virtualenv ahead of sync")?;
-        fs::write(
-            &marker_file,
-            serde_json::to_string_pretty(&VenvMarker { python: py_ver })?,
+        create_virtualenv(
+            output,
+            &self_venv,
+            &py_ver,
+            &venv,
+            &get_venv_prompt(&pyproject),
+        )
+        .context("failed creating virtualenv ahead of sync")?;
+        write_venv_marker(
+            &venv,
+            &VenvMarker {
+                python: py_ver.clone(),
+                synced: None,
+                editable: None,
+                features: None,
+            },
         )
         .context("failed writing venv marker file")?;
     }
 
+    // unknown hooks would only fail after installing everything
+    if !cmd.no_hooks && matches!(cmd.mode, SyncMode::Regular | SyncMode::Full) {
+        for hook in pyproject.post_sync_hooks() {
+            if !pyproject.is_declared_script(&hook) {
+                bail!(
+                    "post-sync hook '{}' is not a script in tool.rye.scripts",
+                    hook
+                );
+            }
+        }
+    }
+
+    // a sync that is interrupted must not look complete
+    if !matches!(cmd.mode, SyncMode::PythonOnly | SyncMode::LockOnly) {
+        let mut marker = read_venv_marker(&venv)?;
+        if marker.synced.take().is_some() {
+            write_venv_marker(&venv, &marker).context("failed writing venv marker file")?;
+        }
+    }
+
     // prepare necessary utilities for pip-sync.  This is a super crude
     // hack to make this work for now.  We basically sym-link pip itself
     // into a folder all by itself and place a second file in there which we
@@ -146,82 +420,19 @@ pub fn sync(cmd: SyncOptions) -> Result<(), Error> {
         symlink(get_pip_module(&self_venv), dir.path().join("pip"))
             .context("failed linking pip module into for pip-sync")?;
 
-        if let Some(workspace) = pyproject.workspace() {
-            // make sure we have an up-to-date lockfile
-            update_workspace_lockfile(
-                workspace,
-                LockMode::Production,
-                &lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write production lockfile for workspace")?;
-            update_workspace_lockfile(
-                workspace,
-                LockMode::Dev,
-                &dev_lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write dev lockfile for workspace")?;
-        } else {
-            // make sure we have an up-to-date lockfile
-            update_single_project_lockfile(
-                &pyproject,
-                LockMode::Production,
-                &lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write production lockfile for project")?;
-            update_single_project_lockfile(
-                &pyproject,
-                LockMode::Dev,
-                &dev_lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write dev lockfile for project")?;
-        }
+        update_lockfiles(&cmd, &pyproject, &lockfile, &dev_lockfile)?;
 
         // run pip install with the lockfile.
         if cmd.mode != SyncMode::LockOnly {
-            if output != CommandOutput::Quiet {
-                eprintln!("Installing dependencies");
-            }
-            let mut pip_sync_cmd = Command::new(self_venv.join("bin/pip-sync"));
-            pip_sync_cmd
-                .env("PYTHONPATH", dir.path())
-                .current_dir(pyproject.workspace_path())
-                .arg("--python-executable")
-                .arg(venv.join("bin/python"))
-                // note that the double quotes are necessary to properly handle
-                // spaces in paths
-                .arg(format!(
-                    "--pip-args=\"--python={}\"",
-                    venv.join("bin/python").display()
-                ));
-
-            if cmd.dev && dev_lockfile.is_file() {
-                pip_sync_cmd.arg(&dev_lockfile);
-            } else {
-                pip_sync_cmd.arg(&lockfile);
-            }
-
-            if output == CommandOutput::Verbose {
-                pip_sync_cmd.arg("--verbose");
-                if env::var("PIP_VERBOSE").is_err() {
-                    pip_sync_cmd.env("PIP_VERBOSE", "2");
-                }
-            } else if output != CommandOutput::Quiet {
-                pip_sync_cmd.env("PYTHONWARNINGS", "ignore");
-            } else {
-                pip_sync_cmd.arg("-q");
-            }
-            let status = pip_sync_cmd.status().context("unable to run pip-sync")?;
-            if !status.success() {
-                bail!("Installation of dependencies failed");
-            }
+            install_lockfile(
+                &cmd,
+                &venv,
+                dir.path(),
+                &pyproject,
+                &lockfile,
+                &dev_lockfile,
+                editable,
+            )?;
         }
     }
 
@@ -231,100 +442,727 @@ pub fn sync(cmd: SyncOptions) -> Result<(), Error> {
         symlink_file(get_pip_module(&self_venv), dir.path().join("pip.exe"))
             .context("failed linking pip module into for pip-sync")?;
 
-        if let Some(workspace) = pyproject.workspace() {
-            // make sure we have an up-to-date lockfile
-            update_workspace_lockfile(
-                workspace,
-                LockMode::Production,
-                &lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write production lockfile for workspace")?;
-            update_workspace_lockfile(
-                workspace,
-                LockMode::Dev,
-                &dev_lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write dev lockfile for workspace")?;
-        } else {
-            // make sure we have an up-to-date lockfile
-            update_single_project_lockfile(
+        update_lockfiles(&cmd, &pyproject, &lockfile, &dev_lockfile)?;
+
+        // run pip install with the lockfile.
+        if cmd.mode != SyncMode::LockOnly {
+            install_lockfile(
+                &cmd,
+                &venv,
+                dir.path(),
                 &pyproject,
-                LockMode::Production,
                 &lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write production lockfile for project")?;
-            update_single_project_lockfile(
-                &pyproject,
-                LockMode::Dev,
                 &dev_lockfile,
-                cmd.output,
-                &cmd.lock_options,
-            )
-            .context("could not write dev lockfile for project")?;
+                editable,
+            )?;
         }
+    }
 
-        // run pip install with the lockfile.
-        if cmd.mode != SyncMode::LockOnly {
-            if output != CommandOutput::Quiet {
-                eprintln!("Installing dependencies");
+    if !cmd.no_hooks && matches!(cmd.mode, SyncMode::Regular | SyncMode::Full) {
+        run_post_sync_hooks(&pyproject, output)?;
+    }
+
+    // the stamp is written last so that an interrupted sync is redone by the
+    // next automatic sync.  It stays unset with --no-install-project, so that
+    // the next automatic sync still installs the projects.
+    if !matches!(cmd.mode, SyncMode::PythonOnly | SyncMode::LockOnly) {
+        let mut marker = read_venv_marker(&venv)?;
+        marker.synced = if cmd.no_install_project {
+            None
+        } else {
+            Some(SyncStamp {
+                inputs: fingerprint_sync_inputs(
+                    &pyproject,
+                    &py_ver,
+                    &lockfile,
+                    &dev_lockfile,
+                    cmd.dev,
+                )?,
+                dev: cmd.dev,
+            })
+        };
+        write_venv_marker(&venv, &marker).context("failed writing venv marker file")?;
+    }
+
+    if output != CommandOutput::Quiet && cmd.mode != SyncMode::PythonOnly {
+        eprintln!("Done!");
+    }
+
+    Ok(())
+}
+
+/// Runs the post-sync hooks of a project in its virtualenv.
+///
+/// The hooks are scripts which are run with `rye run` without syncing again.
+/// The first failing hook fails the sync with its exit code.
+fn run_post_sync_hooks(pyproject: &PyProject, output: CommandOutput) -> Result<(), Error> {
+    let this = env::current_exe()?;
+    for hook in pyproject.post_sync_hooks() {
+        if output != CommandOutput::Quiet {
+            eprintln!("Running post-sync hook {}", style(&hook).cyan());
+        }
+        let mut cmd = Command::new(&this);
+        cmd.arg("run")
+            .arg("--pyproject")
+            .arg(pyproject.toml_path().as_os_str())
+            .arg("--no-sync")
+            .current_dir(pyproject.root_path().as_ref())
+            .env("RYE_NO_AUTO_SYNC", "1");
+        if output == CommandOutput::Quiet {
+            cmd.arg("--quiet");
+        }
+        cmd.arg(&hook);
+        if output == CommandOutput::Verbose {
+            eprintln!("running {}", format_command(&cmd));
+        }
+        let status = cmd
+            .status()
+            .with_context(|| format!("unable to run post-sync hook '{}'", hook))?;
+        if !status.success() {
+            let code = status.code().unwrap_or(1);
+            eprintln!(
+                "{} post-sync hook {} (exit code {})",
+                style("failed:").red(),
+                hook,
+                code
+            );
+            return Err(QuietExit(code).into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks if a sync has nothing to do.
+///
+/// This is the case if the virtualenv has the right interpreter and, unless
+/// only Python is synced, was synced from the same inputs before.  Forced
+/// syncs and options that change the lockfiles or the installation always
+/// sync.
+fn is_synced(
+    cmd: &SyncOptions,
+    pyproject: &PyProject,
+    py_ver: &PythonVersion,
+    lockfile: &Path,
+    dev_lockfile: &Path,
+    editable: bool,
+) -> Result<bool, Error> {
+    let lock_options = &cmd.lock_options;
+    if cmd.force
+        || matches!(cmd.mode, SyncMode::LockOnly | SyncMode::Full)
+        || cmd.no_install_project
+        || cmd.update_prompt
+        || cmd.strict_sources
+        || lock_options.dry_run
+        || lock_options.update_all
+        || !lock_options.update.is_empty()
+        || lock_options.pre
+        || lock_options.features.is_some()
+        || lock_options.all_features
+        || lock_options.no_hashes
+        || !lock_options.constraints.is_empty()
+        || lock_options.exclude_newer.is_some()
+        || lock_options.clear_exclude_newer
+    {
+        return Ok(false);
+    }
+    let venv = pyproject.venv_path();
+    let Ok(marker) = read_venv_marker(&venv) else {
+        return Ok(false);
+    };
+    if marker.python != *py_ver || !venv.join(VENV_BIN).join("python").is_file() {
+        return Ok(false);
+    }
+    if cmd.mode == SyncMode::PythonOnly {
+        return Ok(true);
+    }
+    let Some(synced) = marker.synced else {
+        return Ok(false);
+    };
+    Ok(marker.editable == Some(editable)
+        && synced.dev == cmd.dev
+        && synced.inputs
+            == fingerprint_sync_inputs(pyproject, py_ver, lockfile, dev_lockfile, cmd.dev)?)
+}
+
+/// Prints what a sync would change without changing anything.
+///
+/// The lockfiles are updated in a scratch folder and the packages they pin
+/// are compared with the ones installed in the virtualenv.  Returns whether
+/// the virtualenv or the lockfiles are out of date.
+fn plan_sync(
+    cmd: &SyncOptions,
+    pyproject: &PyProject,
+    self_venv: &Path,
+    py_ver: &PythonVersion,
+    lockfile: &Path,
+    dev_lockfile: &Path,
+    editable: bool,
+) -> Result<bool, Error> {
+    let output = cmd.output;
+    let venv = pyproject.venv_path();
+
+    // the marker is only kept if the virtualenv is
+    let marker = if venv.is_dir() {
+        match read_venv_marker(&venv) {
+            Ok(marker) if cmd.mode == SyncMode::Full || marker.python != *py_ver => None,
+            Ok(marker) => Some(marker),
+            Err(_) if cmd.mode == SyncMode::Full || cmd.force => None,
+            Err(err) if venv.join("rye-venv.json").is_file() => return Err(err),
+            Err(_) => bail!("virtualenv is not managed by rye. Run `rye sync -f` to force."),
+        }
+    } else {
+        None
+    };
+    let mut pending = marker.is_none();
+    if output != CommandOutput::Quiet {
+        println!(
+            "Virtualenv: {} ({})",
+            style(venv.display()).cyan(),
+            match marker {
+                Some(_) => "exists",
+                None if venv.is_dir() => "would be recreated",
+                None => "would be created",
+            }
+        );
+        println!(
+            "Python version: {}{}",
+            style(py_ver).cyan(),
+            if list_known_toolchains()?.contains(py_ver) {
+                ""
+            } else {
+                " (would be downloaded)"
+            }
+        );
+    }
+
+    // with --no-lock the lockfiles are only checked
+    let scratch = TempDir::new()?;
+    let (new_lockfile, new_dev_lockfile) = if cmd.no_lock {
+        (lockfile.to_path_buf(), dev_lockfile.to_path_buf())
+    } else {
+        let new_lockfile = scratch.path().join("requirements.lock");
+        let new_dev_lockfile = scratch.path().join("requirements-dev.lock");
+        for (old, new) in [(lockfile, &new_lockfile), (dev_lockfile, &new_dev_lockfile)] {
+            if old.is_file() {
+                fs::copy(old, new)?;
             }
-            let mut pip_sync_cmd = Command::new(self_venv.join("bin/pip-sync"));
-            pip_sync_cmd
-                .env("PYTHONPATH", dir.path())
-                .current_dir(pyproject.workspace_path())
-                .arg("--python-executable")
-                .arg(venv.join("bin/python"))
-                // note that the double quotes are necessary to properly handle
-                // spaces in paths
-                .arg(format!(
-                    "--pip-args=\"--python={}\"",
-                    venv.join("bin/python").display()
+        }
+        (new_lockfile, new_dev_lockfile)
+    };
+    update_lockfiles(
+        &SyncOptions {
+            output: match output {
+                CommandOutput::Verbose => CommandOutput::Verbose,
+                _ => CommandOutput::Quiet,
+            },
+            mode: cmd.mode,
+            lock_options: LockOptions {
+                dry_run: false,
+                ..cmd.lock_options.clone()
+            },
+            no_lock: cmd.no_lock,
+            ..Default::default()
+        },
+        pyproject,
+        &new_lockfile,
+        &new_dev_lockfile,
+    )?;
+    for (lock_mode, old, new) in [
+        (LockMode::Production, lockfile, &new_lockfile),
+        (LockMode::Dev, dev_lockfile, &new_dev_lockfile),
+    ] {
+        if output != CommandOutput::Quiet {
+            report_pin_changes(lock_mode, old, new)?;
+        }
+        pending |= fs::read(old).ok() != fs::read(new).ok();
+    }
+
+    // the same pins as in install_lockfile are installed
+    let features = match requested_features(cmd) {
+        Some(features) => Some(features),
+        None if cmd.lock_options.all_features => None,
+        None => marker.as_ref().and_then(|x| x.features.clone()),
+    };
+    let mut requirements = fs::read_to_string(if cmd.dev && new_dev_lockfile.is_file() {
+        &new_dev_lockfile
+    } else {
+        &new_lockfile
+    })?;
+    if let Some(ref features) = features {
+        requirements = select_lockfile_features(&requirements, features);
+    }
+    let selected = scratch.path().join("requirements.txt");
+    fs::write(&selected, requirements)?;
+    let planned = read_locked_pins(&selected)?;
+
+    let mut installed = match marker {
+        Some(_) => list_installed_packages(self_venv, &venv)?,
+        None => BTreeMap::new(),
+    };
+    let mut project_changes = Vec::new();
+    if !cmd.no_install_project {
+        let installed_editable = marker.as_ref().and_then(|x| x.editable);
+        for name in local_project_names(pyproject)? {
+            if installed.remove(&name).is_none() {
+                project_changes.push(format!("- {} (install project)", name));
+            } else if installed_editable != Some(editable) {
+                project_changes.push(format!(
+                    "- {} (reinstall {})",
+                    name,
+                    if editable { "editable" } else { "regular" }
                 ));
+            }
+        }
+    }
+    let mut changes = format_package_changes(&installed, &planned);
+    changes.extend(project_changes);
+    if !changes.is_empty() {
+        pending = true;
+        if output != CommandOutput::Quiet {
+            println!("Changes to virtualenv:");
+            for change in changes {
+                println!("{}", change);
+            }
+        }
+    }
 
-            if cmd.dev && dev_lockfile.is_file() {
-                pip_sync_cmd.arg(&dev_lockfile);
-            } else {
-                pip_sync_cmd.arg(&lockfile);
+    if !pending && output != CommandOutput::Quiet {
+        eprintln!("Virtualenv is up to date");
+    }
+    Ok(pending)
+}
+
+/// Returns the normalized names of the projects installed into a virtualenv.
+fn local_project_names(pyproject: &PyProject) -> Result<Vec<String>, Error> {
+    match pyproject.workspace() {
+        Some(workspace) => workspace
+            .iter_projects()
+            .filter_map(|x| x.map(|x| x.normalized_name()).transpose())
+            .collect(),
+        None => Ok(pyproject.normalized_name().into_iter().collect()),
+    }
+}
+
+/// Lists the packages installed in a virtualenv with their versions.
+fn list_installed_packages(
+    self_venv: &Path,
+    venv: &Path,
+) -> Result<BTreeMap<String, String>, Error> {
+    #[derive(Deserialize)]
+    struct Package {
+        name: String,
+        version: String,
+    }
+
+    let out = Command::new(self_venv.join(VENV_BIN).join("pip"))
+        .arg("--python")
+        .arg(venv.join(VENV_BIN).join("python"))
+        .arg("list")
+        .arg("--format=json")
+        .env("PYTHONWARNINGS", "ignore")
+        .env("PIP_DISABLE_PIP_VERSION_CHECK", "1")
+        .output()
+        .context("unable to run pip")?;
+    if !out.status.success() {
+        bail!("failed to list installed packages via pip");
+    }
+    let packages: Vec<Package> =
+        serde_json::from_slice(&out.stdout).context("malformed output of pip list")?;
+    Ok(packages
+        .into_iter()
+        .map(|x| (normalize_package_name(&x.name), x.version))
+        .collect())
+}
+
+/// Formats how the installed packages would change to match the pins.
+///
+/// Packages pinned to URLs are considered up to date once installed.
+fn format_package_changes(
+    installed: &BTreeMap<String, String>,
+    planned: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut rv = Vec::new();
+    for (name, version) in planned {
+        let Some(old_version) = installed.get(name) else {
+            rv.push(format!("- {}: {} (install)", name, version));
+            continue;
+        };
+        let change = match (Version::from_str(old_version), Version::from_str(version)) {
+            (Ok(old), Ok(new)) if new > old => "upgrade",
+            (Ok(old), Ok(new)) if new < old => "downgrade",
+            _ => continue,
+        };
+        rv.push(format!(
+            "- {}: {} → {} ({})",
+            name, old_version, version, change
+        ));
+    }
+    for (name, version) in installed {
+        if !planned.contains_key(name) {
+            rv.push(format!("- {}: {} (remove)", name, version));
+        }
+    }
+    rv
+}
+
+/// Collects the `requires-python` of the projects sharing a virtualenv.
+pub fn collect_requires_python(
+    pyproject: &PyProject,
+) -> Result<Vec<(String, VersionSpecifiers)>, Error> {
+    let mut rv = Vec::new();
+    let mut collect = |project: &PyProject| -> Result<(), Error> {
+        if let Some(requires_python) = project.requires_python()? {
+            let name = project.name().unwrap_or("<unnamed>").to_string();
+            rv.push((name, requires_python));
+        }
+        Ok(())
+    };
+    match pyproject.workspace() {
+        Some(workspace) => {
+            for project in workspace.iter_projects() {
+                collect(&project?)?;
             }
+        }
+        None => collect(pyproject)?,
+    }
+    Ok(rv)
+}
+
+/// Picks the Python version of a project that does not pin one.
+///
+/// This is the latest version that satisfies the `requires-python` of the
+/// projects.  Offline, the toolchains that are already downloaded come
+/// first.
+pub fn pick_python_version(
+    requires_python: &[(String, VersionSpecifiers)],
+) -> Result<PythonVersion, Error> {
+    if requires_python.is_empty() {
+        return Ok(PythonVersion::latest_cpython());
+    }
+    let mut known = Vec::new();
+    if is_offline() {
+        known = list_known_toolchains()?;
+        known.retain(|x| x.kind == "cpython");
+        known.sort_by(|a, b| b.cmp(a));
+    }
+    known
+        .into_iter()
+        .chain(iter_downloadable(OS, ARCH))
+        .find(|py_ver| {
+            requires_python
+                .iter()
+                .all(|(_, specifiers)| py_ver.satisfies(specifiers))
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "no available Python version satisfies requires-python {}",
+                format_requires_python(requires_python)
+            )
+        })
+}
+
+/// Makes sure the Python version of a virtualenv satisfies the
+/// `requires-python` of the projects.
+fn check_requires_python(
+    py_ver: &PythonVersion,
+    requires_python: &[(String, VersionSpecifiers)],
+    pin_file: Option<&Path>,
+) -> Result<(), Error> {
+    let unsatisfied = requires_python
+        .iter()
+        .filter(|(_, specifiers)| !py_ver.satisfies(specifiers))
+        .cloned()
+        .collect::<Vec<_>>();
+    if unsatisfied.is_empty() {
+        return Ok(());
+    }
+    if let Some(pin_file) = pin_file {
+        bail!(
+            "Python {} pinned in {} does not satisfy requires-python {}\n\
+             Pin a compatible version with `rye pin` or adjust requires-python.",
+            py_ver,
+            pin_file.display(),
+            format_requires_python(&unsatisfied)
+        );
+    }
+    bail!(
+        "Python {} does not satisfy requires-python {}\n\
+         Pin a compatible version with `rye pin` or download one with `rye fetch`.",
+        py_ver,
+        format_requires_python(&unsatisfied)
+    );
+}
+
+pub fn format_requires_python(requires_python: &[(String, VersionSpecifiers)]) -> String {
+    requires_python
+        .iter()
+        .map(|(name, specifiers)| format!("'{}' of {}", specifiers, name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-            if output == CommandOutput::Verbose {
-                pip_sync_cmd.arg("--verbose");
-                if env::var("PIP_VERBOSE").is_err() {
-                    pip_sync_cmd.env("PIP_VERBOSE", "2");
+/// Installs the dependencies of a lockfile into the virtualenv.
+///
+/// Lockfiles with hashes are installed with `--require-hashes`.
+fn install_lockfile(
+    cmd: &SyncOptions,
+    venv: &Path,
+    pip_dir: &Path,
+    pyproject: &PyProject,
+    lockfile: &Path,
+    dev_lockfile: &Path,
+    editable: bool,
+) -> Result<(), Error> {
+    let output = cmd.output;
+    if output != CommandOutput::Quiet {
+        eprintln!("Installing dependencies");
+    }
+
+    // syncing uninstalls everything that is not in the lockfile, which
+    // also removes the dev dependencies when switching to --no-dev.
+    let mut lockfile = if cmd.dev && dev_lockfile.is_file() {
+        dev_lockfile.to_path_buf()
+    } else {
+        lockfile.to_path_buf()
+    };
+    ensure_find_links(&lockfile, &pyproject.workspace_path())?;
+    ensure_sources(&lockfile, &pyproject.sources()?, cmd.strict_sources, output)?;
+    // the local projects are the editable requirements.  They are left out
+    // with --no-install-project and pip refuses them with hashes, so they
+    // are installed separately then.  The same goes for regular installs
+    // and for switching between the two, as the sync uninstalls them first.
+    let require_hashes = has_hashes(&lockfile);
+    let marker = read_venv_marker(venv)?;
+    let installed_editable = marker.editable;
+    // the selected features are kept for later syncs
+    let features = match requested_features(cmd) {
+        Some(features) => Some(features),
+        None if cmd.lock_options.all_features => None,
+        None => marker.features,
+    };
+    let mut projects = Vec::new();
+    let mut requirements = fs::read_to_string(&lockfile)?;
+    let mut rewrite = false;
+    if require_hashes || cmd.no_install_project || !editable || installed_editable != Some(true) {
+        let mut rest = String::new();
+        for line in requirements.lines() {
+            match line.strip_prefix("-e ") {
+                Some(url) => {
+                    projects.push(local_project_path(url.trim(), &pyproject.workspace_path())?)
+                }
+                None => {
+                    rest.push_str(line);
+                    rest.push('\n');
                 }
-            } else if output != CommandOutput::Quiet {
-                pip_sync_cmd.env("PYTHONWARNINGS", "ignore");
-            } else {
-                pip_sync_cmd.arg("-q");
             }
-            let status = pip_sync_cmd.status().context("unable to run pip-sync")?;
-            if !status.success() {
-                bail!("Installation of dependencies failed");
+        }
+        requirements = rest;
+        rewrite = true;
+    }
+    // only the pins of the requested features are installed, which does not
+    // require resolving again.
+    if let Some(ref features) = features {
+        requirements = select_lockfile_features(&requirements, features);
+        rewrite = true;
+    }
+    if rewrite {
+        lockfile = pip_dir.join("requirements.txt");
+        fs::write(&lockfile, requirements)?;
+    }
+
+    let backend = Backend::get(cmd.lock_options.backend, output)?;
+    let mut sync_cmd = backend.sync_command(venv, pip_dir, &lockfile, require_hashes, output)?;
+    sync_cmd.current_dir(pyproject.workspace_path());
+    if output == CommandOutput::Verbose {
+        eprintln!("running {}", format_command(&sync_cmd));
+    }
+    let auth = IndexAuth::new(&get_index_urls(&pyproject.sources()?), output)?;
+    auth.apply(&mut sync_cmd);
+    let status = sync_cmd
+        .status()
+        .with_context(|| format!("unable to run {}", backend.installer_name()))?;
+    if !status.success() {
+        if is_offline() {
+            bail!(
+                "Installation of dependencies failed offline, packages must be in the wheelhouse"
+            );
+        }
+        bail!("Installation of dependencies failed");
+    }
+
+    if !projects.is_empty() && !cmd.no_install_project {
+        let mut install_cmd =
+            backend.install_projects_command(venv, &projects, editable, output)?;
+        install_cmd.current_dir(pyproject.workspace_path());
+        if output == CommandOutput::Verbose {
+            eprintln!("running {}", format_command(&install_cmd));
+        }
+        auth.apply(&mut install_cmd);
+        let status = install_cmd
+            .status()
+            .context("unable to install local projects")?;
+        if !status.success() {
+            bail!("Installation of local projects failed");
+        }
+    }
+
+    let mut marker = read_venv_marker(venv)?;
+    marker.editable = (!cmd.no_install_project).then_some(editable);
+    marker.features = features;
+    write_venv_marker(venv, &marker).context("failed writing venv marker file")?;
+
+    Ok(())
+}
+
+/// Resolves the URL of a local project in a lockfile to its path.
+fn local_project_path(url: &str, workspace_path: &Path) -> Result<PathBuf, Error> {
+    Url::from_directory_path(workspace_path)
+        .ok()
+        .and_then(|base| base.join(url).ok())
+        .and_then(|url| url.to_file_path().ok())
+        .ok_or_else(|| anyhow!("invalid local project {} in lockfile", url))
+}
+
+/// Returns the features to install unless all features of the lockfile
+/// are installed.
+fn requested_features(cmd: &SyncOptions) -> Option<Vec<String>> {
+    if cmd.lock_options.all_features {
+        return None;
+    }
+    cmd.lock_options.features.as_ref().map(|features| {
+        features
+            .iter()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect()
+    })
+}
+
+/// Makes sure the lockfiles of a project are up-to-date.
+fn update_lockfiles(
+    cmd: &SyncOptions,
+    pyproject: &PyProject,
+    lockfile: &Path,
+    dev_lockfile: &Path,
+) -> Result<(), Error> {
+    let locked_features = read_lockfile_features(lockfile);
+    if cmd.no_lock {
+        if !lockfile.is_file() {
+            bail!(
+                "lockfile {} does not exist, run `rye lock` to create it",
+                lockfile.display()
+            );
+        }
+        for feature in requested_features(cmd).unwrap_or_default() {
+            if !locked_features.contains(&feature) {
+                bail!(
+                    "feature '{}' is not in lockfile {}, run `rye lock --features` to add it",
+                    feature,
+                    lockfile.display()
+                );
             }
         }
+        return Ok(());
     }
+    ensure_updatable(&cmd.lock_options, &[lockfile, dev_lockfile])?;
 
-    if output != CommandOutput::Quiet && cmd.mode != SyncMode::PythonOnly {
-        eprintln!("Done!");
+    // when syncing, lockfiles are only regenerated if their inputs changed.
+    // As only the requested features are installed from the lockfiles, they
+    // keep the features they were generated with.
+    let lock_options = LockOptions {
+        keep_unchanged: cmd.mode != SyncMode::LockOnly,
+        features: requested_features(cmd).map(|mut features| {
+            if cmd.mode != SyncMode::LockOnly {
+                features.extend(locked_features);
+            }
+            features
+        }),
+        ..cmd.lock_options.clone()
+    };
+    if let Some(workspace) = pyproject.workspace() {
+        // make sure we have an up-to-date lockfile
+        update_workspace_lockfile(
+            workspace,
+            LockMode::Production,
+            lockfile,
+            cmd.output,
+            &lock_options,
+        )
+        .context("could not write production lockfile for workspace")?;
+        update_workspace_lockfile(
+            workspace,
+            LockMode::Dev,
+            dev_lockfile,
+            cmd.output,
+            &lock_options,
+        )
+        .context("could not write dev lockfile for workspace")?;
+    } else {
+        // make sure we have an up-to-date lockfile
+        update_single_project_lockfile(
+            pyproject,
+            LockMode::Production,
+            lockfile,
+            cmd.output,
+            &lock_options,
+        )
+        .context("could not write production lockfile for project")?;
+        update_single_project_lockfile(
+            pyproject,
+            LockMode::Dev,
+            dev_lockfile,
+            cmd.output,
+            &lock_options,
+        )
+        .context("could not write dev lockfile for project")?;
     }
 
     Ok(())
 }
 
+/// Returns the prompt for the virtualenv of a project.
+///
+/// This is the name of the project the virtualenv belongs to, which is the
+/// root project of a workspace.
+fn get_venv_prompt(pyproject: &PyProject) -> String {
+    let root = pyproject.workspace_path();
+    let name = if pyproject.root_path() == root {
+        pyproject.name().map(|x| x.to_string())
+    } else {
+        PyProject::load(&root.join("pyproject.toml"))
+            .ok()
+            .and_then(|x| x.name().map(|x| x.to_string()))
+    };
+    name.or_else(|| root.file_name().map(|x| x.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| ".venv".into())
+}
+
+/// Sets the prompt in the contents of a `pyvenv.cfg` file.
+fn set_pyvenv_cfg_prompt(contents: &str, prompt: &str) -> String {
+    let mut rv = String::new();
+    for line in contents.lines() {
+        if line.split_once('=').map(|x| x.0.trim()) != Some("prompt") {
+            rv.push_str(line);
+            rv.push('\n');
+        }
+    }
+    rv.push_str(&format!("prompt = {}\n", prompt));
+    rv
+}
+
+/// Creates a virtualenv whose shell prompt shows the given name.
+///
+/// Running this on an existing virtualenv updates it in place.
 pub fn create_virtualenv(
     output: CommandOutput,
     self_venv: &Path,
     py_ver: &PythonVersion,
     venv: &Path,
+    prompt: &str,
 ) -> Result<(), Error> {
     let py_bin = get_py_bin(py_ver)?;
-    let mut venv_cmd = Command::new(self_venv.join("bin/virtualenv"));
+    let mut venv_cmd = Command::new(self_venv.join(VENV_BIN).join("virtualenv"));
     if output == CommandOutput::Verbose {
         venv_cmd.arg("--verbose");
     } else {
@@ -334,13 +1172,93 @@ pub fn create_virtualenv(
     venv_cmd.arg("-p");
     venv_cmd.arg(&py_bin);
     venv_cmd.arg("--no-seed");
+    venv_cmd.arg("--prompt");
+    venv_cmd.arg(prompt);
     venv_cmd.arg("--");
     venv_cmd.arg(venv);
+    if output == CommandOutput::Verbose {
+        eprintln!("running {}", format_command(&venv_cmd));
+    }
     let status = venv_cmd
         .status()
         .context("unable to invoke virtualenv command")?;
     if !status.success() {
         bail!("failed to initialize virtualenv");
     }
+
+    // tools other than the activation scripts read the prompt from here
+    let cfg = venv.join("pyvenv.cfg");
+    let contents = fs::read_to_string(&cfg).context("failed to read pyvenv.cfg")?;
+    fs::write(&cfg, set_pyvenv_cfg_prompt(&contents, prompt))
+        .context("failed to write pyvenv.cfg")?;
     Ok(())
 }
+
+#[test]
+fn test_local_project_path() {
+    let workspace = Path::new("/work space");
+    assert_eq!(
+        local_project_path("file:.", workspace).unwrap(),
+        Path::new("/work space/")
+    );
+    assert_eq!(
+        local_project_path("file:libs/my%20lib", workspace).unwrap(),
+        Path::new("/work space/libs/my lib")
+    );
+    assert_eq!(
+        local_project_path("file:///elsewhere", workspace).unwrap(),
+        Path::new("/elsewhere")
+    );
+}
+
+#[test]
+fn test_pick_python_version() {
+    let requires_python = [("demo".to_string(), "<3.10".parse().unwrap())];
+    let py_ver = pick_python_version(&requires_python).unwrap();
+    assert_eq!((py_ver.major, py_ver.minor), (3, 9));
+    let requires_python = [("demo".to_string(), ">=4".parse().unwrap())];
+    assert!(pick_python_version(&requires_python).is_err());
+}
+
+#[test]
+fn test_set_pyvenv_cfg_prompt() {
+    assert_eq!(
+        set_pyvenv_cfg_prompt(
+            "home = /usr/bin\nprompt = .venv\nversion_info = 3.11.4\n",
+            "demo"
+        ),
+        "home = /usr/bin\nversion_info = 3.11.4\nprompt = demo\n"
+    );
+}
+
+#[test]
+fn test_format_package_changes() {
+    let pins = |pins: &[(&str, &str)]| -> BTreeMap<String, String> {
+        pins.iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect()
+    };
+    let installed = pins(&[
+        ("black", "24.1.0"),
+        ("click", "8.1.7"),
+        ("idna", "3.6"),
+        ("pkg", "1.0"),
+        ("six", "1.16.0"),
+    ]);
+    let planned = pins(&[
+        ("black", "23.12.1"),
+        ("click", "8.1.7"),
+        ("idna", "3.7"),
+        ("pkg", "@ https://example.com/pkg.tar.gz"),
+        ("requests", "2.31.0"),
+    ]);
+    assert_eq!(
+        format_package_changes(&installed, &planned),
+        [
+            "- black: 24.1.0 → 23.12.1 (downgrade)",
+            "- idna: 3.6 → 3.7 (upgrade)",
+            "- requests: 2.31.0 (install)",
+            "- six: 1.16.0 (remove)",
+        ]
+    );
+}
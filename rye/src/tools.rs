@@ -0,0 +1,558 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::{env, fs};
+
+use anyhow::{Context, Error};
+use pep508_rs::{Requirement, VersionOrUrl};
+use same_file::is_same_file;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::config::{get_app_dir, get_py_bin};
+use crate::installer::IncludeDeps;
+use crate::pyproject::normalize_package_name;
+use crate::sources::PythonVersion;
+use crate::utils::VENV_BIN;
+
+const FIND_VERSION_SCRIPT: &str = r#"
+import re
+import sys
+from importlib.metadata import distributions
+
+def normalize(name):
+    return re.sub(r"[-_.]+", "-", name).lower()
+
+for dist in distributions():
+    if normalize(dist.metadata["Name"]) == sys.argv[1]:
+        print(dist.version)
+        break
+"#;
+
+/// The name of the receipt file that is placed in tool virtualenvs.
+pub const TOOL_RECEIPT_FILE: &str = "rye-tool.json";
+
+/// Marker file placed in tool virtualenvs while they are being installed.
+pub const TOOL_INCOMPLETE_MARKER: &str = "rye-tool.incomplete";
+
+/// Information about a tool installation persisted next to the virtualenv.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ToolReceipt {
+    /// The requirement the tool was installed from (including extras).
+    pub requirement: String,
+    /// Was the tool installed in editable mode from a local path?
+    #[serde(default)]
+    pub editable: bool,
+    /// Extra arguments that were passed to pip.
+    #[serde(default)]
+    pub pip_args: Vec<String>,
+    /// Which dependencies contributed scripts.
+    #[serde(default)]
+    pub include_deps: IncludeDeps,
+    /// If not empty, only these scripts are linked.
+    #[serde(default)]
+    pub only_scripts: Vec<String>,
+    /// Scripts that are linked under another name.
+    #[serde(default)]
+    pub script_aliases: BTreeMap<String, String>,
+    /// The index the tool was installed from.
+    #[serde(default)]
+    pub index_url: Option<String>,
+    /// Additional indexes the tool was installed from.
+    #[serde(default)]
+    pub extra_index_urls: Vec<String>,
+    /// Local folders of wheels the tool was installed from.
+    #[serde(default)]
+    pub find_links: Vec<PathBuf>,
+    /// Was the tool installed without access to a package index?
+    #[serde(default)]
+    pub offline: bool,
+    /// Does the tool track pre-releases?
+    #[serde(default)]
+    pub pre: bool,
+    /// Was the tool installed without any caches?
+    #[serde(default)]
+    pub no_cache: bool,
+    /// The constraints file pip honors for the tool.
+    #[serde(default)]
+    pub constraints: Option<PathBuf>,
+    /// The hash-locked requirements file the tool was installed from.  If
+    /// set, pip runs in hash-checking mode.
+    #[serde(default)]
+    pub hashed_requirements: Option<PathBuf>,
+    /// Shims of other tools that were taken over.
+    #[serde(default)]
+    pub replaced_shims: Vec<ReplacedShim>,
+    /// The python version the virtualenv was created with.
+    #[serde(default)]
+    pub python: Option<String>,
+    /// The file names of the shims that were created.
+    #[serde(default)]
+    pub shims: Vec<String>,
+    /// When the tool was installed (seconds since the unix epoch).
+    #[serde(default)]
+    pub installed_at: Option<u64>,
+}
+
+/// A shim of another tool that was taken over during installation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplacedShim {
+    /// The file name of the shim.
+    pub shim: String,
+    /// The tool that owned the shim before.
+    pub tool: String,
+    /// The script the shim pointed to before.
+    pub target: PathBuf,
+}
+
+impl ToolReceipt {
+    /// Loads the receipt from a tool virtualenv.
+    ///
+    /// Tools installed by older versions of rye do not have a receipt in
+    /// which case `None` is returned.
+    pub fn load(venv: &Path) -> Result<Option<ToolReceipt>, Error> {
+        let path = venv.join(TOOL_RECEIPT_FILE);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("could not read tool receipt"),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .with_context(|| format!("malformed tool receipt {}", path.display()))
+    }
+
+    /// Writes the receipt into a tool virtualenv.
+    pub fn save(&self, venv: &Path) -> Result<(), Error> {
+        fs::write(
+            venv.join(TOOL_RECEIPT_FILE),
+            serde_json::to_string_pretty(self)?,
+        )
+        .context("failed writing tool receipt")?;
+        Ok(())
+    }
+
+    /// Returns the local path of an editable installation.
+    pub fn editable_path(&self) -> Option<PathBuf> {
+        if !self.editable {
+            return None;
+        }
+        match self
+            .requirement
+            .parse::<Requirement>()
+            .ok()?
+            .version_or_url?
+        {
+            VersionOrUrl::Url(url) => url.to_file_path().ok(),
+            VersionOrUrl::VersionSpecifier(_) => None,
+        }
+    }
+
+    /// Returns the extras of the installed requirement.
+    pub fn extras(&self) -> Vec<String> {
+        self.requirement
+            .parse::<Requirement>()
+            .ok()
+            .and_then(|x| x.extras)
+            .unwrap_or_default()
+    }
+}
+
+/// Prefix of the line in a Windows trampoline that records the target.
+#[cfg(target_os = "windows")]
+const TRAMPOLINE_MARKER: &str = "rem rye-shim: ";
+
+/// Returns the path of the shim for a script in a tool virtualenv.
+///
+/// On Windows shims are `.cmd` trampolines, so the extension of the
+/// script is replaced.
+pub fn get_shim_path(shim_dir: &Path, script: &Path) -> PathBuf {
+    #[cfg(target_os = "windows")]
+    {
+        shim_dir.join(script.with_extension("cmd"))
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        shim_dir.join(script)
+    }
+}
+
+/// Checks if a script in a virtualenv has the given name.
+///
+/// The extension is optional so that `black` also matches `black.exe`.
+pub fn script_matches(script: &Path, name: &str) -> bool {
+    script.file_name().is_some_and(|x| x == name) || script.file_stem().is_some_and(|x| x == name)
+}
+
+/// Creates a shim that points to a script.
+///
+/// On unix this is a symlink.  On Windows creating symlinks requires special
+/// privileges, so a small trampoline is written instead which invokes the
+/// script, forwards all arguments and the exit code.
+pub fn create_shim(script: &Path, shim: &Path) -> Result<(), Error> {
+    #[cfg(not(target_os = "windows"))]
+    {
+        std::os::unix::fs::symlink(script, shim)
+            .with_context(|| format!("unable to symlink tool to {}", script.display()))?;
+    }
+    #[cfg(target_os = "windows")]
+    {
+        fs::write(
+            shim,
+            format!(
+                "@echo off\r\n{}{}\r\n\"{}\" %*\r\nexit /b %ERRORLEVEL%\r\n",
+                TRAMPOLINE_MARKER,
+                script.display(),
+                script.display()
+            ),
+        )
+        .with_context(|| format!("unable to create trampoline for {}", script.display()))?;
+    }
+    Ok(())
+}
+
+/// Reads the script a shim points to.
+///
+/// Returns `None` if the path is not a shim created by [`create_shim`].
+pub fn read_shim_target(shim: &Path) -> Option<PathBuf> {
+    #[cfg(not(target_os = "windows"))]
+    {
+        if !shim.is_symlink() {
+            return None;
+        }
+        fs::read_link(shim).ok()
+    }
+    #[cfg(target_os = "windows")]
+    {
+        if shim.extension() != Some(std::ffi::OsStr::new("cmd")) {
+            return None;
+        }
+        let contents = fs::read_to_string(shim).ok()?;
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(TRAMPOLINE_MARKER))
+            .map(PathBuf::from)
+    }
+}
+
+/// Returns the folder where tool virtualenvs are placed.
+pub fn get_tools_dir() -> Result<PathBuf, Error> {
+    Ok(get_app_dir()?.join("tools"))
+}
+
+/// Returns the folder where shims are placed.
+pub fn get_shims_dir() -> Result<PathBuf, Error> {
+    Ok(get_app_dir()?.join("shims"))
+}
+
+/// Reference to an installed global tool.
+#[derive(Debug, Clone)]
+pub struct InstalledTool {
+    name: String,
+    venv: PathBuf,
+}
+
+impl InstalledTool {
+    /// Returns the normalized name of the tool.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the path to the virtualenv of the tool.
+    pub fn venv_path(&self) -> &Path {
+        &self.venv
+    }
+
+    /// Returns the bin folder of the virtualenv.
+    pub fn venv_bin_path(&self) -> PathBuf {
+        self.venv.join(VENV_BIN)
+    }
+
+    /// Returns the path to the interpreter of the virtualenv.
+    pub fn python_path(&self) -> PathBuf {
+        self.venv_bin_path().join("python")
+    }
+
+    /// Returns the receipt of the installation if there is one.
+    pub fn receipt(&self) -> Option<ToolReceipt> {
+        ToolReceipt::load(&self.venv).ok().flatten()
+    }
+
+    /// Checks if the virtualenv can no longer be used.
+    ///
+    /// This is typically the case if the toolchain it was created from
+    /// was removed.
+    pub fn is_broken(&self) -> bool {
+        !self.python_path().is_file()
+    }
+
+    /// Checks if the installation of the tool never finished.
+    pub fn is_incomplete(&self) -> bool {
+        self.venv.join(TOOL_INCOMPLETE_MARKER).is_file()
+    }
+
+    /// Checks if the virtualenv is left over from an aborted installation.
+    ///
+    /// Besides incomplete installations these are virtualenvs without a
+    /// receipt that no shim points to.
+    pub fn is_leftover(&self, shim_dir: &Path) -> Result<bool, Error> {
+        Ok(self.is_incomplete()
+            || (self.receipt().is_none() && self.list_shims(shim_dir)?.is_empty()))
+    }
+
+    /// Returns the disk space taken up by the virtualenv in bytes.
+    pub fn disk_usage(&self) -> u64 {
+        WalkDir::new(&self.venv)
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_type().is_file())
+            .filter_map(|x| x.metadata().ok())
+            .map(|x| x.len())
+            .sum()
+    }
+
+    /// Returns the python version recorded for the tool.
+    pub fn python_version(&self) -> Option<PythonVersion> {
+        self.receipt()?.python?.parse().ok()
+    }
+
+    /// Checks if the virtualenv needs to be rebuilt.
+    ///
+    /// This is the case if it is broken or the toolchain it was created
+    /// from no longer exists.
+    pub fn needs_rebuild(&self) -> bool {
+        if self.is_broken() || self.is_incomplete() {
+            return true;
+        }
+        match self.python_version() {
+            Some(version) => !get_py_bin(&version).is_ok_and(|x| x.is_file()),
+            None => false,
+        }
+    }
+
+    /// Queries the installed version of the tool from the virtualenv.
+    pub fn version(&self) -> Option<String> {
+        if self.is_broken() {
+            return None;
+        }
+        let out = Command::new(self.python_path())
+            .arg("-c")
+            .arg(FIND_VERSION_SCRIPT)
+            .arg(normalize_package_name(&self.name))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        let version = std::str::from_utf8(&out.stdout).ok()?.trim();
+        if version.is_empty() {
+            None
+        } else {
+            Some(version.to_string())
+        }
+    }
+
+    /// Returns the shims that point into this tool's virtualenv.
+    pub fn list_shims(&self, shim_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut rv = Vec::new();
+        if let Ok(iter) = fs::read_dir(shim_dir) {
+            for entry in iter {
+                let path = entry?.path();
+                if let Some(target) = read_shim_target(&path) {
+                    if target.strip_prefix(&self.venv).is_ok() {
+                        rv.push(path);
+                    }
+                }
+            }
+        }
+        rv.sort();
+        Ok(rv)
+    }
+}
+
+/// Returns a list of all installed tools sorted by name.
+pub fn list_installed_tools() -> Result<Vec<InstalledTool>, Error> {
+    let tool_dir = get_tools_dir()?;
+    let mut rv = Vec::new();
+    if let Ok(iter) = fs::read_dir(&tool_dir) {
+        for entry in iter {
+            let entry = entry.context("could not enumerate tools")?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            rv.push(InstalledTool {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                venv: entry.path(),
+            });
+        }
+    }
+    rv.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(rv)
+}
+
+/// Looks up a single installed tool by package name.
+///
+/// Both the name and the folders of the installed tools are compared after
+/// PEP 503 normalization, so `Flask_SQLAlchemy` finds `flask-sqlalchemy`
+/// and tools installed under a non-normalized folder name are found too.
+pub fn get_installed_tool(name: &str) -> Result<Option<InstalledTool>, Error> {
+    let name = normalize_package_name(name);
+    let venv = get_tools_dir()?.join(&name);
+    if venv.is_dir() {
+        return Ok(Some(InstalledTool { name, venv }));
+    }
+    Ok(list_installed_tools()?
+        .into_iter()
+        .find(|tool| normalize_package_name(&tool.name) == name))
+}
+
+/// Finds the script of an installed tool that a shim of that name would run.
+///
+/// Shims are consulted first so that aliases and scripts of tools with
+/// another name resolve, even if the shims folder is not on the path.  After
+/// that the scripts of a tool with that name are searched.
+pub fn find_tool_script(name: &str) -> Result<Option<PathBuf>, Error> {
+    let shim = get_shim_path(&get_shims_dir()?, Path::new(name));
+    if let Some(target) = read_shim_target(&shim) {
+        if target.strip_prefix(get_tools_dir()?).is_ok() && target.is_file() {
+            return Ok(Some(target));
+        }
+    }
+    let tool = match get_installed_tool(name)? {
+        Some(tool) => tool,
+        None => return Ok(None),
+    };
+    let mut rv = None;
+    if let Ok(iter) = fs::read_dir(tool.venv_bin_path()) {
+        for entry in iter {
+            let path = entry?.path();
+            if script_matches(&path, name) && path.is_file() {
+                rv = Some(path);
+                break;
+            }
+        }
+    }
+    Ok(rv)
+}
+
+/// Returns a hint with the installed tool closest to a name that was not found.
+///
+/// If no tool comes close, an empty string is returned.
+pub fn did_you_mean(name: &str) -> Result<String, Error> {
+    let name = normalize_package_name(name);
+    let best = list_installed_tools()?
+        .into_iter()
+        .map(|tool| {
+            let score = strsim::jaro_winkler(&name, &normalize_package_name(&tool.name));
+            (score, tool.name)
+        })
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(match best {
+        Some((_, other)) => format!(" (did you mean {}?)", other),
+        None => String::new(),
+    })
+}
+
+/// Returns the shims that point into the tools folder but no longer resolve.
+///
+/// Shims that point elsewhere (like the python shims) are never considered
+/// dangling.
+pub fn find_dangling_shims() -> Result<Vec<PathBuf>, Error> {
+    let tool_dir = get_tools_dir()?;
+    let mut rv = Vec::new();
+    if let Ok(iter) = fs::read_dir(get_shims_dir()?) {
+        for entry in iter {
+            let path = entry?.path();
+            if let Some(target) = read_shim_target(&path) {
+                if target.strip_prefix(&tool_dir).is_ok() && !target.exists() {
+                    rv.push(path);
+                }
+            }
+        }
+    }
+    rv.sort();
+    Ok(rv)
+}
+
+/// A problem with a shim found by [`check_shims`].
+#[derive(Debug)]
+pub enum ShimProblem {
+    /// The shim points into the tools folder, but the script is gone.
+    MissingTarget { shim: PathBuf, target: PathBuf },
+    /// A shim listed in the receipt of a tool does not exist.
+    ///
+    /// The script is known if it still exists in the tool virtualenv.
+    MissingShim {
+        shim: PathBuf,
+        tool: String,
+        script: Option<PathBuf>,
+    },
+    /// The shim points outside of the tools folder.
+    ForeignShim { shim: PathBuf, target: PathBuf },
+}
+
+/// Verifies that all shims resolve to tools and all tools have their shims.
+pub fn check_shims() -> Result<Vec<ShimProblem>, Error> {
+    let tool_dir = get_tools_dir()?;
+    let shim_dir = get_shims_dir()?;
+    let this = env::current_exe().ok();
+    let mut rv = Vec::new();
+
+    if let Ok(iter) = fs::read_dir(&shim_dir) {
+        for entry in iter {
+            let shim = entry?.path();
+            let target = match read_shim_target(&shim) {
+                Some(target) => target,
+                None => continue,
+            };
+            if target.strip_prefix(&tool_dir).is_ok() {
+                if !target.is_file() {
+                    rv.push(ShimProblem::MissingTarget { shim, target });
+                }
+            } else if !this
+                .as_ref()
+                .is_some_and(|this| is_same_file(this, &target).unwrap_or(false))
+            {
+                // the python shims can point to rye itself
+                rv.push(ShimProblem::ForeignShim { shim, target });
+            }
+        }
+    }
+
+    for tool in list_installed_tools()? {
+        let receipt = match tool.receipt() {
+            Some(receipt) => receipt,
+            None => continue,
+        };
+        for name in &receipt.shims {
+            let shim = shim_dir.join(name);
+            if shim.is_symlink() || shim.exists() {
+                continue;
+            }
+            // aliased shims are named differently than their scripts
+            let shim_name = Path::new(name);
+            let script_name = receipt
+                .script_aliases
+                .iter()
+                .find(|(_, alias)| script_matches(shim_name, alias))
+                .map_or_else(
+                    || shim_name.file_stem().unwrap_or_default().to_os_string(),
+                    |(original, _)| original.into(),
+                );
+            let script = fs::read_dir(tool.venv_bin_path()).ok().and_then(|iter| {
+                iter.filter_map(|x| x.ok().map(|x| x.path()))
+                    .find(|x| script_matches(x, &script_name.to_string_lossy()))
+            });
+            rv.push(ShimProblem::MissingShim {
+                shim,
+                tool: tool.name().to_string(),
+                script,
+            });
+        }
+    }
+
+    Ok(rv)
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use url::Url;
+
+#[cfg(not(windows))]
+const VENV_BIN: &str = "bin";
+#[cfg(windows)]
+const VENV_BIN: &str = "Scripts";
+
+fn run(cmd: &mut Command) -> String {
+    let out = cmd.output().unwrap();
+    assert!(
+        out.status.success(),
+        "failed to run {:?}\n{}",
+        cmd,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// Builds a wheel of a package with a `main` function into a folder.
+fn build_wheel(dir: &Path, wheels: &Path, name: &str, requires: &str, entry_points: &str) {
+    let package = name.replace('-', "_");
+    let src = dir.join("src").join(&package);
+    let dist_info_name = format!("{}-1.0.dist-info", package);
+    let dist_info = src.join(&dist_info_name);
+    fs::create_dir_all(src.join(&package)).unwrap();
+    fs::create_dir_all(&dist_info).unwrap();
+    fs::write(
+        src.join(&package).join("__init__.py"),
+        "def main():\n    print('hello')\n",
+    )
+    .unwrap();
+    fs::write(
+        dist_info.join("METADATA"),
+        format!(
+            "Metadata-Version: 2.1\nName: {}\nVersion: 1.0\n{}",
+            name, requires
+        ),
+    )
+    .unwrap();
+    fs::write(
+        dist_info.join("WHEEL"),
+        "Wheel-Version: 1.0\nGenerator: rye\nRoot-Is-Purelib: true\nTag: py3-none-any\n",
+    )
+    .unwrap();
+    fs::write(dist_info.join("entry_points.txt"), entry_points).unwrap();
+    fs::write(
+        dist_info.join("RECORD"),
+        format!(
+            "{0}/__init__.py,,\n{1}/METADATA,,\n{1}/WHEEL,,\n\
+             {1}/entry_points.txt,,\n{1}/RECORD,,\n",
+            package, dist_info_name
+        ),
+    )
+    .unwrap();
+    run(Command::new("python3")
+        .args(["-m", "zipfile", "-c"])
+        .arg(wheels.join(format!("{}-1.0-py3-none-any.whl", package)))
+        .arg(src.join(&package))
+        .arg(&dist_info));
+}
+
+fn list_dir(path: &Path) -> Vec<String> {
+    let mut rv = fs::read_dir(path)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|x| x != "__pycache__")
+        .collect::<Vec<_>>();
+    rv.sort();
+    rv
+}
+
+#[test]
+#[ignore = "needs uv and network"]
+fn test_backends_lock_and_sync_alike() {
+    run(Command::new("uv").arg("--version"));
+
+    // rye bootstraps into its own home, the packages come from a local
+    // package source with a library, its dependency and a script.
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    let wheels = dir.path().join("wheels");
+    fs::create_dir_all(&home).unwrap();
+    fs::create_dir_all(&wheels).unwrap();
+    build_wheel(dir.path(), &wheels, "demo-dep", "", "");
+    build_wheel(
+        dir.path(),
+        &wheels,
+        "demo-lib",
+        "Requires-Dist: demo-dep\n",
+        "[console_scripts]\ndemo-lib = demo_lib:main\n",
+    );
+
+    let mut results = Vec::new();
+    for backend in ["pip", "uv"] {
+        let root = dir.path().join(backend).join("demo");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join("pyproject.toml"),
+            format!(
+                "[project]\nname = \"demo\"\nversion = \"1.0\"\ndependencies = []\n\n\
+                 [tool.rye]\nmanaged = true\n\n\
+                 [[tool.rye.sources]]\nname = \"default\"\ntype = \"find-links\"\nurl = \"{}\"\n",
+                Url::from_directory_path(&wheels).unwrap()
+            ),
+        )
+        .unwrap();
+        let rye = |args: &[&str]| {
+            run(Command::new(env!("CARGO_BIN_EXE_rye"))
+                .args(args)
+                .current_dir(&root)
+                .env("HOME", &home)
+                .env("RYE_NO_AUTO_SYNC", "1"))
+        };
+        rye(&["add", "demo-lib"]);
+        rye(&["lock", "--backend", backend]);
+        rye(&[
+            "sync",
+            "--backend",
+            backend,
+            "--no-lock",
+            "--no-install-project",
+        ]);
+
+        let venv = root.join(".venv");
+        let site_packages = run(Command::new(venv.join(VENV_BIN).join("python"))
+            .arg("-c")
+            .arg("import sysconfig; print(sysconfig.get_paths()['purelib'])"));
+        results.push((
+            fs::read_to_string(root.join("requirements.lock")).unwrap(),
+            fs::read_to_string(root.join("requirements-dev.lock")).unwrap(),
+            list_dir(&PathBuf::from(site_packages.trim())),
+            list_dir(&venv.join(VENV_BIN)),
+        ));
+    }
+
+    let (pip, uv) = (&results[0], &results[1]);
+    assert!(pip.0.contains("demo-dep==1.0"));
+    assert!(pip.2.contains(&"demo_lib-1.0.dist-info".to_string()));
+    assert!(pip.3.contains(&"demo-lib".to_string()));
+    assert_eq!(pip, uv);
+}
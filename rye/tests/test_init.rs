@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[cfg(not(windows))]
+const VENV_BIN: &str = "bin";
+#[cfg(windows)]
+const VENV_BIN: &str = "Scripts";
+
+/// The build backends and the modules they need to build without isolation.
+const BUILD_SYSTEMS: &[(&str, &str)] = &[
+    ("hatchling", "hatchling"),
+    ("setuptools", "setuptools, wheel"),
+    ("flit", "flit_core"),
+];
+
+fn run(cmd: &mut Command) -> String {
+    let out = cmd.output().unwrap();
+    assert!(
+        out.status.success(),
+        "failed to run {:?}\n{}",
+        cmd,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// Runs rye with its own home and a known git identity for the author.
+fn rye(home: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_rye"));
+    cmd.env("HOME", home)
+        .env("GIT_CONFIG_GLOBAL", home.join(".gitconfig"))
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("RYE_NO_AUTO_SYNC", "1");
+    cmd
+}
+
+#[test]
+#[ignore = "needs python3 with pip, hatchling, setuptools, wheel and flit_core"]
+fn test_init_projects_build_and_run() {
+    let python = "python3";
+    for (_, modules) in BUILD_SYSTEMS {
+        run(Command::new(python)
+            .arg("-c")
+            .arg(format!("import {}, pip", modules)));
+    }
+    let version = run(Command::new(python)
+        .arg("-c")
+        .arg("import sys; print('%d.%d.%d' % sys.version_info[:3])"));
+    let version = version.trim();
+    let site_packages = run(Command::new(python).arg("-c").arg(
+        "import os, sys; print(os.pathsep.join(x for x in sys.path if x.endswith('-packages')))",
+    ));
+
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(
+        home.join(".gitconfig"),
+        "[user]\n\tname = Jane Doe\n\temail = jane@example.com\n",
+    )
+    .unwrap();
+
+    for (build_system, _) in BUILD_SYSTEMS {
+        for kind in ["lib", "script"] {
+            let name = format!("demo-{}-{}", kind, build_system);
+            let package = name.replace('-', "_");
+            let project = dir.path().join(&name);
+            run(rye(&home)
+                .current_dir(dir.path())
+                .arg("init")
+                .arg(&project)
+                .arg(format!("--{}", kind))
+                .arg("--build-system")
+                .arg(build_system)
+                .arg("--py")
+                .arg(version.rsplit_once('.').unwrap().0));
+
+            // what `rye sync` does: an editable install into the virtualenv
+            // of the project.  pip and the build backend come from the
+            // interpreter running the test.
+            let venv = project.join(".venv");
+            run(Command::new(python)
+                .args(["-m", "venv", "--without-pip"])
+                .arg(&venv));
+            fs::write(
+                venv.join("rye-venv.json"),
+                format!("{{\"python\": \"cpython@{}\"}}", version),
+            )
+            .unwrap();
+            let pip = || {
+                let mut cmd = Command::new(venv.join(VENV_BIN).join("python"));
+                cmd.env("PYTHONPATH", site_packages.trim())
+                    .args(["-m", "pip", "-q"]);
+                cmd
+            };
+            run(pip()
+                .args([
+                    "install",
+                    "--no-index",
+                    "--no-deps",
+                    "--no-build-isolation",
+                    "-e",
+                ])
+                .arg(&project));
+            run(pip()
+                .args([
+                    "wheel",
+                    "--no-index",
+                    "--no-deps",
+                    "--no-build-isolation",
+                    "-w",
+                ])
+                .arg(project.join("dist"))
+                .arg(&project));
+            assert!(fs::read_dir(project.join("dist")).unwrap().any(|x| x
+                .unwrap()
+                .path()
+                .extension()
+                == Some("whl".as_ref())));
+
+            let rye_run = |args: &[&str]| {
+                run(rye(&home)
+                    .current_dir(&project)
+                    .args(["run", "--no-sync"])
+                    .args(args))
+            };
+            let expected = format!("Hello from {}!\n", name);
+            if kind == "lib" {
+                assert!(project
+                    .join("src")
+                    .join(&package)
+                    .join("py.typed")
+                    .is_file());
+                assert_eq!(
+                    rye_run(&[
+                        "python",
+                        "-c",
+                        &format!("import {0}; print({0}.hello())", package)
+                    ]),
+                    expected
+                );
+            } else {
+                assert_eq!(rye_run(&["python", "-m", &package]), expected);
+                assert_eq!(rye_run(&["dev"]), expected);
+                assert_eq!(rye_run(&[&name]), expected);
+            }
+        }
+    }
+}
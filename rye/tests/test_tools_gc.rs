@@ -0,0 +1,34 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn rye_gc(home: &Path, pip_cache_dir: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_rye"))
+        .args(["tools", "gc", "--quiet"])
+        .args(args)
+        .env("HOME", home)
+        .env("PIP_CACHE_DIR", pip_cache_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_gc_keeps_external_pip_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    let rye_cache = home.join(".rye").join("pip-cache").join("http");
+    let external_cache = dir.path().join("external-cache");
+    fs::create_dir_all(&rye_cache).unwrap();
+    fs::create_dir_all(&external_cache).unwrap();
+    fs::write(rye_cache.join("entry"), "rye").unwrap();
+    fs::write(external_cache.join("entry"), "external").unwrap();
+
+    // the pip cache is only cleared on request
+    rye_gc(&home, &external_cache, &[]);
+    assert!(rye_cache.join("entry").is_file());
+
+    rye_gc(&home, &external_cache, &["--pip-cache"]);
+    assert!(!rye_cache.exists());
+    assert!(external_cache.join("entry").is_file());
+}